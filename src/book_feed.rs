@@ -0,0 +1,708 @@
+use rust_decimal::Decimal;
+use rust_decimal::prelude::FromPrimitive;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::glass::{BitScan, CostOverflow, GlassConfig, GlassSnapshot, SnapshotError, SweepLimit};
+use crate::model::{BestBidAsk, DepthUpdate, OrderBookSnapshot, OrderSample, Side};
+use crate::publish::{Publisher, Reader};
+use crate::tick_book::{TickBook, TickScale};
+
+const MIN_HOT_LEVELS: usize = 256;
+
+/// Files of a recording: every depth update as received, one JSON object per line, and a
+/// checkpoint of the synced book that is replaced every [`CHECKPOINT_INTERVAL`].
+pub const DEPTH_FILE: &str = "depth.jsonl";
+pub const CHECKPOINT_FILE: &str = "book.ckpt";
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How the feed builds its book and views.
+pub struct FeedConfig {
+    pub tick_size: Decimal,
+    pub step_size: Decimal,
+    pub bit_scan: BitScan,
+    /// Levels per side that get queues and orders in each view.
+    pub depth: usize,
+    /// Where to record the depth stream and book checkpoints, if anywhere.
+    pub record_dir: Option<PathBuf>,
+}
+
+/// What the feed thread is sent: the depth stream and its REST snapshots, plus a reset
+/// whenever the symbol changes.
+pub enum FeedMessage {
+    Snapshot(OrderBookSnapshot),
+    Update(DepthUpdate),
+    /// Drops the book and waits for the next snapshot, quoted in the given increments.
+    Reset {
+        generation: u64,
+        tick_size: Decimal,
+        step_size: Decimal,
+    },
+}
+
+/// Touch changes for the order flow imbalance, which needs every one, not just the latest.
+pub enum TouchEvent {
+    Moved(u64, BestBidAsk),
+    /// The book was reloaded from a snapshot; flow from before it does not carry over.
+    Resynced,
+}
+
+/// One side of a [`BookView`].
+#[derive(Default)]
+pub struct SideView {
+    /// Every level, keyed by price key.
+    pub levels: GlassSnapshot,
+    /// Estimated order sizes in the levels nearest the touch, per price in queue order.
+    pub queues: BTreeMap<Decimal, VecDeque<Decimal>>,
+    /// The same orders with their ages, touch first.
+    pub orders: Vec<OrderSample>,
+    pub total_qty: Decimal,
+    pub vwap: Option<Decimal>,
+}
+
+/// The book as of one batch of feed messages. Immutable once published.
+pub struct BookView {
+    pub generation: u64,
+    /// False until the depth stream has caught up with the snapshot.
+    pub synced: bool,
+    pub scale: TickScale,
+    pub bids: SideView,
+    pub asks: SideView,
+}
+
+impl BookView {
+    fn empty(generation: u64, scale: TickScale) -> Self {
+        Self {
+            generation,
+            synced: false,
+            scale,
+            bids: SideView::default(),
+            asks: SideView::default(),
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<(Decimal, Decimal)> {
+        self.bids.levels.max().map(|level| self.level(level))
+    }
+
+    pub fn best_ask(&self) -> Option<(Decimal, Decimal)> {
+        self.asks.levels.min().map(|level| self.level(level))
+    }
+
+    /// Average and worst price a market order for `qty` would trade at, walking `side` from
+    /// the touch. None when `qty` is not a whole number of lots or the side holds less than it.
+    pub fn sweep(&self, side: Side, qty: Decimal) -> Option<(Decimal, Decimal)> {
+        let lots = self.scale.qty_to_lots(qty).ok().filter(|&lots| lots > 0)?;
+        let limit = SweepLimit { shares: Some(lots), ..Default::default() };
+        let sweep = match side {
+            Side::Bid => self.bids.levels.sweep_sell(limit),
+            Side::Ask => self.asks.levels.sweep_buy(limit),
+        };
+        let last = sweep.last_price.filter(|_| sweep.filled == lots as u128)?;
+        // Prices are linear in the key, so the average key maps to the average price
+        let average_key = Decimal::from_f64(sweep.vwap()?)?;
+        let average = self.scale.key_to_price(0) + average_key * self.scale.tick_size();
+        Some((average, self.scale.key_to_price(last)))
+    }
+
+    /// Quantity resting within `ticks` of the touch on `side`, the touch included.
+    pub fn depth_near(&self, side: Side, ticks: u32) -> Result<Decimal, CostOverflow> {
+        let (levels, range) = match side {
+            Side::Bid => (&self.bids.levels, self.bids.levels.max().map(|(best, _)| (best.saturating_sub(ticks), best))),
+            Side::Ask => (&self.asks.levels, self.asks.levels.min().map(|(best, _)| (best, best.saturating_add(ticks)))),
+        };
+        let lots = match range {
+            Some((lo, hi)) => levels.range_sum(lo, hi)?,
+            None => 0,
+        };
+        Ok(self.scale.lots_to_qty(lots))
+    }
+
+    fn level(&self, (key, lots): (u32, u64)) -> (Decimal, Decimal) {
+        (self.scale.key_to_price(key), self.scale.lots_to_qty(lots))
+    }
+
+    fn capture(book: &TickBook, depth: usize, generation: u64, synced: bool) -> Self {
+        let side = |side: Side| SideView {
+            levels: book.snapshot(side),
+            queues: book.levels(side, depth),
+            orders: book.order_samples(side, depth),
+            total_qty: book.total_qty(side),
+            vwap: book.vwap(side),
+        };
+        BookView {
+            generation,
+            synced,
+            scale: *book.scale(),
+            bids: side(Side::Bid),
+            asks: side(Side::Ask),
+        }
+    }
+}
+
+// The UI's handle on the feed thread, which owns the TickBook: it syncs the depth stream
+// against the REST snapshot, applies every diff and publishes a BookView per batch of
+// messages, so the UI thread only ever reads.
+pub struct BookFeed {
+    messages: Sender<FeedMessage>,
+    touches: Receiver<TouchEvent>,
+    reader: Reader<BookView>,
+    generation: u64,
+    // Shown instead of views published before the last reset
+    blank: Arc<BookView>,
+}
+
+impl BookFeed {
+    /// Starts the feed thread. `on_gap` is called when the stream skips an update and a new
+    /// snapshot is needed, and `on_publish` after every view.
+    pub fn spawn(
+        config: FeedConfig,
+        on_gap: impl Fn() + Send + 'static,
+        on_publish: impl Fn() + Send + 'static,
+    ) -> Self {
+        let scale = TickScale::new(config.tick_size, config.step_size, Decimal::ZERO);
+        let publisher = Publisher::new(BookView::empty(0, scale));
+        let reader = publisher.reader();
+        let (messages, message_rx) = mpsc::channel();
+        let (touch_tx, touches) = mpsc::channel();
+        let mut feed = Feed {
+            book: TickBook::new(scale),
+            generation: 0,
+            tick_size: config.tick_size,
+            step_size: config.step_size,
+            bit_scan: config.bit_scan,
+            depth: config.depth,
+            last_applied_u: 0,
+            synced: false,
+            buffer: VecDeque::new(),
+            touches: touch_tx,
+            on_gap: Box::new(on_gap),
+            record_dir: config.record_dir,
+            recorder: None,
+        };
+        thread::spawn(move || {
+            feed.start_recording();
+            while let Ok(message) = message_rx.recv() {
+                feed.handle(message);
+                for message in message_rx.try_iter() {
+                    feed.handle(message);
+                }
+                feed.flush_recording();
+                publisher.publish(BookView::capture(&feed.book, feed.depth, feed.generation, feed.synced));
+                on_publish();
+            }
+        });
+        Self {
+            messages,
+            touches,
+            reader,
+            generation: 0,
+            blank: Arc::new(BookView::empty(0, scale)),
+        }
+    }
+
+    /// Where the websocket task sends depth updates and snapshots.
+    pub fn sender(&self) -> Sender<FeedMessage> {
+        self.messages.clone()
+    }
+
+    /// Starts over for a new symbol quoted in the given increments.
+    pub fn reset(&mut self, tick_size: Decimal, step_size: Decimal) {
+        self.generation += 1;
+        let scale = TickScale::new(tick_size, step_size, Decimal::ZERO);
+        self.blank = Arc::new(BookView::empty(self.generation, scale));
+        let _ = self.messages.send(FeedMessage::Reset {
+            generation: self.generation,
+            tick_size,
+            step_size,
+        });
+    }
+
+    /// The latest view of the current symbol, or an empty one until the feed has caught up
+    /// with the last reset.
+    pub fn view(&mut self) -> Arc<BookView> {
+        let latest = self.reader.latest();
+        if latest.generation == self.generation {
+            latest.clone()
+        } else {
+            self.blank.clone()
+        }
+    }
+
+    /// Touch changes since the last call.
+    pub fn touches(&self) -> impl Iterator<Item = TouchEvent> + '_ {
+        self.touches.try_iter()
+    }
+}
+
+// State of the feed thread.
+struct Feed {
+    book: TickBook,
+    generation: u64,
+    tick_size: Decimal,
+    step_size: Decimal,
+    bit_scan: BitScan,
+    depth: usize,
+    last_applied_u: u64,
+    synced: bool,
+    // Updates that arrived before the snapshot they follow
+    buffer: VecDeque<DepthUpdate>,
+    touches: Sender<TouchEvent>,
+    on_gap: Box<dyn Fn() + Send>,
+    record_dir: Option<PathBuf>,
+    recorder: Option<Recorder>,
+}
+
+impl Feed {
+    fn handle(&mut self, message: FeedMessage) {
+        match message {
+            FeedMessage::Snapshot(snap) => {
+                self.load_snapshot(&snap);
+                self.last_applied_u = snap.last_update_id;
+                self.synced = false;
+                let _ = self.touches.send(TouchEvent::Resynced);
+
+                while let Some(update) = self.buffer.pop_front() {
+                    self.process_update(update);
+                }
+            }
+            FeedMessage::Update(update) => {
+                self.record(&update);
+                if self.last_applied_u == 0 {
+                    self.buffer.push_back(update);
+                } else {
+                    self.process_update(update);
+                }
+            }
+            FeedMessage::Reset {
+                generation,
+                tick_size,
+                step_size,
+            } => {
+                self.generation = generation;
+                self.tick_size = tick_size;
+                self.step_size = step_size;
+                self.book.clear();
+                self.last_applied_u = 0;
+                self.synced = false;
+                self.buffer.clear();
+                self.start_recording();
+            }
+        }
+    }
+
+    // A recording covers one symbol, so every reset starts it over
+    fn start_recording(&mut self) {
+        let Some(dir) = &self.record_dir else {
+            return;
+        };
+        self.recorder = Recorder::create(dir)
+            .inspect_err(|err| println!("Warning: not recording to {}: {err}", dir.display()))
+            .ok();
+    }
+
+    fn record(&mut self, update: &DepthUpdate) {
+        if let Some(recorder) = &mut self.recorder
+            && let Err(err) = recorder.record(update)
+        {
+            println!("Warning: recording stopped: {err}");
+            self.recorder = None;
+        }
+    }
+
+    // Once per batch, so a replay sees everything that was published
+    fn flush_recording(&mut self) {
+        let synced_at = self.synced.then_some(self.last_applied_u);
+        if let Some(recorder) = &mut self.recorder
+            && let Err(err) = recorder.flush(synced_at, &self.book)
+        {
+            println!("Warning: recording stopped: {err}");
+            self.recorder = None;
+        }
+    }
+
+    fn process_update(&mut self, update: DepthUpdate) {
+        if update.small_u < self.last_applied_u {
+            return;
+        }
+
+        if self.synced {
+            if (update.pu as u64) != self.last_applied_u {
+                println!(
+                    "Warning: Message gap detected! pu: {}, last: {}",
+                    update.pu, self.last_applied_u
+                );
+                self.buffer.clear();
+                (self.on_gap)();
+                return;
+            }
+            self.apply_update(&update);
+            self.last_applied_u = update.small_u;
+        } else if update.capital_u <= self.last_applied_u && self.last_applied_u <= update.small_u {
+            self.apply_update(&update);
+            self.last_applied_u = update.small_u;
+            self.synced = true;
+        } else {
+            println!(
+                "Initial gap detected! U: {}, u: {}, last: {}",
+                update.capital_u, update.small_u, self.last_applied_u
+            );
+            self.buffer.clear();
+            (self.on_gap)();
+        }
+    }
+
+    fn load_snapshot(&mut self, snap: &OrderBookSnapshot) {
+        let levels = || {
+            snap.bids
+                .iter()
+                .map(|level| (Side::Bid, level))
+                .chain(snap.asks.iter().map(|level| (Side::Ask, level)))
+        };
+        let reference = levels().next().map(|(_, level)| level[0]).unwrap_or_default();
+        let mut scale = TickScale::new(self.tick_size, self.step_size, reference);
+        let fits = |scale: &TickScale| {
+            levels().all(|(_, level)| {
+                scale.price_to_key(level[0]).is_ok() && scale.qty_to_lots(level[1]).is_ok()
+            })
+        };
+        if !fits(&scale) {
+            // exchangeInfo was unavailable or disagrees with the feed: use the finest
+            // increments the snapshot itself quotes
+            let price_dp = levels().map(|(_, level)| level[0].normalize().scale()).max().unwrap_or(0);
+            let qty_dp = levels().map(|(_, level)| level[1].normalize().scale()).max().unwrap_or(0);
+            println!(
+                "Warning: snapshot is off the {} / {} grid, falling back to {} / {} decimals",
+                self.tick_size, self.step_size, price_dp, qty_dp
+            );
+            scale = TickScale::new(Decimal::new(1, price_dp), Decimal::new(1, qty_dp), reference);
+        }
+
+        // Keep the hot set a little deeper than the snapshot so a thin book stays small
+        // and a deep one does not spill into the preempt map right away.
+        let depth = snap.bids.len().max(snap.asks.len());
+        let hot_levels = (depth * 2).max(MIN_HOT_LEVELS);
+        self.book = TickBook::with_config(
+            scale,
+            GlassConfig {
+                hot_levels,
+                arena_capacity: hot_levels * 2,
+                bit_scan: self.bit_scan,
+                ..GlassConfig::default()
+            },
+        );
+        for (side, level) in levels() {
+            let price = level[0];
+            let qty = level[1];
+            if qty > Decimal::ZERO
+                && let Err(err) = self.book.set_level(side, price, qty)
+            {
+                println!("Warning: dropped snapshot level {:?} {} @ {}: {:?}", side, qty, price, err);
+            }
+        }
+    }
+
+    fn apply_update(&mut self, update: &DepthUpdate) {
+        if let Some(best) = apply_update(&mut self.book, update) {
+            let _ = self.touches.send(TouchEvent::Moved(update.event_time, best));
+        }
+    }
+}
+
+// Applies one diff and returns the touch after it, when both sides have one.
+fn apply_update(book: &mut TickBook, update: &DepthUpdate) -> Option<BestBidAsk> {
+    for (side, levels) in [(Side::Bid, &update.b), (Side::Ask, &update.a)] {
+        for level in levels {
+            let price = level[0];
+            let qty = level[1];
+            if let Err(err) = book.apply_level(side, price, qty) {
+                println!("Warning: dropped update {:?} {} @ {}: {:?}", side, qty, price, err);
+            }
+        }
+    }
+    let ((bid_price, bid_qty), (ask_price, ask_qty)) = (book.best_bid()?, book.best_ask()?);
+    Some(BestBidAsk {
+        best_bid_price: bid_price,
+        best_bid_qty: bid_qty,
+        best_ask_price: ask_price,
+        best_offer_qty: ask_qty,
+    })
+}
+
+// Writes a recording directory. A checkpoint is last_update_id: u64 followed by a TickBook
+// checkpoint, and is written beside the old one and renamed over it, so a crash never
+// leaves a torn file behind.
+struct Recorder {
+    dir: PathBuf,
+    depth: BufWriter<File>,
+    last_checkpoint: Option<Instant>,
+}
+
+impl Recorder {
+    // Replaces whatever the directory held; an old capture would not line up with the new book
+    fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        match fs::remove_file(dir.join(CHECKPOINT_FILE)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            depth: BufWriter::new(File::create(dir.join(DEPTH_FILE))?),
+            last_checkpoint: None,
+        })
+    }
+
+    fn record(&mut self, update: &DepthUpdate) -> io::Result<()> {
+        serde_json::to_writer(&mut self.depth, update)?;
+        self.depth.write_all(b"\n")
+    }
+
+    // Checkpoints only a synced book, the update id it is synced to being `synced_at`
+    fn flush(&mut self, synced_at: Option<u64>, book: &TickBook) -> io::Result<()> {
+        // Everything up to a checkpoint is on disk before it is
+        self.depth.flush()?;
+        match synced_at {
+            Some(last_update_id) if self.due() => self.checkpoint(last_update_id, book),
+            _ => Ok(()),
+        }
+    }
+
+    fn due(&self) -> bool {
+        self.last_checkpoint.is_none_or(|at| at.elapsed() >= CHECKPOINT_INTERVAL)
+    }
+
+    fn checkpoint(&mut self, last_update_id: u64, book: &TickBook) -> io::Result<()> {
+        let mut bytes = last_update_id.to_le_bytes().to_vec();
+        bytes.extend(book.to_bytes());
+        let partial = self.dir.join(format!("{CHECKPOINT_FILE}.partial"));
+        fs::write(&partial, bytes)?;
+        fs::rename(partial, self.dir.join(CHECKPOINT_FILE))?;
+        self.last_checkpoint = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// The book [`replay`] rebuilt.
+pub struct Replay {
+    pub view: BookView,
+    pub last_update_id: u64,
+    /// Recorded updates applied on top of the checkpoint.
+    pub applied: usize,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Checkpoint(SnapshotError),
+    /// A line of the depth file is not a depth update.
+    Parse(serde_json::Error),
+    /// The recording skips updates after `last_update_id`, e.g. across a reconnect; the book
+    /// cannot be rebuilt past it without a newer checkpoint.
+    Gap { last_update_id: u64 },
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+/// Rebuilds the book of a recording directory from its checkpoint and every recorded update
+/// after it. The view holds `depth` levels per side, like the live feed's.
+pub fn replay(dir: &Path, depth: usize) -> Result<Replay, ReplayError> {
+    let bytes = fs::read(dir.join(CHECKPOINT_FILE))?;
+    let (last_update_id, book) = bytes
+        .split_first_chunk::<8>()
+        .ok_or(ReplayError::Checkpoint(SnapshotError::Truncated))?;
+    let mut last_update_id = u64::from_le_bytes(*last_update_id);
+    let mut book = TickBook::from_bytes(book).map_err(ReplayError::Checkpoint)?;
+    let mut applied = 0;
+    for line in BufReader::new(File::open(dir.join(DEPTH_FILE))?).lines() {
+        let update: DepthUpdate = serde_json::from_str(&line?).map_err(ReplayError::Parse)?;
+        // Recorded before the checkpoint was taken
+        if update.small_u <= last_update_id {
+            continue;
+        }
+        if update.pu as u64 != last_update_id {
+            return Err(ReplayError::Gap { last_update_id });
+        }
+        apply_update(&mut book, &update);
+        last_update_id = update.small_u;
+        applied += 1;
+    }
+    Ok(Replay {
+        view: BookView::capture(&book, depth, 0, true),
+        last_update_id,
+        applied,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::time::Duration;
+
+    fn update(
+        first: u64,
+        last: u64,
+        previous: u64,
+        bids: Vec<Vec<Decimal>>,
+        asks: Vec<Vec<Decimal>>,
+    ) -> DepthUpdate {
+        DepthUpdate {
+            e: "depthUpdate".to_string(),
+            event_time: last,
+            transaction_time: last,
+            s: "TESTUSDT".to_string(),
+            capital_u: first,
+            small_u: last,
+            pu: previous as i64,
+            b: bids,
+            a: asks,
+        }
+    }
+
+    // A feed whose gaps and publishes are counted on channels the test can wait on
+    fn spawn() -> (BookFeed, Receiver<()>, Receiver<()>) {
+        spawn_recording(None)
+    }
+
+    fn spawn_recording(record_dir: Option<PathBuf>) -> (BookFeed, Receiver<()>, Receiver<()>) {
+        let (gap_tx, gaps) = mpsc::channel();
+        let (publish_tx, publishes) = mpsc::channel();
+        let feed = BookFeed::spawn(
+            FeedConfig {
+                tick_size: dec!(0.1),
+                step_size: dec!(0.01),
+                bit_scan: BitScan::Detect,
+                depth: 10,
+                record_dir,
+            },
+            move || gap_tx.send(()).unwrap(),
+            move || publish_tx.send(()).unwrap(),
+        );
+        (feed, gaps, publishes)
+    }
+
+    // Waits until the feed has published everything sent so far
+    fn settle(feed: &mut BookFeed, publishes: &Receiver<()>) -> Arc<BookView> {
+        publishes.recv_timeout(Duration::from_secs(5)).unwrap();
+        while publishes.recv_timeout(Duration::from_millis(50)) != Err(RecvTimeoutError::Timeout) {}
+        feed.view()
+    }
+
+    #[test]
+    fn test_feed_syncs_and_publishes() {
+        let (mut feed, gaps, publishes) = spawn();
+        let sender = feed.sender();
+        // Buffered until the snapshot arrives, then the one straddling it is applied
+        sender.send(FeedMessage::Update(update(8, 12, 7, vec![vec![dec!(99.9), dec!(2)]], vec![]))).unwrap();
+        sender
+            .send(FeedMessage::Snapshot(OrderBookSnapshot {
+                last_update_id: 10,
+                bids: vec![vec![dec!(99.9), dec!(1)], vec![dec!(99.8), dec!(3)]],
+                asks: vec![vec![dec!(100.1), dec!(4)]],
+            }))
+            .unwrap();
+        sender.send(FeedMessage::Update(update(13, 14, 12, vec![], vec![vec![dec!(100.0), dec!(0.5)]]))).unwrap();
+
+        let view = settle(&mut feed, &publishes);
+        assert!(view.synced);
+        assert_eq!(view.best_bid(), Some((dec!(99.9), dec!(2))));
+        assert_eq!(view.best_ask(), Some((dec!(100.0), dec!(0.5))));
+        assert_eq!(view.bids.queues[&dec!(99.9)], [dec!(1), dec!(1)]);
+        assert_eq!(view.bids.orders.len(), 3);
+        assert_eq!(view.bids.total_qty, dec!(5));
+        assert_eq!(view.asks.levels.len(), 2);
+        assert_eq!(view.sweep(Side::Ask, dec!(0.5)), Some((dec!(100.0), dec!(100.0))));
+        assert_eq!(view.sweep(Side::Ask, dec!(1)), Some((dec!(100.05), dec!(100.1))));
+        assert_eq!(view.sweep(Side::Ask, dec!(5)), None);
+        assert_eq!(view.sweep(Side::Bid, dec!(2.5)).map(|(_, worst)| worst), Some(dec!(99.8)));
+        assert_eq!(view.sweep(Side::Bid, dec!(0.001)), None);
+        assert_eq!(view.depth_near(Side::Bid, 0), Ok(dec!(2)));
+        assert_eq!(view.depth_near(Side::Bid, 1), Ok(dec!(5)));
+        assert_eq!(view.depth_near(Side::Ask, 1), Ok(dec!(4.5)));
+        assert_eq!(feed.view().depth_near(Side::Ask, 1000), Ok(dec!(4.5)));
+        let touches: Vec<TouchEvent> = feed.touches().collect();
+        assert!(matches!(touches[0], TouchEvent::Resynced));
+        assert!(matches!(touches[2], TouchEvent::Moved(14, best) if best.best_ask_price == dec!(100.0)));
+        assert!(gaps.try_recv().is_err());
+
+        // A skipped update asks for a new snapshot and leaves the book as it was
+        sender.send(FeedMessage::Update(update(16, 17, 15, vec![], vec![vec![dec!(100.0), dec!(0)]]))).unwrap();
+        let view = settle(&mut feed, &publishes);
+        assert!(gaps.try_recv().is_ok());
+        assert_eq!(view.best_ask(), Some((dec!(100.0), dec!(0.5))));
+    }
+
+    #[test]
+    fn test_reset_hides_the_old_book() {
+        let (mut feed, _gaps, publishes) = spawn();
+        feed.sender()
+            .send(FeedMessage::Snapshot(OrderBookSnapshot {
+                last_update_id: 1,
+                bids: vec![vec![dec!(99.9), dec!(1)]],
+                asks: vec![],
+            }))
+            .unwrap();
+        assert!(settle(&mut feed, &publishes).best_bid().is_some());
+        feed.reset(dec!(0.01), dec!(1));
+        // Straight away, before the feed thread has seen the reset
+        assert_eq!(feed.view().generation, 1);
+        assert!(feed.view().best_bid().is_none());
+        let view = settle(&mut feed, &publishes);
+        assert_eq!(view.generation, 1);
+        assert_eq!(view.bids.levels.len(), 0);
+    }
+
+    #[test]
+    fn test_recording_replays_to_the_live_book() {
+        let dir = std::env::temp_dir().join(format!("book_feed_replay_{}", std::process::id()));
+        let (mut feed, _gaps, publishes) = spawn_recording(Some(dir.clone()));
+        let sender = feed.sender();
+        sender
+            .send(FeedMessage::Snapshot(OrderBookSnapshot {
+                last_update_id: 10,
+                bids: vec![vec![dec!(99.9), dec!(1)], vec![dec!(99.8), dec!(3)]],
+                asks: vec![vec![dec!(100.1), dec!(4)]],
+            }))
+            .unwrap();
+        // The first synced batch is checkpointed, the ones after it only recorded
+        sender.send(FeedMessage::Update(update(9, 12, 8, vec![vec![dec!(99.9), dec!(2)]], vec![]))).unwrap();
+        settle(&mut feed, &publishes);
+        sender.send(FeedMessage::Update(update(13, 14, 12, vec![], vec![vec![dec!(100.0), dec!(0.5)]]))).unwrap();
+        sender.send(FeedMessage::Update(update(15, 15, 14, vec![vec![dec!(99.8), dec!(0)]], vec![]))).unwrap();
+        let live = settle(&mut feed, &publishes);
+
+        let replayed = replay(&dir, 10).unwrap();
+        assert_eq!(replayed.last_update_id, 15);
+        assert_eq!(replayed.applied, 2);
+        assert_eq!(replayed.view.best_bid(), live.best_bid());
+        assert_eq!(replayed.view.best_ask(), live.best_ask());
+        for (replayed, live) in [(&replayed.view.bids, &live.bids), (&replayed.view.asks, &live.asks)] {
+            assert_eq!(replayed.levels, live.levels);
+            assert_eq!(replayed.queues, live.queues);
+            assert_eq!(replayed.orders, live.orders);
+        }
+        // 99.9 grew from 1 to 2 after the snapshot: two estimated orders, not one
+        assert_eq!(replayed.view.bids.queues[&dec!(99.9)], [dec!(1), dec!(1)]);
+        assert_eq!(replayed.view.bids.total_qty, dec!(2));
+
+        // A recording that skips an update stops the replay instead of building a wrong book
+        let mut depth = fs::OpenOptions::new().append(true).open(dir.join(DEPTH_FILE)).unwrap();
+        let skipped = update(18, 19, 17, vec![], vec![]);
+        writeln!(depth, "{}", serde_json::to_string(&skipped).unwrap()).unwrap();
+        assert!(matches!(replay(&dir, 10), Err(ReplayError::Gap { last_update_id: 15 })));
+        drop(feed);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,223 @@
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::model::{OrderSample, Side};
+
+// Orders within this many ticks of their side's best price count as resting at the touch
+const TOUCH_TICKS: u32 = 5;
+// Labeling thresholds, relative to the side's median order size
+const WALL_SIZE_MULTIPLE: f64 = 10.0;
+const RETAIL_SIZE_MULTIPLE: f64 = 2.0;
+const MAKER_TOUCH_SHARE: f64 = 0.5;
+const MAKER_CANCEL_RATE: f64 = 0.2;
+const WALL_CANCEL_RATE: f64 = 0.1;
+
+/// Rough guess at who is behind a cluster, for reading the chart without the numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Participant {
+    Retail,
+    MarketMaker,
+    Wall,
+    Unclassified,
+}
+
+impl Participant {
+    pub fn label(self) -> &'static str {
+        match self {
+            Participant::Retail => "retail",
+            Participant::MarketMaker => "market maker",
+            Participant::Wall => "whale/wall",
+            Participant::Unclassified => "unclassified",
+        }
+    }
+}
+
+/// What one cluster on one side of the book looks like.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClusterSummary {
+    pub cluster: usize,
+    pub count: usize,
+    pub total_size: Decimal,
+    pub mean_size: Decimal,
+    pub mean_age: f64,
+    // Share of the cluster's orders at the touch; the rest sit deeper
+    pub touch_share: f64,
+    // Share of the cluster's orders in the previous labelling that have left the book since,
+    // cancelled or filled alike. Orders last seen deeper than the levels labelled now may just
+    // have left the window and are not counted. None when there is nothing to compare with
+    pub cancel_rate: Option<f64>,
+    pub participant: Participant,
+}
+
+/// Where a labelled order rested and which cluster it was in, by id.
+pub type OrderClusters = HashMap<u64, (Decimal, usize)>;
+
+/// Price and cluster of every labelled order, to work out cancel rates on the next labelling.
+pub fn order_clusters(orders: &[OrderSample], labels: &[usize]) -> OrderClusters {
+    orders
+        .iter()
+        .filter(|order| order.qty > Decimal::ZERO)
+        .zip(labels)
+        .map(|(order, &label)| (order.id, (order.price, label)))
+        .collect()
+}
+
+/// Per-cluster statistics for one side, in cluster order with noise last. `orders` run from
+/// the touch outwards like [`crate::tick_book::TickBook::order_samples`], and `labels` cover
+/// those with a positive size; `previous` is the last labelling's [`order_clusters`].
+pub fn summarize(
+    orders: &[OrderSample],
+    labels: &[usize],
+    tick_size: Decimal,
+    previous: &OrderClusters,
+) -> Vec<ClusterSummary> {
+    let resting: Vec<&OrderSample> =
+        orders.iter().filter(|order| order.qty > Decimal::ZERO).collect();
+    let Some(touch) = resting.first().map(|order| order.price) else {
+        return vec![];
+    };
+    let touch_width = tick_size * Decimal::from(TOUCH_TICKS);
+
+    #[derive(Default)]
+    struct Totals {
+        count: usize,
+        size: Decimal,
+        age: f64,
+        at_touch: usize,
+    }
+    let mut totals: BTreeMap<usize, Totals> = BTreeMap::new();
+    for (order, &label) in resting.iter().zip(labels) {
+        let t = totals.entry(label).or_default();
+        t.count += 1;
+        t.size += order.qty;
+        t.age += order.age as f64;
+        if (order.price - touch).abs() <= touch_width {
+            t.at_touch += 1;
+        }
+    }
+
+    // Orders each cluster had last time, and how many of those have left. `orders` only reach
+    // so deep, so one last seen beyond the deepest level labelled now is out of view rather
+    // than gone
+    let current: HashSet<u64> = resting.iter().map(|order| order.id).collect();
+    let deepest = resting.last().map(|order| (order.side, order.price));
+    let in_view = |price: Decimal| match deepest {
+        Some((Side::Bid, deepest)) => price >= deepest,
+        Some((Side::Ask, deepest)) => price <= deepest,
+        None => false,
+    };
+    let mut carried: HashMap<usize, (usize, usize)> = HashMap::new();
+    for (id, &(price, label)) in previous {
+        if !current.contains(id) && !in_view(price) {
+            continue;
+        }
+        let entry = carried.entry(label).or_default();
+        entry.0 += 1;
+        if !current.contains(id) {
+            entry.1 += 1;
+        }
+    }
+
+    let mut sizes: Vec<f64> =
+        resting.iter().map(|order| order.qty.to_f64().unwrap_or(0.0)).collect();
+    sizes.sort_by(f64::total_cmp);
+    let median_size = sizes[sizes.len() / 2];
+    let side_mean_age =
+        resting.iter().map(|order| order.age as f64).sum::<f64>() / resting.len() as f64;
+
+    // Noise has the largest id, so it comes last
+    totals
+        .into_iter()
+        .map(|(cluster, t)| {
+            let mean_size = t.size / Decimal::from(t.count);
+            let mean_age = t.age / t.count as f64;
+            let touch_share = t.at_touch as f64 / t.count as f64;
+            let cancel_rate = carried
+                .get(&cluster)
+                .map(|&(before, gone)| gone as f64 / before as f64);
+            let relative_size = mean_size.to_f64().unwrap_or(0.0) / median_size;
+            let participant = if relative_size >= WALL_SIZE_MULTIPLE
+                && mean_age >= side_mean_age
+                && cancel_rate.is_none_or(|rate| rate <= WALL_CANCEL_RATE)
+            {
+                Participant::Wall
+            } else if touch_share >= MAKER_TOUCH_SHARE
+                && cancel_rate.is_some_and(|rate| rate >= MAKER_CANCEL_RATE)
+            {
+                Participant::MarketMaker
+            } else if relative_size <= RETAIL_SIZE_MULTIPLE {
+                Participant::Retail
+            } else {
+                Participant::Unclassified
+            };
+            ClusterSummary {
+                cluster,
+                count: t.count,
+                total_size: t.size,
+                mean_size,
+                mean_age,
+                touch_share,
+                cancel_rate,
+                participant,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Side;
+    use rust_decimal::dec;
+
+    fn order(id: u64, price: Decimal, qty: Decimal, age: u64) -> OrderSample {
+        OrderSample { id, side: Side::Bid, price, qty, age, queue_index: 0 }
+    }
+
+    #[test]
+    fn test_summarize_labels_clusters() {
+        // Small orders spread through the book, a quoting cluster at the touch and an old
+        // wall deep down
+        let mut orders = vec![
+            order(10, dec!(100), dec!(3), 1),
+            order(11, dec!(100), dec!(3), 0),
+            order(12, dec!(99), dec!(3), 2),
+        ];
+        orders.extend((0..6).map(|i| order(20 + i, dec!(98) - Decimal::from(i * 3), dec!(1), 5)));
+        orders.push(order(30, dec!(80), dec!(0), 9));
+        orders.push(order(31, dec!(80), dec!(60), 40));
+        let labels = [2, 2, 2, 0, 0, 0, 0, 0, 0, 1];
+
+        // Last time the touch cluster had two other orders that are gone now
+        let mut previous = order_clusters(&orders, &labels);
+        previous.insert(1, (dec!(100), 2));
+        previous.insert(2, (dec!(99), 2));
+        // and the small cluster one beyond the deepest level labelled now, which may still rest
+        previous.insert(3, (dec!(79), 0));
+
+        let summaries = summarize(&orders, &labels, dec!(1), &previous);
+        assert_eq!(summaries.len(), 3);
+        let [small, wall, maker] = &summaries[..] else { unreachable!() };
+
+        assert_eq!((small.cluster, small.count, small.total_size), (0, 6, dec!(6)));
+        assert_eq!(small.mean_size, dec!(1));
+        // 98 and 95 are within five ticks of the best bid at 100
+        assert!((small.touch_share - 2.0 / 6.0).abs() < 1e-9);
+        assert_eq!(small.cancel_rate, Some(0.0));
+        assert_eq!(small.participant, Participant::Retail);
+
+        assert_eq!((wall.count, wall.mean_age), (1, 40.0));
+        assert_eq!(wall.touch_share, 0.0);
+        assert_eq!(wall.participant, Participant::Wall);
+
+        assert_eq!(maker.touch_share, 1.0);
+        assert_eq!(maker.cancel_rate, Some(0.4));
+        assert_eq!(maker.participant, Participant::MarketMaker);
+
+        // Nothing to compare with yet: no cancel rates, so nobody looks like a market maker
+        let fresh = summarize(&orders, &labels, dec!(1), &HashMap::new());
+        assert_eq!(fresh[2].cancel_rate, None);
+        assert_eq!(fresh[2].participant, Participant::Unclassified);
+    }
+}
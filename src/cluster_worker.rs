@@ -0,0 +1,434 @@
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use crate::cluster_summary::{self, ClusterSummary, OrderClusters};
+use crate::clustering::{ClusterFit, ClusterMethod, ClusterParams};
+use crate::model::OrderSample;
+
+/// A resting order as it stood when it was labelled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LabelledOrder {
+    pub qty: Decimal,
+    pub age: u64,
+    pub queue_index: usize,
+    pub cluster: usize,
+}
+
+pub type ClusteredOrders = BTreeMap<Decimal, VecDeque<LabelledOrder>>;
+
+// Labelled orders per price in queue order; like `kmeans::build_clustered_orders`, but keeping
+// what the chart tooltips show
+fn cluster_orders(orders: &[OrderSample], labels: &[usize]) -> ClusteredOrders {
+    let mut clustered = ClusteredOrders::new();
+    let resting = orders.iter().filter(|order| order.qty > Decimal::ZERO);
+    for (order, &cluster) in resting.zip(labels) {
+        clustered.entry(order.price).or_default().push_back(LabelledOrder {
+            qty: order.qty,
+            age: order.age,
+            queue_index: order.queue_index,
+            cluster,
+        });
+    }
+    clustered
+}
+
+// Everything one labelling needs, copied out of the book so the worker never touches it.
+pub struct ClusterJob {
+    pub bids: Vec<OrderSample>,
+    pub asks: Vec<OrderSample>,
+    pub mid: Decimal,
+    pub tick_size: Decimal,
+    pub method: ClusterMethod,
+    pub params: ClusterParams,
+    // One model over both sides, so a cluster id means the same thing either side of the spread
+    pub joint: bool,
+}
+
+pub struct SideLabelling {
+    pub orders: ClusteredOrders,
+    pub num_clusters: usize,
+    pub silhouette: f64,
+    pub summary: Vec<ClusterSummary>,
+}
+
+pub struct Labelling {
+    pub bids: SideLabelling,
+    pub asks: SideLabelling,
+    // Both sides share cluster ids, and their fit
+    pub joint: bool,
+}
+
+// Splits a joint fit over `bids ++ asks` back into the two sides; both keep the joint count
+// and score.
+fn split_fit(fit: ClusterFit, bids: &[OrderSample]) -> (ClusterFit, ClusterFit) {
+    let at = bids.iter().filter(|order| order.qty > Decimal::ZERO).count();
+    let ClusterFit { mut labels, num_clusters, silhouette } = fit;
+    let ask_labels = labels.split_off(at);
+    (
+        ClusterFit { labels, num_clusters, silhouette },
+        ClusterFit { labels: ask_labels, num_clusters, silhouette },
+    )
+}
+
+enum Request {
+    // Jobs carry the generation they were submitted in, see `ClusterWorker::reset`
+    Fit(u64, Box<ClusterJob>),
+    Reset,
+    // Order ids restarted, so the last labelling's orders cannot be followed any more
+    Resync,
+    // Stands in for a fit that panics
+    #[cfg(test)]
+    Panic,
+}
+
+// Runs the clustering fits on their own thread. The UI submits book snapshots at most once per
+// interval and only when the previous one is done, and keeps drawing the last completed
+// labelling in the meantime. A fit that panics takes the thread down with it; the next update
+// notices and starts a new one.
+pub struct ClusterWorker {
+    requests: Sender<Request>,
+    results: Receiver<(u64, Labelling)>,
+    on_done: Arc<dyn Fn() + Send + Sync>,
+    restarts: usize,
+    generation: u64,
+    interval_ms: u64,
+    last_submit_ms: u64,
+    busy: bool,
+    latest: Option<Labelling>,
+}
+
+impl ClusterWorker {
+    /// `on_done` runs on the worker thread after every labelling, e.g. to request a repaint.
+    pub fn spawn(interval_ms: u64, on_done: impl Fn() + Send + Sync + 'static) -> Self {
+        let on_done: Arc<dyn Fn() + Send + Sync> = Arc::new(on_done);
+        let (requests, results) = Self::start(on_done.clone());
+        Self {
+            requests,
+            results,
+            on_done,
+            restarts: 0,
+            generation: 0,
+            interval_ms,
+            last_submit_ms: 0,
+            busy: false,
+            latest: None,
+        }
+    }
+
+    // Starts a worker thread with fresh models
+    fn start(on_done: Arc<dyn Fn() + Send + Sync>) -> (Sender<Request>, Receiver<(u64, Labelling)>) {
+        let (requests, request_rx) = mpsc::channel();
+        let (result_tx, results) = mpsc::channel();
+        thread::spawn(move || {
+            // The models live here so every fit warm-starts from the previous one; switching
+            // method starts all of them afresh
+            let mut method = ClusterMethod::KMeans;
+            let mut joint = false;
+            let mut bid_model = method.build();
+            let mut ask_model = method.build();
+            let mut joint_model = method.build();
+            // Cluster of each order in the last labelling, for the cancel rates
+            let mut bid_previous = OrderClusters::new();
+            let mut ask_previous = OrderClusters::new();
+            while let Ok(request) = request_rx.recv() {
+                match request {
+                    Request::Reset => {
+                        bid_model.reset();
+                        ask_model.reset();
+                        joint_model.reset();
+                        bid_previous.clear();
+                        ask_previous.clear();
+                    }
+                    Request::Resync => {
+                        bid_previous.clear();
+                        ask_previous.clear();
+                    }
+                    #[cfg(test)]
+                    Request::Panic => panic!("fit failed"),
+                    Request::Fit(generation, job) => {
+                        if job.method != method || job.joint != joint {
+                            // Cluster ids change meaning, so there is nothing to compare with
+                            bid_previous.clear();
+                            ask_previous.clear();
+                            joint = job.joint;
+                        }
+                        if job.method != method {
+                            method = job.method;
+                            bid_model = method.build();
+                            ask_model = method.build();
+                            joint_model = method.build();
+                        }
+                        let (bid_fit, ask_fit) = if job.joint {
+                            let both: Vec<OrderSample> =
+                                job.bids.iter().chain(&job.asks).copied().collect();
+                            let fit =
+                                joint_model.cluster(&both, job.mid, job.tick_size, &job.params);
+                            split_fit(fit, &job.bids)
+                        } else {
+                            (
+                                bid_model.cluster(&job.bids, job.mid, job.tick_size, &job.params),
+                                ask_model.cluster(&job.asks, job.mid, job.tick_size, &job.params),
+                            )
+                        };
+                        let label = |fit: ClusterFit,
+                                     previous: &mut OrderClusters,
+                                     orders: &[OrderSample]| {
+                            let ClusterFit { labels, num_clusters, silhouette } = fit;
+                            let summary =
+                                cluster_summary::summarize(orders, &labels, job.tick_size, previous);
+                            *previous = cluster_summary::order_clusters(orders, &labels);
+                            SideLabelling {
+                                orders: cluster_orders(orders, &labels),
+                                num_clusters,
+                                silhouette,
+                                summary,
+                            }
+                        };
+                        let labelling = Labelling {
+                            bids: label(bid_fit, &mut bid_previous, &job.bids),
+                            asks: label(ask_fit, &mut ask_previous, &job.asks),
+                            joint: job.joint,
+                        };
+                        if result_tx.send((generation, labelling)).is_err() {
+                            break;
+                        }
+                        on_done();
+                    }
+                }
+            }
+        });
+        (requests, results)
+    }
+
+    /// Collects a finished labelling, if any, and submits a new job when the worker is idle
+    /// and the interval has passed. `job` is only built when it will be sent.
+    pub fn update(&mut self, now_ms: u64, job: impl FnOnce() -> ClusterJob) {
+        loop {
+            match self.results.try_recv() {
+                Ok((generation, labelling)) => {
+                    if generation == self.generation {
+                        self.latest = Some(labelling);
+                    }
+                    self.busy = false;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // The job in flight died with the thread; the last labelling stays up
+                    println!("Warning: clustering worker stopped, restarting it");
+                    (self.requests, self.results) = Self::start(self.on_done.clone());
+                    self.restarts += 1;
+                    self.busy = false;
+                    break;
+                }
+            }
+        }
+        if self.busy || now_ms < self.last_submit_ms + self.interval_ms {
+            return;
+        }
+        if self.requests.send(Request::Fit(self.generation, Box::new(job()))).is_ok() {
+            self.busy = true;
+            self.last_submit_ms = now_ms;
+        }
+    }
+
+    /// How many times the worker thread died and was replaced.
+    pub fn restarts(&self) -> usize {
+        self.restarts
+    }
+
+    /// The last completed labelling.
+    pub fn latest(&self) -> Option<&Labelling> {
+        self.latest.as_ref()
+    }
+
+    /// Forgets which orders the last labelling saw, for when the book is reloaded from a
+    /// snapshot and its order ids start over. The models and the labelling on show are kept.
+    pub fn resync(&mut self) {
+        let _ = self.requests.send(Request::Resync);
+    }
+
+    /// Drops the models and the last labelling, e.g. on a symbol change.
+    pub fn reset(&mut self) {
+        let _ = self.requests.send(Request::Reset);
+        self.latest = None;
+        // A labelling still in flight belongs to the old book: it lands but is not shown
+        self.generation += 1;
+        self.last_submit_ms = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmeans::FeatureWeights;
+    use crate::model::Side;
+    use rust_decimal::dec;
+    use std::time::{Duration, Instant};
+
+    fn job() -> ClusterJob {
+        let order = |side, price, qty| OrderSample { id: 0, side, price, qty, age: 0, queue_index: 0 };
+        ClusterJob {
+            bids: vec![order(Side::Bid, dec!(99), dec!(1)), order(Side::Bid, dec!(98), dec!(9))],
+            asks: vec![order(Side::Ask, dec!(101), dec!(2))],
+            mid: dec!(100),
+            tick_size: dec!(1),
+            method: ClusterMethod::KMeans,
+            params: ClusterParams {
+                batch_size: 8,
+                max_iter: 8,
+                weights: FeatureWeights::default(),
+                num_clusters: 2..=2,
+                eps: 0.1,
+                min_points: 2,
+                seed: Some(1),
+            },
+            joint: false,
+        }
+    }
+
+    fn wait_for_labelling(worker: &mut ClusterWorker, now_ms: u64) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while worker.latest().is_none() {
+            assert!(Instant::now() < deadline, "worker never answered");
+            worker.update(now_ms, || panic!("busy worker was given a job"));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_worker_labels_in_background() {
+        let mut worker = ClusterWorker::spawn(100, || {});
+        assert!(worker.latest().is_none());
+        worker.update(1_000, job);
+        wait_for_labelling(&mut worker, 1_000);
+        let labelling = worker.latest().unwrap();
+        assert_eq!(labelling.bids.orders.len(), 2);
+        assert_eq!(labelling.bids.num_clusters, 2);
+        assert_eq!(labelling.asks.orders[&dec!(101)].len(), 1);
+        assert_eq!(labelling.bids.summary.len(), 2);
+        assert_eq!(labelling.bids.summary[0].cancel_rate, None);
+        let bids = &labelling.bids.orders;
+        assert_ne!(bids[&dec!(99)][0].cluster, bids[&dec!(98)][0].cluster);
+
+        // Idle again, but the interval has not passed
+        worker.update(1_050, || panic!("submitted before the interval"));
+        let mut submitted = false;
+        worker.update(1_100, || {
+            submitted = true;
+            job()
+        });
+        assert!(submitted);
+
+        // The job in flight was for the old book and never shows up
+        worker.reset();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while worker.busy {
+            assert!(Instant::now() < deadline, "worker never answered");
+            worker.update(0, || panic!("busy worker was given a job"));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(worker.latest().is_none());
+    }
+
+    // Distinct ids, so orders can be followed from one labelling to the next
+    fn tracked_job(method: ClusterMethod) -> ClusterJob {
+        let mut job = ClusterJob { method, ..job() };
+        for (id, order) in job.bids.iter_mut().chain(&mut job.asks).enumerate() {
+            order.id = id as u64;
+        }
+        job
+    }
+
+    // Labels the same k-means job twice, so the second labelling has cancel rates
+    fn label_twice(worker: &mut ClusterWorker) {
+        worker.update(1_000, || tracked_job(ClusterMethod::KMeans));
+        wait_for_labelling(worker, 1_000);
+        worker.latest = None;
+        worker.update(2_000, || tracked_job(ClusterMethod::KMeans));
+        wait_for_labelling(worker, 2_000);
+        assert!(worker.latest().unwrap().bids.summary.iter().all(|s| s.cancel_rate == Some(0.0)));
+    }
+
+    #[test]
+    fn test_switching_method_forgets_previous_labelling() {
+        let mut worker = ClusterWorker::spawn(100, || {});
+        label_twice(&mut worker);
+        worker.latest = None;
+        worker.update(3_000, || tracked_job(ClusterMethod::GaussianMixture));
+        wait_for_labelling(&mut worker, 3_000);
+        let labelling = worker.latest().unwrap();
+        assert!(!labelling.bids.summary.is_empty());
+        assert!(labelling.bids.summary.iter().all(|s| s.cancel_rate.is_none()));
+        assert!(labelling.asks.summary.iter().all(|s| s.cancel_rate.is_none()));
+    }
+
+    #[test]
+    fn test_resync_forgets_previous_labelling() {
+        let mut worker = ClusterWorker::spawn(100, || {});
+        label_twice(&mut worker);
+        // The reloaded book reuses the ids for other orders
+        worker.resync();
+        assert!(worker.latest().is_some());
+        worker.latest = None;
+        worker.update(3_000, || tracked_job(ClusterMethod::KMeans));
+        wait_for_labelling(&mut worker, 3_000);
+        assert!(worker.latest().unwrap().bids.summary.iter().all(|s| s.cancel_rate.is_none()));
+    }
+
+    #[test]
+    fn test_joint_mode_shares_ids_across_sides() {
+        let order =
+            |id, side, price, qty| OrderSample { id, side, price, qty, age: 0, queue_index: 0 };
+        let mut job = job();
+        // The same two kinds of order on both sides; with side weighted out they pair up
+        job.bids = vec![
+            order(1, Side::Bid, dec!(99), dec!(1)),
+            order(2, Side::Bid, dec!(99), dec!(0)),
+            order(3, Side::Bid, dec!(98), dec!(50)),
+        ];
+        job.asks = vec![
+            order(1, Side::Ask, dec!(101), dec!(50)),
+            order(2, Side::Ask, dec!(102), dec!(1)),
+        ];
+        job.params.weights = FeatureWeights([1.0, 0.0, 0.0, 0.0, 0.0]);
+        job.joint = true;
+
+        let mut worker = ClusterWorker::spawn(100, || {});
+        worker.update(1_000, || job);
+        wait_for_labelling(&mut worker, 1_000);
+        let labelling = worker.latest().unwrap();
+        assert!(labelling.joint);
+        let (bids, asks) = (&labelling.bids.orders, &labelling.asks.orders);
+        assert_eq!(bids[&dec!(99)].len(), 1);
+        assert_eq!(bids[&dec!(99)][0].cluster, asks[&dec!(102)][0].cluster);
+        assert_eq!(bids[&dec!(98)][0].cluster, asks[&dec!(101)][0].cluster);
+        assert_ne!(bids[&dec!(99)][0].cluster, bids[&dec!(98)][0].cluster);
+        assert_eq!(labelling.bids.num_clusters, labelling.asks.num_clusters);
+    }
+
+    #[test]
+    fn test_worker_restarts_after_a_panic() {
+        let mut worker = ClusterWorker::spawn(100, || {});
+        worker.update(1_000, job);
+        wait_for_labelling(&mut worker, 1_000);
+        worker.requests.send(Request::Panic).unwrap();
+        worker.busy = true;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while worker.restarts() == 0 {
+            assert!(Instant::now() < deadline, "worker death went unnoticed");
+            worker.update(1_000, || panic!("busy worker was given a job"));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!worker.busy);
+        assert!(worker.latest().is_some());
+
+        // The new thread takes jobs as before
+        worker.latest = None;
+        worker.update(2_000, job);
+        wait_for_labelling(&mut worker, 2_000);
+        assert_eq!(worker.restarts(), 1);
+    }
+}
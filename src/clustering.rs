@@ -0,0 +1,94 @@
+use rust_decimal::Decimal;
+use std::env;
+use std::ops::RangeInclusive;
+
+use crate::dbscan::DensityClusterer;
+use crate::gmm::GaussianMixture;
+use crate::kmeans::{FeatureWeights, MiniBatchKMeans};
+use crate::model::OrderSample;
+
+/// Label given to orders that belong to no cluster, e.g. sparse outliers under DBSCAN.
+pub const NOISE: usize = usize::MAX;
+
+/// A labelling of the orders with a positive size, in their order.
+pub struct ClusterFit {
+    pub labels: Vec<usize>,
+    // Clusters found, noise excluded
+    pub num_clusters: usize,
+    // Mean silhouette of the labelling, 0 when nothing could be compared
+    pub silhouette: f64,
+}
+
+/// Settings shared by every clusterer; each one reads the fields it needs.
+#[derive(Clone, Debug)]
+pub struct ClusterParams {
+    pub batch_size: usize,
+    pub max_iter: usize,
+    pub weights: FeatureWeights,
+    // Cluster counts tried by the methods that pick one
+    pub num_clusters: RangeInclusive<usize>,
+    // Density clustering: neighbourhood radius in the scaled feature space and the
+    // neighbours (itself included) an order needs to seed a cluster
+    pub eps: f64,
+    pub min_points: usize,
+    // Reseeds the randomised methods before every fit, so the same books give the same labels
+    pub seed: Option<u64>,
+}
+
+/// The seed KMEANS_SEED asks for, if it holds a u64. Anything else is reported and ignored.
+pub fn env_seed() -> Option<u64> {
+    let seed = env::var("KMEANS_SEED").ok()?;
+    match seed.parse() {
+        Ok(seed) => Some(seed),
+        Err(_) => {
+            println!("Warning: KMEANS_SEED={seed} is not a u64, clustering unseeded");
+            None
+        }
+    }
+}
+
+/// Groups resting orders into clusters. Implementations are kept alive across fits so they
+/// can warm-start and keep cluster ids stable while the book evolves.
+pub trait Clusterer: Send {
+    fn cluster(
+        &mut self,
+        orders: &[OrderSample],
+        mid: Decimal,
+        tick_size: Decimal,
+        params: &ClusterParams,
+    ) -> ClusterFit;
+
+    /// Forgets whatever carried over between fits, e.g. when the book switches symbol.
+    fn reset(&mut self);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClusterMethod {
+    KMeans,
+    GaussianMixture,
+    Density,
+}
+
+impl ClusterMethod {
+    pub const ALL: [ClusterMethod; 3] = [
+        ClusterMethod::KMeans,
+        ClusterMethod::GaussianMixture,
+        ClusterMethod::Density,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ClusterMethod::KMeans => "Mini-batch k-means",
+            ClusterMethod::GaussianMixture => "Gaussian mixture (log size)",
+            ClusterMethod::Density => "Density (DBSCAN)",
+        }
+    }
+
+    pub fn build(self) -> Box<dyn Clusterer> {
+        match self {
+            ClusterMethod::KMeans => Box::new(MiniBatchKMeans::new(0, 0, 0)),
+            ClusterMethod::GaussianMixture => Box::new(GaussianMixture::new()),
+            ClusterMethod::Density => Box::new(DensityClusterer::new()),
+        }
+    }
+}
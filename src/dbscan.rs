@@ -0,0 +1,148 @@
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+use crate::clustering::{ClusterFit, ClusterParams, Clusterer, NOISE};
+use crate::kmeans::{self, Point};
+use crate::model::OrderSample;
+
+// Indices of the points within `eps` of `points[i]`, itself included
+fn neighbours(points: &[Point], i: usize, eps: f64) -> Vec<usize> {
+    (0..points.len())
+        .filter(|&j| kmeans::euclidean_distance(&points[i], &points[j]) <= eps)
+        .collect()
+}
+
+// Plain DBSCAN over the weighted feature vectors. Dense regions become clusters whatever their
+// shape or count, and orders in sparse regions are left as noise rather than forced into the
+// nearest group. Clusters are numbered by their mean scaled size, so ids stay put between fits
+// as long as the clusters keep their order.
+pub struct DensityClusterer;
+
+impl DensityClusterer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Clusterer for DensityClusterer {
+    fn cluster(
+        &mut self,
+        orders: &[OrderSample],
+        mid: Decimal,
+        tick_size: Decimal,
+        params: &ClusterParams,
+    ) -> ClusterFit {
+        let points = kmeans::feature_points(orders, mid, tick_size, &params.weights);
+        let min_points = params.min_points.max(1);
+
+        let mut labels = vec![NOISE; points.len()];
+        let mut visited = vec![false; points.len()];
+        let mut num_clusters = 0;
+        for i in 0..points.len() {
+            if visited[i] {
+                continue;
+            }
+            visited[i] = true;
+            let seeds = neighbours(&points, i, params.eps);
+            if seeds.len() < min_points {
+                continue;
+            }
+
+            // Grow a new cluster from this core point, expanding through other core points
+            let cluster = num_clusters;
+            num_clusters += 1;
+            labels[i] = cluster;
+            // Neighbours of the latest core point join the cluster unless an earlier one claimed
+            // them; only those not seen yet are queued to be checked for being core points
+            let mut reach = seeds;
+            let mut queue = VecDeque::new();
+            loop {
+                for k in reach {
+                    if labels[k] == NOISE {
+                        labels[k] = cluster;
+                    }
+                    if !visited[k] {
+                        visited[k] = true;
+                        queue.push_back(k);
+                    }
+                }
+                let Some(j) = queue.pop_front() else {
+                    break;
+                };
+                reach = neighbours(&points, j, params.eps);
+                if reach.len() < min_points {
+                    reach.clear();
+                }
+            }
+        }
+
+        // Renumber by mean scaled size, smallest first
+        let mut sizes = vec![(0.0, 0usize); num_clusters];
+        for (p, &label) in points.iter().zip(&labels) {
+            if label != NOISE {
+                sizes[label].0 += p.coords[0];
+                sizes[label].1 += 1;
+            }
+        }
+        let mut order: Vec<usize> = (0..num_clusters).collect();
+        order.sort_by(|&a, &b| {
+            let mean = |c: usize| sizes[c].0 / sizes[c].1 as f64;
+            mean(a).total_cmp(&mean(b))
+        });
+        let mut rank = vec![0; num_clusters];
+        for (r, &c) in order.iter().enumerate() {
+            rank[c] = r;
+        }
+        for label in labels.iter_mut().filter(|label| **label != NOISE) {
+            *label = rank[*label];
+        }
+
+        ClusterFit {
+            silhouette: kmeans::silhouette(&points, &labels),
+            num_clusters,
+            labels,
+        }
+    }
+
+    fn reset(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmeans::FeatureWeights;
+    use crate::model::Side;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_dbscan_finds_dense_groups_and_noise() {
+        // Many large orders, many small ones, and one odd size in between
+        let sizes = std::iter::repeat_n(dec!(50), 5)
+            .chain(std::iter::repeat_n(dec!(1), 6))
+            .chain([dec!(7)]);
+        let orders: Vec<OrderSample> = sizes
+            .map(|qty| OrderSample {
+                id: 0,
+                side: Side::Ask,
+                price: dec!(101),
+                qty,
+                age: 0,
+                queue_index: 0,
+            })
+            .collect();
+        let params = ClusterParams {
+            batch_size: 0,
+            max_iter: 0,
+            weights: FeatureWeights([1.0, 0.0, 0.0, 0.0, 0.0]),
+            num_clusters: 1..=1,
+            eps: 0.05,
+            min_points: 3,
+            seed: None,
+        };
+        let fit = DensityClusterer::new().cluster(&orders, dec!(100), dec!(1), &params);
+        assert_eq!(fit.num_clusters, 2);
+        // Numbered by size, so the small orders come first
+        assert_eq!(fit.labels, [1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, NOISE]);
+        assert!(fit.silhouette > 0.99);
+    }
+}
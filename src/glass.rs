@@ -1,21 +1,18 @@
 use ahash::AHashMap as HashMap;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 use std::array;
 use std::cell::{Cell, UnsafeCell};
 
 const BITS_PER_LEVEL: usize = 6; // Radix: 64 children per node
 const NUM_CHILDREN: usize = 1 << BITS_PER_LEVEL;
-const KEY_BITS: usize = 32;
-const LAST_BITS: usize = if KEY_BITS % BITS_PER_LEVEL == 0 {
-    BITS_PER_LEVEL
-} else {
-    KEY_BITS % BITS_PER_LEVEL
-};
-const LAST_MASK: u64 = (1 << LAST_BITS) - 1;
-const NUM_LEVELS: usize = (KEY_BITS + BITS_PER_LEVEL - 1) / BITS_PER_LEVEL;
-const MAX_SIZE: usize = 4096;
-const ARENA_CAPACITY: usize = 16384;
+const MAX_KEY_BITS: usize = 32;
+// Deepest trie any key width needs; per-glass depth is `num_levels`
+const MAX_LEVELS: usize = MAX_KEY_BITS.div_ceil(BITS_PER_LEVEL);
+const DEFAULT_MAX_SIZE: usize = 4096;
+const DEFAULT_ARENA_CAPACITY: usize = 16384;
 
+#[derive(Clone)]
 struct GlassNode {
     mask: u64,
     value: Option<u64>,
@@ -36,12 +33,343 @@ impl GlassNode {
     }
 }
 
+/// How the trie finds set bits in its child masks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitScan {
+    /// BMI1/BMI2/LZCNT intrinsics where the CPU reports them, portable code otherwise.
+    Detect,
+    /// Plain `u64` bit operations, on every target.
+    Portable,
+}
+
+/// Sizing of a [`Glass`]. The defaults are what `Glass::new()` uses.
+#[derive(Clone, Copy, Debug)]
+pub struct GlassConfig {
+    /// Keys held in the trie; anything beyond waits in the preempt map.
+    pub hot_levels: usize,
+    /// Trie nodes reserved up front.
+    pub arena_capacity: usize,
+    /// Significant key bits, 1..=32. Fewer bits make a shallower trie; keys at or above
+    /// 2^key_bits are always kept in the preempt map.
+    pub key_bits: usize,
+    pub bit_scan: BitScan,
+}
+
+impl Default for GlassConfig {
+    fn default() -> Self {
+        Self {
+            hot_levels: DEFAULT_MAX_SIZE,
+            arena_capacity: DEFAULT_ARENA_CAPACITY,
+            key_bits: MAX_KEY_BITS,
+            bit_scan: BitScan::Detect,
+        }
+    }
+}
+
+/// Bounds for a non-mutating walk through the book. Unset fields do not limit the walk.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SweepLimit {
+    pub shares: Option<u64>,
+    pub notional: Option<u64>,
+    /// Worst key the walk may reach: highest for buys, lowest for sells.
+    pub price: Option<u32>,
+}
+
+/// What a walk through the book would fill.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sweep {
+    /// Exact: the levels of a book can hold more than u64::MAX shares between them.
+    pub filled: u128,
+    /// Exact: a key times any share count stays far inside u128.
+    pub notional: u128,
+    /// Last key the walk took shares from.
+    pub last_price: Option<u32>,
+}
+
+impl Sweep {
+    pub fn vwap(&self) -> Option<f64> {
+        (self.filled > 0).then(|| self.notional as f64 / self.filled as f64)
+    }
+}
+
+/// A total (cost, proceeds or summed depth) that does not fit in a u64, with its exact value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CostOverflow(pub u128);
+
+// Totals are summed in u128, where u32 keys times at most u64::MAX shares (or 2^32 values of
+// u64::MAX each) cannot wrap, and only narrowed at the end.
+#[inline(always)]
+fn narrow_cost(total: u128) -> Result<u64, CostOverflow> {
+    u64::try_from(total).map_err(|_| CostOverflow(total))
+}
+
+// Walks `levels` (already ordered from the touch) until a bound in `limit` is hit.
+#[inline(always)]
+fn sweep_levels(levels: impl Iterator<Item = (u32, u64)>, ascending: bool, limit: SweepLimit) -> Sweep {
+    let mut sweep = Sweep::default();
+    for (price, avail) in levels {
+        if let Some(limit_price) = limit.price
+            && (if ascending { price > limit_price } else { price < limit_price })
+        {
+            break;
+        }
+        let mut take = avail;
+        if let Some(shares) = limit.shares {
+            // Never more than `shares`, so the difference fits
+            take = take.min((shares as u128 - sweep.filled) as u64);
+        }
+        if let Some(notional) = limit.notional
+            && price > 0
+        {
+            // Never more than `notional`, so the quotient fits
+            take = take.min(((notional as u128 - sweep.notional) / price as u128) as u64);
+        }
+        if take == 0 {
+            break;
+        }
+        sweep.filled += take as u128;
+        sweep.notional += price as u128 * take as u128;
+        sweep.last_price = Some(price);
+        // Only move to the next level once this one is used up
+        if take < avail {
+            break;
+        }
+    }
+    sweep
+}
+
+/// Ordered walk over the (key, value) pairs of a [`Glass`], merging the trie and the preempt
+/// map. Ascending walks finish the glass before the preempt keys, descending ones the reverse.
+pub struct Levels<'a> {
+    glass: &'a Glass,
+    ascending: bool,
+    // Trie frames from the root down: (node, key prefix, children still to visit)
+    stack: [(usize, u32, u64); MAX_LEVELS],
+    depth: usize,
+    // The starting key itself, when it is stored in the glass
+    pending: Option<(u32, u64)>,
+    preempt_keys: &'a [u32],
+}
+
+impl<'a> Levels<'a> {
+    fn new(glass: &'a Glass, ascending: bool, from: u32) -> Self {
+        let sorted = glass.sorted_preempt_keys();
+        let preempt_keys = if ascending {
+            &sorted[sorted.partition_point(|&k| k < from)..]
+        } else {
+            &sorted[..sorted.partition_point(|&k| k <= from)]
+        };
+        let mut levels = Levels {
+            glass,
+            ascending,
+            stack: [(0, 0, 0); MAX_LEVELS],
+            depth: 0,
+            pending: None,
+            preempt_keys,
+        };
+
+        // No glass key reaches the key limit: ascending walks from there skip the trie,
+        // descending ones start from its top.
+        let from = if from < glass.key_limit {
+            from
+        } else if ascending {
+            return levels;
+        } else {
+            glass.key_limit - 1
+        };
+
+        // Follow the path of `from`, leaving behind at each node only the children on the
+        // walking side of it.
+        let mut node_idx = glass.root;
+        let mut key = 0u32;
+        for depth in 0..glass.num_levels {
+            let shift = glass.level_shift(depth);
+            let bits_this_level = glass.level_bits(depth);
+            let slot = ((from >> shift) & ((1u32 << bits_this_level) - 1)) as usize;
+            let mask = glass.arena[node_idx].mask;
+            let rest = if ascending {
+                mask & u64::MAX.checked_shl(slot as u32 + 1).unwrap_or(0)
+            } else {
+                mask & ((1u64 << slot) - 1)
+            };
+            levels.stack[depth] = (node_idx, key, rest);
+            levels.depth = depth + 1;
+            match glass.arena[node_idx].children[slot] {
+                Some(child) => {
+                    key |= (slot as u32) << shift;
+                    node_idx = child;
+                }
+                None => return levels,
+            }
+        }
+        levels.pending = glass.arena[node_idx].value.map(|v| (from, v));
+        levels
+    }
+
+    fn next_glass(&mut self) -> Option<(u32, u64)> {
+        if let Some(hit) = self.pending.take() {
+            return Some(hit);
+        }
+        let arena = &self.glass.arena;
+        while self.depth > 0 {
+            let depth = self.depth - 1;
+            let (node_idx, key, mask) = self.stack[depth];
+            let slot = if self.ascending {
+                self.glass.find_next_set_bit(mask, 0)
+            } else {
+                self.glass.find_prev_set_bit(mask, NUM_CHILDREN)
+            };
+            let Some(slot) = slot else {
+                self.depth -= 1;
+                continue;
+            };
+            self.stack[depth].2 &= !(1u64 << slot);
+            let shift = self.glass.level_shift(depth);
+            let child_key = key | ((slot as u32) << shift);
+            let child_idx = arena[node_idx].children[slot].unwrap();
+            if depth + 1 == self.glass.num_levels {
+                if let Some(v) = arena[child_idx].value {
+                    return Some((child_key, v));
+                }
+            } else {
+                self.stack[depth + 1] = (child_idx, child_key, arena[child_idx].mask);
+                self.depth += 1;
+            }
+        }
+        None
+    }
+
+    fn next_preempt(&mut self) -> Option<(u32, u64)> {
+        let (&key, rest) = if self.ascending {
+            self.preempt_keys.split_first()?
+        } else {
+            self.preempt_keys.split_last()?
+        };
+        self.preempt_keys = rest;
+        let value = unsafe { (&*self.glass.preempt.get())[&key] };
+        Some((key, value))
+    }
+}
+
+impl Iterator for Levels<'_> {
+    type Item = (u32, u64);
+
+    fn next(&mut self) -> Option<(u32, u64)> {
+        if self.ascending {
+            self.next_glass().or_else(|| self.next_preempt())
+        } else {
+            self.next_preempt().or_else(|| self.next_glass())
+        }
+    }
+}
+
+/// Read-only copy of every level of a [`Glass`], sorted by key. Nothing is shared with the
+/// book: taking one walks and copies all of its levels, 16 bytes each, preempt map included.
+/// Afterwards it is plain data, independent of the book it came from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GlassSnapshot {
+    levels: Vec<(u32, u64)>,
+}
+
+impl GlassSnapshot {
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn min(&self) -> Option<(u32, u64)> {
+        self.levels.first().copied()
+    }
+
+    pub fn max(&self) -> Option<(u32, u64)> {
+        self.levels.last().copied()
+    }
+
+    /// Every (key, value) in ascending key order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (u32, u64)> + '_ {
+        self.levels.iter().copied()
+    }
+
+    /// Total value stored under keys in `lo..=hi`.
+    pub fn range_sum(&self, lo: u32, hi: u32) -> Result<u64, CostOverflow> {
+        let start = self.levels.partition_point(|&(k, _)| k < lo);
+        let total = self.levels[start..]
+            .iter()
+            .take_while(|&&(k, _)| k <= hi)
+            .map(|&(_, v)| v as u128)
+            .sum();
+        narrow_cost(total)
+    }
+
+    pub fn sweep_buy(&self, limit: SweepLimit) -> Sweep {
+        sweep_levels(self.iter(), true, limit)
+    }
+
+    pub fn sweep_sell(&self, limit: SweepLimit) -> Sweep {
+        sweep_levels(self.iter().rev(), false, limit)
+    }
+}
+
+/// Collects levels in any order; they are sorted by key.
+impl FromIterator<(u32, u64)> for GlassSnapshot {
+    fn from_iter<I: IntoIterator<Item = (u32, u64)>>(iter: I) -> Self {
+        let mut levels: Vec<(u32, u64)> = iter.into_iter().collect();
+        levels.sort_unstable_by_key(|&(key, _)| key);
+        Self { levels }
+    }
+}
+
+/// Why [`Glass::from_bytes`] rejected its input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Not a serialized glass, or one written by an incompatible version.
+    BadMagic,
+    /// The input ends before the data it describes.
+    Truncated,
+    /// The input parses but does not describe a valid glass.
+    Corrupt,
+}
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"GLS\x02";
+
+// Little-endian cursor over a serialized glass.
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl ByteReader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        let (head, rest) = self.bytes.split_first_chunk::<N>().ok_or(SnapshotError::Truncated)?;
+        self.bytes = rest;
+        Ok(*head)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+}
+
 pub struct Glass {
     // === Hot frequently accessed fields ===
     root: usize,                        // 8 bytes
     cached_d: Cell<usize>,              // 8
     cached_last_key: Cell<Option<u32>>, // 8
 
+    // Geometry and capacity, fixed at construction
+    max_size: usize,   // 8
+    key_bits: usize,   // 8
+    num_levels: usize, // 8
+    last_bits: usize,  // 8
+    // Exclusive upper bound for glass keys; `thres` never exceeds it
+    key_limit: u32, // 4
+
     // Bounds and thresholds
     min_key: Cell<u32>,     // 4
     preempt_min: Cell<u32>, // 4
@@ -52,10 +380,16 @@ pub struct Glass {
     // Flags: manually packed
     preempt_bounds_valid: Cell<bool>, // 1
     preempt_dirty: Cell<bool>,        // 1
-    has_bmi2: bool,                   // 1
-    has_bmi1: bool,                   // 1
-    has_lzcnt: bool,                  // 1
-    _padding_flags: [u8; 3],          // pad to align next 8-byte field
+    // CPU features, only consulted on x86_64
+    #[cfg_attr(not(target_arch = "x86_64"), allow(dead_code))]
+    has_bmi2: bool, // 1
+    #[cfg_attr(not(target_arch = "x86_64"), allow(dead_code))]
+    has_bmi1: bool, // 1
+    #[cfg_attr(not(target_arch = "x86_64"), allow(dead_code))]
+    has_lzcnt: bool, // 1
+    // How the flags above were chosen, kept for checkpoints
+    bit_scan: BitScan, // 1
+    _padding_flags: [u8; 2],          // pad to align next 8-byte field
 
     // Leaf node tracking
     min_leaf: Cell<Option<usize>>, // 16
@@ -64,7 +398,7 @@ pub struct Glass {
     // === Larger cold/cached structures ===
     cache: UnsafeCell<HashMap<u32, usize>>,       // 8
     preempt: UnsafeCell<HashMap<u32, u64>>,       // 8
-    cached_path: UnsafeCell<[usize; NUM_LEVELS]>, // 8
+    cached_path: UnsafeCell<[usize; MAX_LEVELS]>, // 8
     sorted_preempt_keys: UnsafeCell<Vec<u32>>,    // 16
 
     arena: Vec<GlassNode>,  // 24
@@ -79,11 +413,60 @@ impl Default for Glass {
 
 impl Glass {
     pub fn new() -> Self {
-        let mut arena = Vec::with_capacity(ARENA_CAPACITY);
+        Self::with_config(GlassConfig::default())
+    }
+
+    /// Picks the bit-scan implementation, e.g. to force the portable one on x86.
+    #[cfg(test)]
+    pub fn with_bit_scan(bit_scan: BitScan) -> Self {
+        Self::with_config(GlassConfig { bit_scan, ..GlassConfig::default() })
+    }
+
+    /// Keeps up to `hot_levels` keys in the trie and reserves `arena_capacity` nodes.
+    #[cfg(test)]
+    pub fn with_capacity(hot_levels: usize, arena_capacity: usize) -> Self {
+        Self::with_config(GlassConfig {
+            hot_levels,
+            arena_capacity,
+            ..GlassConfig::default()
+        })
+    }
+
+    pub fn with_config(config: GlassConfig) -> Self {
+        assert!(config.hot_levels > 0, "a glass needs room for at least one key");
+        assert!(
+            (1..=MAX_KEY_BITS).contains(&config.key_bits),
+            "key_bits must be within 1..={MAX_KEY_BITS}"
+        );
+        let key_bits = config.key_bits;
+        let num_levels = key_bits.div_ceil(BITS_PER_LEVEL);
+        let last_bits = key_bits - (num_levels - 1) * BITS_PER_LEVEL;
+        // u32::MAX itself has never been a glass key
+        let key_limit = if key_bits == MAX_KEY_BITS { u32::MAX } else { 1 << key_bits };
+
+        let bit_scan = config.bit_scan;
+        #[cfg(target_arch = "x86_64")]
+        let (has_bmi1, has_bmi2, has_lzcnt) = match bit_scan {
+            BitScan::Detect => (
+                std::is_x86_feature_detected!("bmi1"),
+                std::is_x86_feature_detected!("bmi2"),
+                std::is_x86_feature_detected!("lzcnt"),
+            ),
+            BitScan::Portable => (false, false, false),
+        };
+        #[cfg(not(target_arch = "x86_64"))]
+        let (has_bmi1, has_bmi2, has_lzcnt) = (false, false, false);
+
+        let mut arena = Vec::with_capacity(config.arena_capacity.max(1));
         arena.push(GlassNode::new());
-        let mut cached_path = [0; NUM_LEVELS];
+        let mut cached_path = [0; MAX_LEVELS];
         cached_path[0] = 0;
         Glass {
+            max_size: config.hot_levels,
+            key_bits,
+            num_levels,
+            last_bits,
+            key_limit,
             arena,
             free_list: Vec::new(),
             root: 0,
@@ -99,13 +482,14 @@ impl Glass {
             cached_last_key: Cell::new(None),
             min_key: Cell::new(u32::MAX),
             preempt_min: Cell::new(u32::MAX),
-            thres: Cell::new(u32::MAX),
+            thres: Cell::new(key_limit),
             max_key: Cell::new(0),
             preempt_max: Cell::new(0),
-            has_bmi2: std::is_x86_feature_detected!("bmi2"),
-            has_bmi1: std::is_x86_feature_detected!("bmi1"),
-            has_lzcnt: std::is_x86_feature_detected!("lzcnt"),
-            _padding_flags: [0; 3],
+            has_bmi2,
+            has_bmi1,
+            has_lzcnt,
+            bit_scan,
+            _padding_flags: [0; 2],
         }
     }
 
@@ -114,6 +498,43 @@ impl Glass {
         self.arena[self.root].count as usize
     }
 
+    /// How many keys the trie holds before spilling into the preempt map.
+    pub fn hot_levels(&self) -> usize {
+        self.max_size
+    }
+
+    // Whether `key` lives in the trie rather than the preempt map.
+    #[cfg(test)]
+    pub(crate) fn is_hot(&self, key: u32) -> bool {
+        self.check_bounds_and_thres(key)
+    }
+
+    pub fn key_bits(&self) -> usize {
+        self.key_bits
+    }
+
+    pub fn bit_scan(&self) -> BitScan {
+        self.bit_scan
+    }
+
+    // Shift of the child slot bits at `depth`; the last level takes whatever bits remain.
+    #[inline(always)]
+    fn level_shift(&self, depth: usize) -> usize {
+        self.key_bits.saturating_sub((depth + 1) * BITS_PER_LEVEL)
+    }
+
+    #[inline(always)]
+    fn level_bits(&self, depth: usize) -> usize {
+        BITS_PER_LEVEL.min(self.key_bits.saturating_sub(depth * BITS_PER_LEVEL))
+    }
+
+    // Number of whole trie levels two keys share, used to resume from the cached path.
+    #[inline(always)]
+    fn shared_levels(&self, a: u32, b: u32) -> usize {
+        let common_bits = ((a ^ b).leading_zeros() as usize).saturating_sub(MAX_KEY_BITS - self.key_bits);
+        (common_bits / BITS_PER_LEVEL).min(self.num_levels - 1)
+    }
+
     #[inline(always)]
     pub fn insert(&mut self, key: u32, value: u64) {
         if value == 0 {
@@ -123,28 +544,40 @@ impl Glass {
         if self.get(key).is_some() {
             self.update_value(key, |v| *v = value);
         } else if self.check_bounds_and_thres(key) {
-            if self.glass_size() < MAX_SIZE {
+            if self.glass_size() < self.max_size {
                 self.glass_insert(key, value);
-            } else {
-                if let Some((worst_key, worst_v)) = self.glass_max() {
-                    self.glass_remove(worst_key);
-                    unsafe {
-                        let preempt = &mut *self.preempt.get();
-                        preempt.insert(worst_key, worst_v);
-                    }
-                    self.preempt_bounds_valid.set(false);
-                    self.preempt_dirty.set(true);
-                }
+            } else if let Some((worst_key, worst_v)) = self.glass_max()
+                && worst_key > key
+            {
+                self.glass_remove(worst_key);
+                self.preempt_insert(worst_key, worst_v);
+                // Everything left in the glass is below the evicted key, which is now the
+                // smallest preempted key.
+                self.thres.set(worst_key);
                 self.glass_insert(key, value);
+            } else {
+                // Full glass and the key sits above all of it: it belongs in the preempt map.
+                self.preempt_insert(key, value);
+                self.thres.set(self.thres.get().min(key));
             }
         } else {
-            unsafe {
-                let preempt = &mut *self.preempt.get();
-                preempt.insert(key, value);
+            self.preempt_insert(key, value);
+        }
+    }
+
+    /// Adds a key that is not stored yet to the preempt map, keeping the sorted key cache in
+    /// step when it is current.
+    #[inline(always)]
+    fn preempt_insert(&mut self, key: u32, value: u64) {
+        unsafe {
+            (*self.preempt.get()).insert(key, value);
+            if !self.preempt_dirty.get() {
+                let sorted = &mut *self.sorted_preempt_keys.get();
+                let pos = sorted.partition_point(|&k| k < key);
+                sorted.insert(pos, key);
             }
-            self.preempt_bounds_valid.set(false);
-            self.preempt_dirty.set(true);
         }
+        self.preempt_bounds_valid.set(false);
     }
 
     #[inline(always)]
@@ -159,39 +592,34 @@ impl Glass {
     /// Removes and returns the k-th smallest element from the entire collection (glass + preempt).
     ///
     /// The overall sorted order is all glass elements followed by all preempt elements.
-    /// Complexity is dominated by sorting the preempt map's keys if the index `k`
-    /// falls within the preempt map's range.
+    /// Positions in the preempt map come from the cached sorted key list, see [`Glass::kth`].
     ///
     /// # Arguments
     /// * `k`: The 0-based index of the element to remove.
     ///
     /// # Returns
     /// `Some((key, value))` of the removed element, or `None` if `k` is out of bounds.
+    #[cfg(test)]
     #[inline(always)]
     pub fn remove_by_index(&mut self, k: usize) -> Option<(u32, u64)> {
-        let glass_size = self.glass_size();
+        let (key, _) = self.kth(k)?;
+        self.remove(key).map(|value| (key, value))
+    }
 
-        let key_to_remove = if k < glass_size {
-            // The k-th element is in the glass. Find its key.
-            self.glass_find_kth_key(k)?
+    /// The k-th smallest (key, value) without removing it. Glass positions come from the
+    /// subtree counts, preempt ones from the sorted key cache, which is only rebuilt after
+    /// bulk changes.
+    #[cfg(test)]
+    #[inline(always)]
+    pub fn kth(&self, k: usize) -> Option<(u32, u64)> {
+        let glass_size = self.glass_size();
+        if k < glass_size {
+            let key = self.glass_find_kth_key(k)?;
+            self.glass_get(key).map(|value| (key, value))
         } else {
-            // The k-th element is in the preempt map.
-            let preempt_k = k - glass_size;
-            let preempt_map = unsafe { &*self.preempt.get() };
-            if preempt_k >= preempt_map.len() {
-                return None; // k is out of bounds.
-            }
-
-            // This is O(M log M) where M is preempt size, but unavoidable for a HashMap.
-            let mut keys: Vec<u32> = preempt_map.keys().cloned().collect();
-            keys.sort_unstable();
-            keys[preempt_k]
-        };
-
-        // Use the existing general-purpose remove function, which will correctly
-        // handle state changes (including glass counts).
-        self.remove(key_to_remove)
-            .map(|value| (key_to_remove, value))
+            let key = *self.sorted_preempt_keys().get(k - glass_size)?;
+            unsafe { (*self.preempt.get()).get(&key).map(|&value| (key, value)) }
+        }
     }
 
     #[inline(always)]
@@ -224,15 +652,21 @@ impl Glass {
             unsafe {
                 let preempt = &mut *self.preempt.get();
                 preempt.remove(&key).inspect(|_val| {
+                    let sorted = &mut *self.sorted_preempt_keys.get();
                     if preempt.is_empty() {
-                        self.thres.set(u32::MAX);
+                        self.thres.set(self.key_limit);
                         self.preempt_min.set(u32::MAX);
                         self.preempt_max.set(0);
                         self.preempt_bounds_valid.set(true);
                         self.preempt_dirty.set(false);
+                        sorted.clear();
                     } else {
                         self.preempt_bounds_valid.set(false);
-                        self.preempt_dirty.set(true);
+                        if !self.preempt_dirty.get()
+                            && let Ok(pos) = sorted.binary_search(&key)
+                        {
+                            sorted.remove(pos);
+                        }
                     }
                 })
             }
@@ -242,7 +676,7 @@ impl Glass {
     #[inline(always)]
     fn check_bounds_and_thres(&self, key: u32) -> bool {
         let thres = self.thres.get();
-        if thres == u32::MAX && !self.preempt_bounds_valid.get() {
+        if thres == self.key_limit && !self.preempt_bounds_valid.get() {
             self.update_preempt_bounds();
         }
         key < self.thres.get()
@@ -292,6 +726,7 @@ impl Glass {
         }
     }
 
+    #[cfg(test)]
     #[inline(always)]
     pub fn max(&self) -> Option<(u32, u64)> {
         if !self.preempt_bounds_valid.get() {
@@ -341,7 +776,7 @@ impl Glass {
         unsafe {
             let preempt = &*self.preempt.get();
             if preempt.is_empty() {
-                self.thres.set(u32::MAX);
+                self.thres.set(self.key_limit);
                 self.preempt_min.set(u32::MAX);
                 self.preempt_max.set(0);
             } else {
@@ -355,7 +790,7 @@ impl Glass {
                         new_max = k;
                     }
                 }
-                self.thres.set(new_min);
+                self.thres.set(new_min.min(self.key_limit));
                 self.preempt_min.set(new_min);
                 self.preempt_max.set(new_max);
             }
@@ -363,13 +798,14 @@ impl Glass {
         self.preempt_bounds_valid.set(true);
     }
 
+    #[cfg(test)]
     #[inline(always)]
     fn restructure(&mut self) {
         let sigma = self.glass_size();
-        if sigma >= MAX_SIZE {
+        if sigma >= self.max_size {
             return;
         }
-        let n = MAX_SIZE - sigma;
+        let n = self.max_size - sigma;
 
         let mut to_move = vec![];
         unsafe {
@@ -379,7 +815,8 @@ impl Glass {
             }
             let mut keys_vec: Vec<u32> = preempt.keys().cloned().collect();
             keys_vec.sort_unstable();
-            for k in keys_vec.into_iter().take(n) {
+            // Keys past the key width can never move into the trie
+            for k in keys_vec.into_iter().take_while(|&k| k < self.key_limit).take(n) {
                 if let Some(v) = preempt.remove(&k) {
                     to_move.push((k, v));
                 }
@@ -388,24 +825,29 @@ impl Glass {
         for (k, v) in to_move {
             self.glass_insert(k, v);
         }
-        self.preempt_bounds_valid.set(false);
+        // The moved keys were the smallest preempted ones, so the threshold has to follow.
+        self.update_preempt_bounds();
         self.preempt_dirty.set(true);
     }
 
+    /// Buys from the lowest key up and returns the total cost. The shares are taken even
+    /// when the cost overflows a u64; the error then carries the exact amount.
+    #[cfg(test)]
     #[inline(always)]
-    pub fn buy_shares(&mut self, mut shares_to_buy: u64) -> u64 {
-        let mut total_cost = 0u64;
+    pub fn buy_shares(&mut self, mut shares_to_buy: u64) -> Result<u64, CostOverflow> {
+        let mut total_cost = 0u128;
 
         if self.glass_size() == 0 && !unsafe { (&*self.preempt.get()).is_empty() } {
             self.restructure();
         }
 
         while shares_to_buy > 0 {
-            if let Some((price, _)) = self.glass_min() {
+            // Normally the glass minimum; keys past the key width only ever live preempted
+            if let Some((price, _)) = self.min() {
                 let mut is_empty = false;
                 let updated = self.update_value(price, |avail| {
                     let buy = (*avail).min(shares_to_buy);
-                    total_cost += (price as u64) * buy;
+                    total_cost += price as u128 * buy as u128;
                     *avail -= buy;
                     shares_to_buy -= buy;
                     is_empty = *avail == 0;
@@ -413,7 +855,7 @@ impl Glass {
 
                 if updated && is_empty {
                     self.remove(price);
-                    if self.glass_size() < MAX_SIZE {
+                    if self.glass_size() < self.max_size {
                         self.restructure();
                     }
                 } else if !updated {
@@ -423,32 +865,197 @@ impl Glass {
                 break;
             }
         }
-        total_cost
+        narrow_cost(total_cost)
+    }
+
+    /// Sells into the book from the highest key down, consuming preempted levels first
+    /// since they always sit above the glass. Returns the total proceeds, or their exact
+    /// value if they overflow a u64 (the shares are sold either way).
+    #[cfg(test)]
+    #[inline(always)]
+    pub fn sell_shares(&mut self, mut shares_to_sell: u64) -> Result<u64, CostOverflow> {
+        let mut total_proceeds = 0u128;
+
+        while shares_to_sell > 0 {
+            if let Some((price, _)) = self.max() {
+                let mut is_empty = false;
+                let updated = self.update_value(price, |avail| {
+                    let sell = (*avail).min(shares_to_sell);
+                    total_proceeds += price as u128 * sell as u128;
+                    *avail -= sell;
+                    shares_to_sell -= sell;
+                    is_empty = *avail == 0;
+                });
+
+                if updated && is_empty {
+                    self.remove(price);
+                } else if !updated {
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+        narrow_cost(total_proceeds)
+    }
+
+    #[inline(always)]
+    fn sorted_preempt_keys(&self) -> &Vec<u32> {
+        if self.preempt_dirty.get() {
+            let mut keys: Vec<u32> = unsafe { (*self.preempt.get()).keys().cloned().collect() };
+            keys.sort_unstable();
+            unsafe { *self.sorted_preempt_keys.get() = keys; }
+            self.preempt_dirty.set(false);
+        }
+        unsafe { &*self.sorted_preempt_keys.get() }
     }
 
+    /// Cost of buying `target_shares` from the lowest key up, without mutating the book.
+    #[cfg(test)]
     #[inline(always)]
-    pub fn compute_buy_cost(&self, mut target_shares: u64) -> u64 {
-        let mut total_cost = 0u64;
+    pub fn compute_buy_cost(&self, mut target_shares: u64) -> Result<u64, CostOverflow> {
+        let mut total_cost = 0u128;
         self.glass_compute_buy_cost(&mut target_shares, &mut total_cost);
         if target_shares > 0 {
-            if self.preempt_dirty.get() {
-                let mut keys: Vec<u32> = unsafe { (*self.preempt.get()).keys().cloned().collect() };
-                keys.sort_unstable();
-                unsafe { *self.sorted_preempt_keys.get() = keys; }
-                self.preempt_dirty.set(false);
-            }
-            let sorted_keys = unsafe { &*self.sorted_preempt_keys.get() };
+            let sorted_keys = self.sorted_preempt_keys();
             for &k in sorted_keys {
                 if target_shares == 0 {
                     break;
                 }
                 let avail_shares = *unsafe { (*self.preempt.get()).get(&k).unwrap() };
                 let buy = avail_shares.min(target_shares);
-                total_cost = total_cost.saturating_add((k as u64).saturating_mul(buy));
+                total_cost += k as u128 * buy as u128;
                 target_shares -= buy;
             }
         }
-        total_cost
+        narrow_cost(total_cost)
+    }
+
+    /// Proceeds of selling `target_shares` from the highest key down, without mutating the book.
+    #[cfg(test)]
+    #[inline(always)]
+    pub fn compute_sell_proceeds(&self, mut target_shares: u64) -> Result<u64, CostOverflow> {
+        let mut total_proceeds = 0u128;
+        for &k in self.sorted_preempt_keys().iter().rev() {
+            if target_shares == 0 {
+                break;
+            }
+            let avail_shares = *unsafe { (*self.preempt.get()).get(&k).unwrap() };
+            let sell = avail_shares.min(target_shares);
+            total_proceeds += k as u128 * sell as u128;
+            target_shares -= sell;
+        }
+        self.glass_compute_sell_proceeds(&mut target_shares, &mut total_proceeds);
+        narrow_cost(total_proceeds)
+    }
+
+    /// Walks up from the lowest key until one of the bounds in `limit` is hit.
+    #[cfg(test)]
+    pub fn sweep_buy(&self, limit: SweepLimit) -> Sweep {
+        self.sweep(true, limit)
+    }
+
+    /// Walks down from the highest key until one of the bounds in `limit` is hit.
+    #[cfg(test)]
+    pub fn sweep_sell(&self, limit: SweepLimit) -> Sweep {
+        self.sweep(false, limit)
+    }
+
+    /// Shares (and their cost) available at or below `limit_price`.
+    #[cfg(test)]
+    pub fn buy_quantity_within(&self, limit_price: u32) -> Sweep {
+        self.sweep_buy(SweepLimit { price: Some(limit_price), ..Default::default() })
+    }
+
+    /// Shares (and their cost) bought without exceeding `notional` in value.
+    #[cfg(test)]
+    pub fn buy_quantity_for_notional(&self, notional: u64) -> Sweep {
+        self.sweep_buy(SweepLimit { notional: Some(notional), ..Default::default() })
+    }
+
+    /// Average price of buying `shares`, `None` if the book is empty.
+    #[cfg(test)]
+    pub fn buy_vwap(&self, shares: u64) -> Option<f64> {
+        self.sweep_buy(SweepLimit { shares: Some(shares), ..Default::default() }).vwap()
+    }
+
+    /// Highest key touched when buying `shares`.
+    #[cfg(test)]
+    pub fn buy_price_reached(&self, shares: u64) -> Option<u32> {
+        self.sweep_buy(SweepLimit { shares: Some(shares), ..Default::default() }).last_price
+    }
+
+    /// Shares (and their proceeds) available at or above `limit_price`.
+    #[cfg(test)]
+    pub fn sell_quantity_within(&self, limit_price: u32) -> Sweep {
+        self.sweep_sell(SweepLimit { price: Some(limit_price), ..Default::default() })
+    }
+
+    /// Shares (and their proceeds) sold without exceeding `notional` in value.
+    #[cfg(test)]
+    pub fn sell_quantity_for_notional(&self, notional: u64) -> Sweep {
+        self.sweep_sell(SweepLimit { notional: Some(notional), ..Default::default() })
+    }
+
+    /// Average price of selling `shares`, `None` if the book is empty.
+    #[cfg(test)]
+    pub fn sell_vwap(&self, shares: u64) -> Option<f64> {
+        self.sweep_sell(SweepLimit { shares: Some(shares), ..Default::default() }).vwap()
+    }
+
+    /// Lowest key touched when selling `shares`.
+    #[cfg(test)]
+    pub fn sell_price_reached(&self, shares: u64) -> Option<u32> {
+        self.sweep_sell(SweepLimit { shares: Some(shares), ..Default::default() }).last_price
+    }
+
+    #[cfg(test)]
+    #[inline(always)]
+    fn sweep(&self, ascending: bool, limit: SweepLimit) -> Sweep {
+        let levels = if ascending { self.iter() } else { self.iter_rev() };
+        sweep_levels(levels, ascending, limit)
+    }
+
+    /// Every (key, value) in ascending key order.
+    pub fn iter(&self) -> Levels<'_> {
+        Levels::new(self, true, 0)
+    }
+
+    /// Every (key, value) in descending key order.
+    #[cfg(test)]
+    pub fn iter_rev(&self) -> Levels<'_> {
+        Levels::new(self, false, u32::MAX)
+    }
+
+    /// Ascending from the first key at or above `from`.
+    #[cfg(test)]
+    pub fn iter_from(&self, from: u32) -> Levels<'_> {
+        Levels::new(self, true, from)
+    }
+
+    /// Descending from the first key at or below `from`.
+    #[cfg(test)]
+    pub fn iter_rev_from(&self, from: u32) -> Levels<'_> {
+        Levels::new(self, false, from)
+    }
+
+    /// (key, value) pairs with `lo <= key <= hi`, ascending.
+    #[cfg(test)]
+    pub fn range(&self, lo: u32, hi: u32) -> impl Iterator<Item = (u32, u64)> + '_ {
+        self.iter_from(lo).take_while(move |&(k, _)| k <= hi)
+    }
+
+    /// Total value stored under keys in `lo..=hi`.
+    #[cfg(test)]
+    pub fn range_sum(&self, lo: u32, hi: u32) -> Result<u64, CostOverflow> {
+        narrow_cost(self.range(lo, hi).map(|(_, v)| v as u128).sum())
+    }
+
+    /// Total value stored at or below `key`, i.e. the depth an ascending sweep sees up to
+    /// that price. The descending counterpart is `range_sum(key, u32::MAX)`.
+    #[cfg(test)]
+    pub fn cumulative_depth_at(&self, key: u32) -> Result<u64, CostOverflow> {
+        self.range_sum(0, key)
     }
 
     // #[inline(always)]
@@ -505,14 +1112,16 @@ impl Glass {
     //     }
     // }
 
+    #[cfg(test)]
     #[inline(always)]
-    fn glass_compute_buy_cost(&self, target_shares: &mut u64, total_cost: &mut u64) {
+    fn glass_compute_buy_cost(&self, target_shares: &mut u64, total_cost: &mut u128) {
         if *target_shares == 0 || self.arena[self.root].mask == 0 {
             return;
         }
         self.glass_compute_buy_cost_recursive(self.root, 0, 0, target_shares, total_cost);
     }
 
+    #[cfg(test)]
     #[inline(always)]
     fn glass_compute_buy_cost_recursive(
         &self,
@@ -520,16 +1129,16 @@ impl Glass {
         depth: usize,
         key: u32,
         target_shares: &mut u64,
-        total_cost: &mut u64
+        total_cost: &mut u128
     ) {
         if *target_shares == 0 {
             return;
         }
 
-        if depth == NUM_LEVELS {
+        if depth == self.num_levels {
             if let Some(avail_shares) = self.arena[node_idx].value {
                 let buy = avail_shares.min(*target_shares);
-                *total_cost += (key as u64) * buy;
+                *total_cost += key as u128 * buy as u128;
                 *target_shares -= buy;
             }
             return;
@@ -538,31 +1147,69 @@ impl Glass {
         let mut remaining_mask = mask;
 
         while remaining_mask != 0 && *target_shares > 0 {
-            let child_idx = if self.has_bmi1 {
-                unsafe { _tzcnt_u64(remaining_mask) as usize }
-            } else {
-                remaining_mask.trailing_zeros() as usize
-            };
+            let child_idx = self.lowest_set_bit(remaining_mask);
             remaining_mask &= !(1u64 << child_idx);
-            let shift = KEY_BITS.saturating_sub((depth + 1) * BITS_PER_LEVEL);
+            let shift = self.level_shift(depth);
             let child_key = key | ((child_idx as u32) << shift);
             let child_node_idx = self.arena[node_idx].children[child_idx].unwrap();
             self.glass_compute_buy_cost_recursive(child_node_idx, depth + 1, child_key, target_shares, total_cost);
         }
     }
 
+    #[cfg(test)]
+    #[inline(always)]
+    fn glass_compute_sell_proceeds(&self, target_shares: &mut u64, total_proceeds: &mut u128) {
+        if *target_shares == 0 || self.arena[self.root].mask == 0 {
+            return;
+        }
+        self.glass_compute_sell_proceeds_recursive(self.root, 0, 0, target_shares, total_proceeds);
+    }
+
+    #[cfg(test)]
+    #[inline(always)]
+    fn glass_compute_sell_proceeds_recursive(
+        &self,
+        node_idx: usize,
+        depth: usize,
+        key: u32,
+        target_shares: &mut u64,
+        total_proceeds: &mut u128
+    ) {
+        if *target_shares == 0 {
+            return;
+        }
+
+        if depth == self.num_levels {
+            if let Some(avail_shares) = self.arena[node_idx].value {
+                let sell = avail_shares.min(*target_shares);
+                *total_proceeds += key as u128 * sell as u128;
+                *target_shares -= sell;
+            }
+            return;
+        }
+        let mask = self.arena[node_idx].mask;
+        let mut remaining_mask = mask;
+
+        // Highest child first
+        while remaining_mask != 0 && *target_shares > 0 {
+            let child_idx = self.highest_set_bit(remaining_mask);
+            remaining_mask &= !(1u64 << child_idx);
+            let shift = self.level_shift(depth);
+            let child_key = key | ((child_idx as u32) << shift);
+            let child_node_idx = self.arena[node_idx].children[child_idx].unwrap();
+            self.glass_compute_sell_proceeds_recursive(child_node_idx, depth + 1, child_key, target_shares, total_proceeds);
+        }
+    }
+
     #[inline(always)]
     fn glass_insert(&mut self, key: u32, value: u64) {
-        let partial = key >> LAST_BITS;
+        let partial = key >> self.last_bits;
         let mut level = 0usize;
         let mut node_idx = self.root;
 
         // --- Path caching for traversal speed ---
         if let Some(lk) = self.cached_last_key.get() {
-            let xor = key ^ lk;
-            let common_bits = xor.leading_zeros() as usize;
-            let lambda = common_bits / BITS_PER_LEVEL;
-            level = self.cached_d.get().min(lambda);
+            level = self.cached_d.get().min(self.shared_levels(key, lk));
             if level > 0 {
                 node_idx = unsafe { (*self.cached_path.get())[level] };
             }
@@ -570,9 +1217,9 @@ impl Glass {
 
         // --- Traverse and create nodes ---
         let current_key = key as u64;
-        for l in level..NUM_LEVELS {
-            let shift = KEY_BITS.saturating_sub((l + 1) * BITS_PER_LEVEL);
-            let bits_this_level = BITS_PER_LEVEL.min(KEY_BITS.saturating_sub(l * BITS_PER_LEVEL));
+        for l in level..self.num_levels {
+            let shift = self.level_shift(l);
+            let bits_this_level = self.level_bits(l);
             let child_mask = (1u64 << bits_this_level) - 1;
             let child_slot = ((current_key >> shift) & child_mask) as usize;
 
@@ -588,7 +1235,7 @@ impl Glass {
                 self.arena[node_idx].children[child_slot] = Some(new_idx);
                 self.arena[node_idx].mask |= 1u64 << child_slot;
             }
-            if l == NUM_LEVELS - 1 {
+            if l == self.num_levels - 1 {
                 unsafe {
                     (*self.cache.get()).entry(partial).or_insert(node_idx);
                 }
@@ -604,20 +1251,23 @@ impl Glass {
         self.arena[node_idx].count = 1; // A leaf with a value has a count of 1.
 
         // Increment counts of all ancestors on the path.
-        for l in 0..NUM_LEVELS {
+        for l in 0..self.num_levels {
             let ancestor_idx = unsafe { (*self.cached_path.get())[l] };
             self.arena[ancestor_idx].count += 1;
         }
 
         // --- Update global state ---
         self.cached_last_key.set(Some(key));
-        self.cached_d.set(NUM_LEVELS);
+        self.cached_d.set(self.num_levels);
 
-        if key < self.min_key.get() {
+        // A cleared extreme is found again lazily; unless the glass was empty the new key
+        // alone says nothing about it.
+        let only_key = self.glass_size() == 1;
+        if only_key || (self.min_leaf.get().is_some() && key < self.min_key.get()) {
             self.min_key.set(key);
             self.min_leaf.set(Some(node_idx));
         }
-        if key > self.max_key.get() {
+        if only_key || (self.max_leaf.get().is_some() && key > self.max_key.get()) {
             self.max_key.set(key);
             self.max_leaf.set(Some(node_idx));
         }
@@ -625,8 +1275,8 @@ impl Glass {
 
     #[inline(always)]
     fn glass_get(&self, key: u32) -> Option<u64> {
-        let partial = key >> LAST_BITS;
-        let last = (key & (LAST_MASK as u32)) as usize;
+        let partial = key >> self.last_bits;
+        let last = (key & ((1u32 << self.last_bits) - 1)) as usize;
         if let Some(preleaf_idx) = unsafe { (*self.cache.get()).get(&partial).copied() }
             && let Some(leaf_idx) = self.arena[preleaf_idx].children[last]
         {
@@ -637,23 +1287,20 @@ impl Glass {
         let mut node_idx = self.root;
 
         if let Some(lk) = self.cached_last_key.get() {
-            let xor = key ^ lk;
-            let common_bits = xor.leading_zeros() as usize;
-            let lambda = common_bits / BITS_PER_LEVEL;
-            level = self.cached_d.get().min(lambda);
+            level = self.cached_d.get().min(self.shared_levels(key, lk));
             if level > 0 {
                 node_idx = unsafe { (*self.cached_path.get())[level] };
             }
         }
 
         let current_key = key as u64;
-        for l in level..NUM_LEVELS {
-            let shift = KEY_BITS.saturating_sub((l + 1) * BITS_PER_LEVEL);
-            let bits_this_level = BITS_PER_LEVEL.min(KEY_BITS.saturating_sub(l * BITS_PER_LEVEL));
+        for l in level..self.num_levels {
+            let shift = self.level_shift(l);
+            let bits_this_level = self.level_bits(l);
             let child_mask = (1u64 << bits_this_level) - 1;
             let child_slot = ((current_key >> shift) & child_mask) as usize;
 
-            if l == NUM_LEVELS - 1 {
+            if l == self.num_levels - 1 {
                 unsafe {
                     (*self.cache.get()).entry(partial).or_insert(node_idx);
                 }
@@ -662,21 +1309,24 @@ impl Glass {
                 unsafe { (*self.cached_path.get())[l] = node_idx };
                 node_idx = child;
             } else {
+                // The path was overwritten past `level` for a key that is not there, so only
+                // the prefix shared with the cached key is still valid.
+                self.cached_d.set(self.cached_d.get().min(level));
                 return None;
             }
         }
         let val = self.arena[node_idx].value;
         if val.is_some() {
             self.cached_last_key.set(Some(key));
-            self.cached_d.set(NUM_LEVELS);
+            self.cached_d.set(self.num_levels);
         }
         val
     }
 
     #[inline(always)]
     fn glass_get_mut(&mut self, key: u32) -> Option<&mut u64> {
-        let partial = key >> LAST_BITS;
-        let last = (key & (LAST_MASK as u32)) as usize;
+        let partial = key >> self.last_bits;
+        let last = (key & ((1u32 << self.last_bits) - 1)) as usize;
         if let Some(preleaf_idx) = unsafe { (*self.cache.get()).get(&partial).copied() }
             && let Some(leaf_idx) = self.arena[preleaf_idx].children[last]
         {
@@ -687,23 +1337,20 @@ impl Glass {
         let mut node_idx = self.root;
 
         if let Some(lk) = self.cached_last_key.get() {
-            let xor = key ^ lk;
-            let common_bits = xor.leading_zeros() as usize;
-            let lambda = common_bits / BITS_PER_LEVEL;
-            level = self.cached_d.get().min(lambda);
+            level = self.cached_d.get().min(self.shared_levels(key, lk));
             if level > 0 {
                 node_idx = unsafe { (*self.cached_path.get())[level] };
             }
         }
 
         let current_key = key as u64;
-        for l in level..NUM_LEVELS {
-            let shift = KEY_BITS.saturating_sub((l + 1) * BITS_PER_LEVEL);
-            let bits_this_level = BITS_PER_LEVEL.min(KEY_BITS.saturating_sub(l * BITS_PER_LEVEL));
+        for l in level..self.num_levels {
+            let shift = self.level_shift(l);
+            let bits_this_level = self.level_bits(l);
             let child_mask = (1u64 << bits_this_level) - 1;
             let child_slot = ((current_key >> shift) & child_mask) as usize;
 
-            if l == NUM_LEVELS - 1 {
+            if l == self.num_levels - 1 {
                 unsafe {
                     (*self.cache.get()).entry(partial).or_insert(node_idx);
                 }
@@ -712,30 +1359,33 @@ impl Glass {
                 unsafe { (*self.cached_path.get())[l] = node_idx };
                 node_idx = child;
             } else {
+                // The path was overwritten past `level` for a key that is not there, so only
+                // the prefix shared with the cached key is still valid.
+                self.cached_d.set(self.cached_d.get().min(level));
                 return None;
             }
         }
         if self.arena[node_idx].value.is_some() {
             self.cached_last_key.set(Some(key));
-            self.cached_d.set(NUM_LEVELS);
+            self.cached_d.set(self.num_levels);
         }
         self.arena[node_idx].value.as_mut()
     }
 
     #[inline(always)]
     fn glass_remove(&mut self, key: u32) -> Option<u64> {
-        let partial = key >> LAST_BITS;
-        let mut path: [(usize, usize); NUM_LEVELS] = [(0, 0); NUM_LEVELS];
+        let partial = key >> self.last_bits;
+        let mut path: [(usize, usize); MAX_LEVELS] = [(0, 0); MAX_LEVELS];
         let mut path_len = 0;
         let mut node_idx = self.root;
         let current_key = key as u64;
 
-        for l in 0..NUM_LEVELS {
-            let shift = KEY_BITS.saturating_sub((l + 1) * BITS_PER_LEVEL);
-            let bits_this_level = BITS_PER_LEVEL.min(KEY_BITS.saturating_sub(l * BITS_PER_LEVEL));
+        for l in 0..self.num_levels {
+            let shift = self.level_shift(l);
+            let bits_this_level = self.level_bits(l);
             let child_mask = (1u64 << bits_this_level) - 1;
             let child_slot = ((current_key >> shift) & child_mask) as usize;
-            if l == NUM_LEVELS - 1 {
+            if l == self.num_levels - 1 {
                 unsafe {
                     (*self.cache.get()).entry(partial).or_insert(node_idx);
                 }
@@ -763,7 +1413,6 @@ impl Glass {
         // --- Prune empty branches ---
         let mut current = node_idx;
         let mut pruned_count = 0;
-        let is_last_key = self.cached_last_key.get() == Some(key);
 
         let mut i = path_len;
         while i > 0 {
@@ -785,10 +1434,10 @@ impl Glass {
             current = parent;
         }
 
-        if is_last_key {
-            self.cached_d
-                .set(self.cached_d.get().saturating_sub(pruned_count));
-        }
+        // Pruned nodes may sit on the cached path even when another key was cached last;
+        // only the depths above them stay usable.
+        self.cached_d
+            .set(self.cached_d.get().min(self.num_levels - pruned_count));
 
         if key == self.min_key.get() {
             self.min_key.set(u32::MAX);
@@ -802,6 +1451,7 @@ impl Glass {
     }
 
     /// Finds the key of the k-th smallest element in the glass.
+    #[cfg(test)]
     #[inline(always)]
     fn glass_find_kth_key(&self, mut k: usize) -> Option<u32> {
         if k >= self.glass_size() {
@@ -811,7 +1461,7 @@ impl Glass {
         let mut node_idx = self.root;
         let mut key = 0u32;
 
-        'level_loop: for depth in 0..NUM_LEVELS {
+        'level_loop: for depth in 0..self.num_levels {
             let node = &self.arena[node_idx];
             let mut start_search_idx = 0;
             loop {
@@ -822,7 +1472,7 @@ impl Glass {
 
                     if k < child_count {
                         // The k-th element is in this child's subtree. Descend.
-                        let shift = KEY_BITS.saturating_sub((depth + 1) * BITS_PER_LEVEL);
+                        let shift = self.level_shift(depth);
                         key |= (child_slot as u32) << shift;
                         node_idx = child_idx;
                         continue 'level_loop;
@@ -873,16 +1523,15 @@ impl Glass {
 
         let mut node_idx = self.root;
         let mut key = 0u32;
-        for depth in 0..NUM_LEVELS {
+        for depth in 0..self.num_levels {
             let mask = self.arena[node_idx].mask;
-            let bits_this_level =
-                BITS_PER_LEVEL.min(KEY_BITS.saturating_sub(depth * BITS_PER_LEVEL));
+            let bits_this_level = self.level_bits(depth);
             let idx = if is_min {
                 self.find_next_set_bit(mask, 0)?
             } else {
                 self.find_prev_set_bit(mask, 1 << bits_this_level)?
             };
-            let shift = KEY_BITS.saturating_sub((depth + 1) * BITS_PER_LEVEL);
+            let shift = self.level_shift(depth);
             key |= (idx as u32) << shift;
             node_idx = self.arena[node_idx].children[idx].unwrap();
         }
@@ -911,12 +1560,7 @@ impl Glass {
         if mask == 0 {
             return None;
         }
-        let pos = if self.has_bmi1 {
-            unsafe { _tzcnt_u64(mask) as usize }
-        } else {
-            mask.trailing_zeros() as usize
-        };
-        Some(start + pos)
+        Some(start + self.lowest_set_bit(mask))
     }
 
     #[inline(always)]
@@ -924,22 +1568,284 @@ impl Glass {
         if end == 0 {
             return None;
         }
+        mask = self.low_bits(mask, end);
+        if mask == 0 {
+            return None;
+        }
+        Some(self.highest_set_bit(mask))
+    }
+
+    // Bit-scan primitives. The intrinsics are only compiled on x86_64 and only used when
+    // the CPU reported them at construction; `mask` must be non-zero for the two scans.
+    #[inline(always)]
+    fn lowest_set_bit(&self, mask: u64) -> usize {
+        #[cfg(target_arch = "x86_64")]
+        if self.has_bmi1 {
+            return unsafe { _tzcnt_u64(mask) as usize };
+        }
+        mask.trailing_zeros() as usize
+    }
+
+    #[inline(always)]
+    fn highest_set_bit(&self, mask: u64) -> usize {
+        #[cfg(target_arch = "x86_64")]
+        if self.has_lzcnt {
+            return unsafe { (63 - _lzcnt_u64(mask)) as usize };
+        }
+        63 - mask.leading_zeros() as usize
+    }
+
+    /// `mask` with every bit at or above `end` cleared.
+    #[inline(always)]
+    fn low_bits(&self, mask: u64, end: usize) -> u64 {
+        #[cfg(target_arch = "x86_64")]
         if self.has_bmi2 {
-            unsafe {
-                mask = _bzhi_u64(mask, end as u32);
+            return unsafe { _bzhi_u64(mask, end as u32) };
+        }
+        if end < 64 { mask & ((1u64 << end) - 1) } else { mask }
+    }
+}
+
+impl Clone for Glass {
+    fn clone(&self) -> Self {
+        // The arena is copied as is, so every cached index stays valid in the clone.
+        Glass {
+            root: self.root,
+            cached_d: self.cached_d.clone(),
+            cached_last_key: self.cached_last_key.clone(),
+            max_size: self.max_size,
+            key_bits: self.key_bits,
+            num_levels: self.num_levels,
+            last_bits: self.last_bits,
+            key_limit: self.key_limit,
+            min_key: self.min_key.clone(),
+            preempt_min: self.preempt_min.clone(),
+            thres: self.thres.clone(),
+            max_key: self.max_key.clone(),
+            preempt_max: self.preempt_max.clone(),
+            preempt_bounds_valid: self.preempt_bounds_valid.clone(),
+            preempt_dirty: self.preempt_dirty.clone(),
+            has_bmi2: self.has_bmi2,
+            has_bmi1: self.has_bmi1,
+            has_lzcnt: self.has_lzcnt,
+            bit_scan: self.bit_scan,
+            _padding_flags: self._padding_flags,
+            min_leaf: self.min_leaf.clone(),
+            max_leaf: self.max_leaf.clone(),
+            cache: UnsafeCell::new(unsafe { (*self.cache.get()).clone() }),
+            preempt: UnsafeCell::new(unsafe { (*self.preempt.get()).clone() }),
+            cached_path: UnsafeCell::new(unsafe { *self.cached_path.get() }),
+            sorted_preempt_keys: UnsafeCell::new(unsafe { (*self.sorted_preempt_keys.get()).clone() }),
+            arena: self.arena.clone(),
+            free_list: self.free_list.clone(),
+        }
+    }
+}
+
+// Checkpointing. The format is little-endian throughout:
+//   magic, key_bits: u8, bit_scan: u8 (0 detect, 1 portable), hot_levels: u64, thres: u32,
+//   arena_len: u32, then per node mask: u64, has_value: u8, [value: u64], count: u32 and one
+//   u32 child index per set mask bit, lowest first,
+//   free_len: u32, free indices: u32 each,
+//   preempt_len: u32, then (key: u32, value: u64) pairs in key order.
+// Caches are not written; they refill on first use after a restore.
+impl Glass {
+    /// Copies out every level, in key order. This is O(levels) in time and memory on every
+    /// call, so deep books are better snapshotted once per batch of updates than per update.
+    pub fn snapshot(&self) -> GlassSnapshot {
+        GlassSnapshot { levels: self.iter().collect() }
+    }
+
+    /// Serializes the arena and the preempt map into a compact binary checkpoint.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let index = |idx: usize| u32::try_from(idx).expect("arena index fits in u32");
+        let preempt = unsafe { &*self.preempt.get() };
+        let mut out = Vec::with_capacity(32 + self.arena.len() * 24 + preempt.len() * 12);
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        out.push(self.key_bits as u8);
+        out.push(match self.bit_scan {
+            BitScan::Detect => 0,
+            BitScan::Portable => 1,
+        });
+        out.extend_from_slice(&(self.max_size as u64).to_le_bytes());
+        out.extend_from_slice(&self.thres.get().to_le_bytes());
+
+        out.extend_from_slice(&index(self.arena.len()).to_le_bytes());
+        for node in &self.arena {
+            out.extend_from_slice(&node.mask.to_le_bytes());
+            match node.value {
+                Some(value) => {
+                    out.push(1);
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                None => out.push(0),
+            }
+            out.extend_from_slice(&node.count.to_le_bytes());
+            let mut mask = node.mask;
+            while mask != 0 {
+                let slot = mask.trailing_zeros() as usize;
+                mask &= mask - 1;
+                let child = node.children[slot].expect("mask bits have children");
+                out.extend_from_slice(&index(child).to_le_bytes());
             }
-        } else if end < 64 {
-            mask &= (1u64 << end) - 1;
         }
-        if mask == 0 {
-            return None;
+
+        out.extend_from_slice(&index(self.free_list.len()).to_le_bytes());
+        for &idx in &self.free_list {
+            out.extend_from_slice(&index(idx).to_le_bytes());
         }
-        let pos = if self.has_lzcnt {
-            unsafe { (63 - _lzcnt_u64(mask)) as usize }
-        } else {
-            63 - mask.leading_zeros() as usize
+
+        let mut entries: Vec<(u32, u64)> = preempt.iter().map(|(&k, &v)| (k, v)).collect();
+        entries.sort_unstable_by_key(|&(k, _)| k);
+        out.extend_from_slice(&index(entries.len()).to_le_bytes());
+        for (key, value) in entries {
+            out.extend_from_slice(&key.to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out
+    }
+
+    /// Restores a glass written by [`Glass::to_bytes`], checking that the trie is well formed
+    /// so a damaged checkpoint is an error rather than a corrupt book.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Glass, SnapshotError> {
+        let mut reader = ByteReader { bytes };
+        if reader.take::<4>()? != *SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let key_bits = reader.u8()? as usize;
+        // Detection reruns on the restoring CPU; only the choice itself is restored
+        let bit_scan = match reader.u8()? {
+            0 => BitScan::Detect,
+            1 => BitScan::Portable,
+            _ => return Err(SnapshotError::Corrupt),
         };
-        Some(pos)
+        let hot_levels = usize::try_from(reader.u64()?).map_err(|_| SnapshotError::Corrupt)?;
+        if !(1..=MAX_KEY_BITS).contains(&key_bits) || hot_levels == 0 {
+            return Err(SnapshotError::Corrupt);
+        }
+        let thres = reader.u32()?;
+
+        let arena_len = reader.u32()? as usize;
+        if arena_len == 0 {
+            return Err(SnapshotError::Corrupt);
+        }
+        // Each node takes at least 13 bytes, which bounds the allocation below
+        if arena_len > reader.bytes.len() / 13 {
+            return Err(SnapshotError::Truncated);
+        }
+        let mut arena = Vec::with_capacity(arena_len);
+        for _ in 0..arena_len {
+            let mut node = GlassNode::new();
+            node.mask = reader.u64()?;
+            node.value = match reader.u8()? {
+                0 => None,
+                1 => Some(reader.u64()?),
+                _ => return Err(SnapshotError::Corrupt),
+            };
+            node.count = reader.u32()?;
+            let mut mask = node.mask;
+            while mask != 0 {
+                let slot = mask.trailing_zeros() as usize;
+                mask &= mask - 1;
+                let child = reader.u32()? as usize;
+                if child == 0 || child >= arena_len {
+                    return Err(SnapshotError::Corrupt);
+                }
+                node.children[slot] = Some(child);
+            }
+            arena.push(node);
+        }
+
+        let free_len = reader.u32()? as usize;
+        let mut free_list = Vec::with_capacity(free_len.min(arena_len));
+        for _ in 0..free_len {
+            free_list.push(reader.u32()? as usize);
+        }
+
+        let preempt_len = reader.u32()? as usize;
+        let mut preempt = HashMap::with_capacity(preempt_len.min(reader.bytes.len() / 12));
+        let mut last_key = None;
+        for _ in 0..preempt_len {
+            let key = reader.u32()?;
+            let value = reader.u64()?;
+            if value == 0 || key < thres || last_key.is_some_and(|last| key <= last) {
+                return Err(SnapshotError::Corrupt);
+            }
+            last_key = Some(key);
+            preempt.insert(key, value);
+        }
+        if !reader.bytes.is_empty() {
+            return Err(SnapshotError::Corrupt);
+        }
+
+        let mut glass = Glass::with_config(GlassConfig {
+            hot_levels,
+            arena_capacity: arena_len,
+            key_bits,
+            bit_scan,
+        });
+        if thres > glass.key_limit {
+            return Err(SnapshotError::Corrupt);
+        }
+        glass.arena = arena;
+        glass.free_list = free_list;
+        glass.check_restored_trie(thres)?;
+        if glass.glass_size() > hot_levels {
+            return Err(SnapshotError::Corrupt);
+        }
+        glass.thres.set(thres);
+        glass.preempt = UnsafeCell::new(preempt);
+        glass.preempt_bounds_valid.set(false);
+        glass.preempt_dirty.set(true);
+        Ok(glass)
+    }
+
+    // Every node must be reached exactly once from the root or sit in the free list, with
+    // leaves only at the bottom, counts matching, no empty branches and all keys below `thres`.
+    fn check_restored_trie(&self, thres: u32) -> Result<(), SnapshotError> {
+        let mut seen = vec![false; self.arena.len()];
+        let mut stack = vec![(self.root, 0usize, 0u32)];
+        while let Some((idx, depth, key)) = stack.pop() {
+            if std::mem::replace(&mut seen[idx], true) {
+                return Err(SnapshotError::Corrupt);
+            }
+            let node = &self.arena[idx];
+            if depth == self.num_levels {
+                if node.mask != 0 || node.value.is_none() || node.count != 1 || key >= thres {
+                    return Err(SnapshotError::Corrupt);
+                }
+                continue;
+            }
+            let bits = self.level_bits(depth);
+            if node.value.is_some()
+                || (bits < BITS_PER_LEVEL && node.mask >> (1u32 << bits) != 0)
+                || (idx != self.root && node.mask == 0)
+            {
+                return Err(SnapshotError::Corrupt);
+            }
+            let mut count = 0u64;
+            let mut mask = node.mask;
+            while mask != 0 {
+                let slot = mask.trailing_zeros() as usize;
+                mask &= mask - 1;
+                let child = node.children[slot].unwrap();
+                count += self.arena[child].count as u64;
+                stack.push((child, depth + 1, key | ((slot as u32) << self.level_shift(depth))));
+            }
+            if count != node.count as u64 {
+                return Err(SnapshotError::Corrupt);
+            }
+        }
+        for &idx in &self.free_list {
+            if idx >= self.arena.len() || std::mem::replace(&mut seen[idx], true) {
+                return Err(SnapshotError::Corrupt);
+            }
+        }
+        // Every node is either reachable or free; anything else is an orphan
+        if seen.contains(&false) {
+            return Err(SnapshotError::Corrupt);
+        }
+        Ok(())
     }
 }
 
@@ -1060,29 +1966,382 @@ mod tests {
     #[test]
     fn test_restructure() {
         let mut glass = Glass::new();
-        // Fill beyond MAX_SIZE to trigger preempt
-        for i in 0..(MAX_SIZE + 10) {
+        // Fill beyond DEFAULT_MAX_SIZE to trigger preempt
+        for i in 0..(DEFAULT_MAX_SIZE + 10) {
             glass.insert(i as u32, 1);
         }
-        assert_eq!(glass.glass_size(), MAX_SIZE);
+        assert_eq!(glass.glass_size(), DEFAULT_MAX_SIZE);
         assert!(!unsafe { &*glass.preempt.get() }.is_empty());
         // Restructure should move some from preempt to trie
         glass.remove(0); // Make space in the trie
-        assert_eq!(glass.glass_size(), MAX_SIZE - 1);
+        assert_eq!(glass.glass_size(), DEFAULT_MAX_SIZE - 1);
         let preempt_size_before = unsafe { &*glass.preempt.get() }.len();
         glass.restructure(); // Should pull one item from preempt
-        assert_eq!(glass.glass_size(), MAX_SIZE);
+        assert_eq!(glass.glass_size(), DEFAULT_MAX_SIZE);
         let preempt_size_after = unsafe { &*glass.preempt.get() }.len();
         assert_eq!(preempt_size_after, preempt_size_before - 1);
     }
 
+    #[test]
+    fn test_with_capacity_spills_and_restructures() {
+        let mut glass = Glass::with_capacity(4, 8);
+        assert_eq!(glass.hot_levels(), 4);
+        // Highest keys first: each lower one evicts the largest hot key into the preempt map
+        for key in (0..10u32).rev() {
+            glass.insert(key, key as u64 + 1);
+        }
+        assert_eq!(glass.glass_size(), 4);
+        assert!((0..4).all(|key| glass.is_hot(key)));
+        assert!(!(4..10).any(|key| glass.is_hot(key)));
+        assert_eq!(unsafe { &*glass.preempt.get() }.len(), 6);
+
+        // Freeing a hot slot pulls the lowest preempted key back into the trie
+        glass.remove(1);
+        glass.restructure();
+        assert_eq!(glass.glass_size(), 4);
+        assert!(glass.is_hot(4));
+        assert_eq!(unsafe { &*glass.preempt.get() }.len(), 5);
+        assert!(glass.iter().map(|(key, _)| key).eq([0, 2, 3, 4, 5, 6, 7, 8, 9]));
+        assert_eq!(glass.max(), Some((9, 10)));
+    }
+
+    #[test]
+    fn test_full_glass_keeps_keys_reachable() {
+        let mut glass = Glass::new();
+        for i in 0..(DEFAULT_MAX_SIZE + 10) {
+            glass.insert(i as u32, 1);
+        }
+        for i in 0..(DEFAULT_MAX_SIZE + 10) {
+            assert_eq!(glass.get(i as u32), Some(1));
+        }
+        // A key pulled in from the preempt map must still be found afterwards
+        glass.remove(0);
+        glass.restructure();
+        assert_eq!(glass.get(DEFAULT_MAX_SIZE as u32), Some(1));
+        assert!(glass.update_value(DEFAULT_MAX_SIZE as u32, |v| *v = 2));
+        assert_eq!(glass.get(DEFAULT_MAX_SIZE as u32), Some(2));
+    }
+
+    #[test]
+    fn test_bit_scans_agree() {
+        let detected = Glass::new();
+        let portable = Glass::with_bit_scan(BitScan::Portable);
+        assert!(!portable.has_bmi1 && !portable.has_bmi2 && !portable.has_lzcnt);
+        let masks = [1u64, 1 << 63, 0b1010_0000, u64::MAX, 0x8000_0000_0000_0001, 0x00f0_0000_0000_0000];
+        for glass in [&detected, &portable] {
+            for mask in masks {
+                assert_eq!(glass.lowest_set_bit(mask), mask.trailing_zeros() as usize);
+                assert_eq!(glass.highest_set_bit(mask), 63 - mask.leading_zeros() as usize);
+                for end in [0, 1, 5, 63, 64] {
+                    let expected = if end < 64 { mask & ((1u64 << end) - 1) } else { mask };
+                    assert_eq!(glass.low_bits(mask, end), expected);
+                }
+            }
+            assert_eq!(glass.find_next_set_bit(0b1010_0000, 6), Some(7));
+            assert_eq!(glass.find_prev_set_bit(0b1010_0000, 7), Some(5));
+            assert_eq!(glass.find_prev_set_bit(0b1010_0000, 5), None);
+        }
+
+        // Same book through both paths gives the same answers
+        let mut books = [Glass::new(), Glass::with_bit_scan(BitScan::Portable)];
+        for book in &mut books {
+            for i in 0..300u32 {
+                book.insert(i * 37 % 1_000 + 5_000, (i % 7 + 1) as u64);
+            }
+        }
+        let [a, b] = &mut books;
+        assert!(a.iter().eq(b.iter()));
+        assert!(a.iter_rev().eq(b.iter_rev()));
+        assert_eq!(a.compute_buy_cost(500), b.compute_buy_cost(500));
+        assert_eq!(a.compute_sell_proceeds(500), b.compute_sell_proceeds(500));
+        assert_eq!(a.buy_shares(700), b.buy_shares(700));
+        assert_eq!(a.sell_shares(300), b.sell_shares(300));
+        assert_eq!((a.min(), a.max()), (b.min(), b.max()));
+    }
+
+    #[test]
+    fn test_iter_merges_glass_and_preempt() {
+        let mut glass = Glass::new();
+        // Every third key, enough of them to spill into the preempt map
+        let keys: Vec<u32> = (0..(DEFAULT_MAX_SIZE as u32 + 50)).map(|i| i * 3 + 1).collect();
+        for &k in keys.iter().rev() {
+            glass.insert(k, k as u64 * 10);
+        }
+        assert!(glass.glass_size() < keys.len());
+        let expected: Vec<(u32, u64)> = keys.iter().map(|&k| (k, k as u64 * 10)).collect();
+        assert_eq!(glass.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(
+            glass.iter_rev().collect::<Vec<_>>(),
+            expected.iter().rev().copied().collect::<Vec<_>>()
+        );
+
+        // Starting points on, between and past stored keys, inside the glass and the preempt map
+        let last = *keys.last().unwrap();
+        for from in [0, 1, 2, 4, 3 * 2000 + 2, last - 3, last - 1, last, last + 1] {
+            let asc: Vec<u32> = glass.iter_from(from).map(|(k, _)| k).collect();
+            let desc: Vec<u32> = glass.iter_rev_from(from).map(|(k, _)| k).collect();
+            assert_eq!(asc, keys.iter().copied().filter(|&k| k >= from).collect::<Vec<_>>());
+            assert_eq!(desc, keys.iter().rev().copied().filter(|&k| k <= from).collect::<Vec<_>>());
+        }
+        assert_eq!(Glass::new().iter().next(), None);
+    }
+
+    #[test]
+    fn test_range_sum_and_cumulative_depth() {
+        let mut glass = Glass::new();
+        glass.insert(10, 5);
+        glass.insert(20, 7);
+        glass.insert(30, 11);
+        glass.insert(4_000_000_000, 13);
+        assert_eq!(glass.range(15, 30).collect::<Vec<_>>(), vec![(20, 7), (30, 11)]);
+        assert_eq!(glass.range_sum(10, 20), Ok(12));
+        assert_eq!(glass.range_sum(11, 19), Ok(0));
+        assert_eq!(glass.range_sum(0, u32::MAX), Ok(36));
+        assert_eq!(glass.cumulative_depth_at(9), Ok(0));
+        assert_eq!(glass.cumulative_depth_at(25), Ok(12));
+        assert_eq!(glass.cumulative_depth_at(u32::MAX), Ok(36));
+        // Reported with the exact total instead of saturating
+        glass.insert(25, u64::MAX);
+        assert_eq!(glass.range_sum(20, 25), Err(CostOverflow(7 + u64::MAX as u128)));
+        assert_eq!(glass.cumulative_depth_at(u32::MAX), Err(CostOverflow(36 + u64::MAX as u128)));
+        assert_eq!(glass.snapshot().range_sum(0, 25), Err(CostOverflow(12 + u64::MAX as u128)));
+        assert_eq!(glass.range_sum(25, 25), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn test_kth_across_preempt() {
+        let mut glass = Glass::new();
+        let n = DEFAULT_MAX_SIZE as u32 + 20;
+        let level = |i: u32| (i * 2, i as u64 + 1);
+        for i in 0..n {
+            let (k, v) = level(i);
+            glass.insert(k, v);
+        }
+        assert_eq!(glass.kth(0), Some(level(0)));
+        assert_eq!(glass.kth(DEFAULT_MAX_SIZE - 1), Some(level(DEFAULT_MAX_SIZE as u32 - 1)));
+        assert_eq!(glass.kth(n as usize - 1), Some(level(n - 1)));
+        assert_eq!(glass.kth(n as usize), None);
+
+        // Repeated removals from the preempt map keep its sorted order usable
+        assert_eq!(glass.remove_by_index(n as usize - 1), Some(level(n - 1)));
+        assert_eq!(glass.remove_by_index(DEFAULT_MAX_SIZE), Some(level(DEFAULT_MAX_SIZE as u32)));
+        glass.insert(DEFAULT_MAX_SIZE as u32 * 2 + 1, 99);
+        assert_eq!(glass.kth(DEFAULT_MAX_SIZE), Some((DEFAULT_MAX_SIZE as u32 * 2 + 1, 99)));
+        assert_eq!(glass.kth(DEFAULT_MAX_SIZE + 1), Some(level(DEFAULT_MAX_SIZE as u32 + 1)));
+        assert_eq!(glass.iter().count(), n as usize - 1);
+    }
+
+    #[test]
+    fn test_matches_btree_under_churn() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        use std::collections::BTreeMap;
+
+        // Book-like churn around a drifting centre: misses, removals that prune shared
+        // paths and extremes leaving, with and without spilling into the preempt map.
+        let cases = [(0u64, 200i64), (1, 6_000)];
+        for (bit_scan, (seed, spread)) in [BitScan::Detect, BitScan::Portable]
+            .into_iter()
+            .flat_map(|bit_scan| cases.map(|case| (bit_scan, case)))
+        {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut glass = Glass::with_bit_scan(bit_scan);
+            let mut expected = BTreeMap::new();
+            let mut centre: i64 = 1 << 31;
+            for _ in 0..20_000 {
+                centre += rng.random_range(-2..=2);
+                let key = (centre + rng.random_range(-spread..spread)) as u32;
+                let value = if rng.random_bool(0.3) { 0 } else { rng.random_range(1..100) };
+                glass.insert(key, value);
+                if value == 0 {
+                    expected.remove(&key);
+                } else {
+                    expected.insert(key, value);
+                }
+                let probe = (centre + rng.random_range(-2 * spread..2 * spread)) as u32;
+                assert_eq!(glass.get(probe), expected.get(&probe).copied());
+                assert_eq!(glass.min(), expected.first_key_value().map(|(&k, &v)| (k, v)));
+                assert_eq!(glass.max(), expected.last_key_value().map(|(&k, &v)| (k, v)));
+            }
+            for (&key, &value) in &expected {
+                assert_eq!(glass.get(key), Some(value));
+            }
+            assert!(glass.iter().eq(expected.iter().map(|(&k, &v)| (k, v))));
+            assert!(glass.iter_rev().eq(expected.iter().rev().map(|(&k, &v)| (k, v))));
+        }
+    }
+
+    #[test]
+    fn test_configured_capacity_and_key_width() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        use std::collections::BTreeMap;
+
+        // Tiny hot sets force constant eviction and restructuring; narrow widths put the
+        // churn right on the key limit so keys cross between glass and preempt.
+        for (hot_levels, key_bits, centre) in [(8, 32, 1i64 << 31), (64, 12, 1 << 12), (3, 7, 1 << 7)] {
+            let mut rng = StdRng::seed_from_u64(hot_levels as u64);
+            let mut glass = Glass::with_config(GlassConfig {
+                hot_levels,
+                arena_capacity: 16,
+                key_bits,
+                ..GlassConfig::default()
+            });
+            assert_eq!(glass.hot_levels(), hot_levels);
+            assert_eq!(glass.key_bits(), key_bits);
+            let mut expected = BTreeMap::new();
+            for _ in 0..5_000 {
+                let key = (centre + rng.random_range(-100..100)) as u32;
+                let value = if rng.random_bool(0.3) { 0 } else { rng.random_range(1..100) };
+                glass.insert(key, value);
+                if value == 0 {
+                    expected.remove(&key);
+                } else {
+                    expected.insert(key, value);
+                }
+                assert!(glass.glass_size() <= hot_levels);
+                assert_eq!(glass.min(), expected.first_key_value().map(|(&k, &v)| (k, v)));
+                assert_eq!(glass.max(), expected.last_key_value().map(|(&k, &v)| (k, v)));
+            }
+            for (&key, &value) in &expected {
+                assert_eq!(glass.get(key), Some(value));
+            }
+            assert!(glass.iter().eq(expected.iter().map(|(&k, &v)| (k, v))));
+            assert!(glass.iter_rev().eq(expected.iter().rev().map(|(&k, &v)| (k, v))));
+            let from = centre as u32 + 10;
+            assert!(glass.iter_rev_from(from).eq(expected.range(..=from).rev().map(|(&k, &v)| (k, v))));
+            assert_eq!(glass.range_sum(0, u32::MAX), Ok(expected.values().sum::<u64>()));
+
+            let total: u64 = expected.values().sum();
+            let cost: u64 = expected.iter().map(|(&k, &v)| k as u64 * v).sum();
+            assert_eq!(glass.compute_buy_cost(total), Ok(cost));
+            assert_eq!(glass.buy_shares(total), Ok(cost));
+            assert_eq!(glass.min(), None);
+        }
+    }
+
+    #[test]
+    fn test_to_bytes_round_trip() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut glass = Glass::with_config(GlassConfig { hot_levels: 64, key_bits: 20, ..GlassConfig::default() });
+        for _ in 0..3_000 {
+            let key = rng.random_range(0..(1 << 20) + 500);
+            let value = if rng.random_bool(0.3) { 0 } else { rng.random_range(1..100) };
+            glass.insert(key, value);
+        }
+        assert!(!unsafe { &*glass.preempt.get() }.is_empty());
+        assert!(!glass.free_list.is_empty());
+
+        let bytes = glass.to_bytes();
+        let mut restored = Glass::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.to_bytes(), bytes);
+        assert_eq!(restored.hot_levels(), 64);
+        assert_eq!(restored.key_bits(), 20);
+        assert!(restored.iter().eq(glass.iter()));
+
+        // Both keep behaving the same after the restore, clones included
+        let mut cloned = restored.clone();
+        for _ in 0..2_000 {
+            let key = rng.random_range(0..(1 << 20) + 500);
+            let value = if rng.random_bool(0.3) { 0 } else { rng.random_range(1..100) };
+            glass.insert(key, value);
+            restored.insert(key, value);
+            cloned.insert(key, value);
+            assert_eq!(restored.min(), glass.min());
+            assert_eq!(restored.max(), glass.max());
+        }
+        assert!(cloned.iter().eq(glass.iter()));
+        assert_eq!(restored.buy_shares(500), glass.buy_shares(500));
+        assert!(restored.iter().eq(glass.iter()));
+        // The clone is independent of the book it came from
+        assert!(cloned.iter().count() > glass.iter().count());
+    }
+
+    #[test]
+    fn test_from_bytes_keeps_bit_scan() {
+        for bit_scan in [BitScan::Detect, BitScan::Portable] {
+            let mut glass = Glass::with_bit_scan(bit_scan);
+            for key in [3, 900, 70_000] {
+                glass.insert(key, 1);
+            }
+            let restored = Glass::from_bytes(&glass.to_bytes()).unwrap();
+            assert_eq!(restored.bit_scan(), bit_scan);
+            assert_eq!(
+                (restored.has_bmi1, restored.has_bmi2, restored.has_lzcnt),
+                (glass.has_bmi1, glass.has_bmi2, glass.has_lzcnt)
+            );
+            assert!(restored.iter().eq(glass.iter()));
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_damage() {
+        let mut glass = Glass::new();
+        for key in [5, 70, 4_000] {
+            glass.insert(key, key as u64);
+        }
+        let bytes = glass.to_bytes();
+        assert_eq!(Glass::from_bytes(&bytes[..bytes.len() - 1]).err(), Some(SnapshotError::Truncated));
+        assert_eq!(Glass::from_bytes(b"nope").err(), Some(SnapshotError::BadMagic));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(Glass::from_bytes(&trailing).err(), Some(SnapshotError::Corrupt));
+        let mut bad_scan = bytes.clone();
+        bad_scan[5] = 2;
+        assert_eq!(Glass::from_bytes(&bad_scan).err(), Some(SnapshotError::Corrupt));
+        // Root node count, right after magic, key bits, bit scan, hot levels, thres, arena
+        // length, mask and the value flag
+        let count_at = 4 + 1 + 1 + 8 + 4 + 4 + 8 + 1;
+        let mut miscounted = bytes.clone();
+        miscounted[count_at] += 1;
+        assert_eq!(Glass::from_bytes(&miscounted).err(), Some(SnapshotError::Corrupt));
+        // First child index of the root pointing back at the root
+        let mut cycle = bytes;
+        cycle[count_at + 4..count_at + 8].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(Glass::from_bytes(&cycle).err(), Some(SnapshotError::Corrupt));
+        // Nodes freed by a removal dropped from the free list, leaving them unaccounted for
+        glass.remove(4_000);
+        let bytes = glass.to_bytes();
+        assert!(!glass.free_list.is_empty());
+        let free_at = bytes.len() - 4 - 4 - 4 * glass.free_list.len();
+        let mut orphaned = bytes[..free_at].to_vec();
+        orphaned.extend_from_slice(&0u32.to_le_bytes());
+        orphaned.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(Glass::from_bytes(&orphaned).err(), Some(SnapshotError::Corrupt));
+        assert!(Glass::from_bytes(&bytes).is_ok());
+    }
+
+    #[test]
+    fn test_read_only_snapshot() {
+        let mut glass = Glass::new();
+        for i in 0..(DEFAULT_MAX_SIZE as u32 + 10) {
+            glass.insert(i * 2, 1);
+        }
+        let snapshot = glass.snapshot();
+        glass.insert(1, 5);
+        assert_eq!(snapshot.len(), DEFAULT_MAX_SIZE + 10);
+        // The key inserted after the snapshot is not in it
+        assert!(snapshot.iter().all(|(key, _)| key != 1));
+        assert_eq!(snapshot.range_sum(4, 4), Ok(1));
+        assert_eq!(snapshot.min(), Some((0, 1)));
+        assert_eq!(snapshot.max(), Some(((DEFAULT_MAX_SIZE as u32 + 9) * 2, 1)));
+        assert_eq!(snapshot.range_sum(3, 9), Ok(3));
+        let limit = SweepLimit { shares: Some(3), ..Default::default() };
+        assert_eq!(snapshot.sweep_buy(limit).notional, 2 + 4);
+        glass.remove(1);
+        assert_eq!(snapshot.sweep_sell(limit), glass.sweep_sell(limit));
+    }
+
     #[test]
     fn test_buy_shares() {
         let mut glass = Glass::new();
         glass.insert(10, 500);
         glass.insert(20, 600);
         let cost = glass.buy_shares(700);
-        assert_eq!(cost, (10 * 500) + (20 * 200));
+        assert_eq!(cost, Ok((10 * 500) + (20 * 200)));
         assert_eq!(glass.get(10), None); // Removed since 0
         assert_eq!(glass.get(20), Some(400));
     }
@@ -1095,9 +2354,142 @@ mod tests {
         glass.insert(30, 700);
         glass.insert(40, 800);
         let cost = glass.compute_buy_cost(1000);
-        assert_eq!(cost, (10 * 500) + (20 * 500)); // Partial
+        assert_eq!(cost, Ok((10 * 500) + (20 * 500))); // Partial
         let full_cost = glass.compute_buy_cost(2600);
-        assert_eq!(full_cost, (10 * 500) + (20 * 600) + (30 * 700) + (40 * 800));
+        assert_eq!(full_cost, Ok((10 * 500) + (20 * 600) + (30 * 700) + (40 * 800)));
+    }
+
+    #[test]
+    fn test_sell_shares() {
+        let mut glass = Glass::new();
+        glass.insert(10, 500);
+        glass.insert(20, 600);
+        let proceeds = glass.sell_shares(700);
+        assert_eq!(proceeds, Ok((20 * 600) + (10 * 100)));
+        assert_eq!(glass.get(20), None); // Removed since 0
+        assert_eq!(glass.get(10), Some(400));
+        assert_eq!(glass.max(), Some((10, 400)));
+    }
+
+    #[test]
+    fn test_compute_sell_proceeds() {
+        let mut glass = Glass::new();
+        glass.insert(10, 500);
+        glass.insert(20, 600);
+        glass.insert(30, 700);
+        glass.insert(40, 800);
+        let proceeds = glass.compute_sell_proceeds(1000);
+        assert_eq!(proceeds, Ok((40 * 800) + (30 * 200))); // Partial
+        let full_proceeds = glass.compute_sell_proceeds(2600);
+        assert_eq!(full_proceeds, Ok((10 * 500) + (20 * 600) + (30 * 700) + (40 * 800)));
+        // Nothing was consumed
+        assert_eq!(glass.get(40), Some(800));
+    }
+
+    #[test]
+    fn test_cost_overflow_is_reported() {
+        let mut glass = Glass::new();
+        let top = u32::MAX - 1;
+        glass.insert(top, u64::MAX);
+        glass.insert(7, 3);
+        assert_eq!(glass.compute_buy_cost(13), Ok(7 * 3 + top as u64 * 10));
+        let all = u64::MAX / 2;
+        let exact = 7 * 3 + top as u128 * (all - 3) as u128;
+        assert_eq!(glass.compute_buy_cost(all), Err(CostOverflow(exact)));
+        assert_eq!(glass.sweep_buy(SweepLimit { shares: Some(all), ..Default::default() }).notional, exact);
+        // Two full levels hold more shares than a u64 counts
+        glass.insert(8, u64::MAX);
+        let everything = glass.sweep_buy(SweepLimit::default());
+        assert_eq!(everything.filled, 3 + 2 * u64::MAX as u128);
+        assert_eq!(everything.last_price, Some(top));
+
+        let proceeds = top as u128 * all as u128;
+        assert_eq!(glass.compute_sell_proceeds(all), Err(CostOverflow(proceeds)));
+        // The shares are sold regardless
+        assert_eq!(glass.sell_shares(all), Err(CostOverflow(proceeds)));
+        assert_eq!(glass.get(top), Some(u64::MAX - all));
+        assert_eq!(glass.buy_shares(3), Ok(21));
+    }
+
+    #[test]
+    fn test_sell_through_preempt() {
+        let mut glass = Glass::new();
+        for i in 0..(DEFAULT_MAX_SIZE + 10) {
+            glass.insert(i as u32, 1);
+        }
+        assert!(!unsafe { &*glass.preempt.get() }.is_empty());
+
+        // The top 12 keys span all 10 preempted keys and the 2 highest glass keys
+        let top: u64 = ((DEFAULT_MAX_SIZE - 2)..(DEFAULT_MAX_SIZE + 10)).map(|k| k as u64).sum();
+        assert_eq!(glass.compute_sell_proceeds(12), Ok(top));
+        assert_eq!(glass.sell_shares(12), Ok(top));
+        assert!(unsafe { &*glass.preempt.get() }.is_empty());
+        assert_eq!(glass.max(), Some(((DEFAULT_MAX_SIZE - 3) as u32, 1)));
+        assert_eq!(glass.min(), Some((0, 1)));
+    }
+
+    #[test]
+    fn test_glass_compute_sell_proceeds() {
+        let mut glass = Glass::new();
+        glass.glass_insert(10, 500);
+        glass.glass_insert(20, 600);
+        let mut target = 700u64;
+        let mut proceeds = 0u128;
+        glass.glass_compute_sell_proceeds(&mut target, &mut proceeds);
+        assert_eq!(proceeds, (20 * 600) + (10 * 100));
+        assert_eq!(target, 0);
+    }
+
+    #[test]
+    fn test_sweep_buy_limits() {
+        let mut glass = Glass::new();
+        glass.insert(10, 500);
+        glass.insert(20, 600);
+        glass.insert(30, 700);
+        assert_eq!(
+            glass.buy_quantity_within(20),
+            Sweep { filled: 1100, notional: (10 * 500) + (20 * 600), last_price: Some(20) }
+        );
+        assert_eq!(glass.buy_quantity_within(5), Sweep::default());
+        assert_eq!(glass.buy_vwap(1000), Some(15.0));
+        assert_eq!(glass.buy_price_reached(1000), Some(20));
+        assert_eq!(glass.buy_price_reached(0), None);
+        // 5000 takes the first level, the remaining 2005 affords 100 shares at 20
+        assert_eq!(
+            glass.buy_quantity_for_notional(7005),
+            Sweep { filled: 600, notional: 7000, last_price: Some(20) }
+        );
+        let capped = glass.sweep_buy(SweepLimit {
+            shares: Some(5000),
+            price: Some(20),
+            ..Default::default()
+        });
+        assert_eq!(capped.filled, 1100);
+        // Queries never consume the book
+        assert_eq!(glass.get(10), Some(500));
+        assert_eq!(glass.glass_size(), 3);
+    }
+
+    #[test]
+    fn test_sweep_sell_through_preempt() {
+        let mut glass = Glass::new();
+        for i in 0..(DEFAULT_MAX_SIZE + 10) {
+            glass.insert(i as u32, 2);
+        }
+        let top = (DEFAULT_MAX_SIZE + 9) as u32;
+        // The 10 preempted keys hold 20 shares, the other 5 come from the glass
+        assert_eq!(glass.sell_price_reached(25), Some(top - 12));
+        let within = glass.sell_quantity_within(top - 1);
+        assert_eq!(within.filled, 4);
+        assert_eq!(within.notional, 2 * (top as u128 + (top - 1) as u128));
+        assert_eq!(glass.sell_vwap(4), Some((top as f64 + (top - 1) as f64) / 2.0));
+        assert_eq!(
+            glass.sell_quantity_for_notional(top as u64 * 3),
+            Sweep { filled: 3, notional: 3 * top as u128 - 1, last_price: Some(top - 1) }
+        );
+        // Buying walks from the glass into the preempt map
+        assert_eq!(glass.buy_quantity_within(top).filled, 2 * (DEFAULT_MAX_SIZE as u128 + 10));
+        assert_eq!(glass.buy_price_reached(2 * DEFAULT_MAX_SIZE as u64 + 1), Some(DEFAULT_MAX_SIZE as u32));
     }
 
     #[test]
@@ -1172,7 +2564,7 @@ mod tests {
         glass.glass_insert(10, 500);
         glass.glass_insert(20, 600);
         let mut target = 700u64;
-        let mut cost = 0u64;
+        let mut cost = 0u128;
         glass.glass_compute_buy_cost(&mut target, &mut cost);
         assert_eq!(cost, (10 * 500) + (20 * 200));
         assert_eq!(target, 0);
@@ -0,0 +1,246 @@
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use std::f64::consts::PI;
+
+use crate::clustering::{ClusterFit, ClusterParams, Clusterer};
+use crate::kmeans::{self, FEATURE_COUNT, Point};
+use crate::model::OrderSample;
+
+// Keeps a component on repeated identical sizes from collapsing to a spike (log units)
+const MIN_VARIANCE: f64 = 1e-4;
+// EM stops once an iteration improves the log-likelihood by less than this
+const TOLERANCE: f64 = 1e-6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Component {
+    weight: f64,
+    mean: f64,
+    variance: f64,
+}
+
+impl Component {
+    fn log_density(&self, x: f64) -> f64 {
+        let d = x - self.mean;
+        self.weight.ln() - 0.5 * (2.0 * PI * self.variance).ln() - d * d / (2.0 * self.variance)
+    }
+}
+
+fn log_sum_exp(values: &[f64]) -> f64 {
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + values.iter().map(|v| (v - max).exp()).sum::<f64>().ln()
+}
+
+fn mean_and_variance(xs: &[f64]) -> (f64, f64) {
+    let mean = xs.iter().sum::<f64>() / xs.len() as f64;
+    let variance = xs.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / xs.len() as f64;
+    (mean, variance)
+}
+
+// Runs EM from `components` and returns the fitted mixture, sorted by mean, with its
+// log-likelihood.
+fn em(xs: &[f64], mut components: Vec<Component>, max_iter: usize) -> (Vec<Component>, f64) {
+    let k = components.len();
+    let mut resp = vec![0.0; xs.len() * k];
+    let mut log_p = vec![0.0; k];
+    let mut log_likelihood = f64::NEG_INFINITY;
+    for _ in 0..max_iter.max(1) {
+        // E-step: responsibilities, and the likelihood of the current parameters
+        let mut ll = 0.0;
+        for (i, &x) in xs.iter().enumerate() {
+            for (lp, c) in log_p.iter_mut().zip(&components) {
+                *lp = c.log_density(x);
+            }
+            let total = log_sum_exp(&log_p);
+            ll += total;
+            for j in 0..k {
+                resp[i * k + j] = (log_p[j] - total).exp();
+            }
+        }
+
+        // M-step
+        for (j, c) in components.iter_mut().enumerate() {
+            let mass: f64 = (0..xs.len()).map(|i| resp[i * k + j]).sum();
+            if mass <= f64::EPSILON {
+                // Nothing assigned: keep the component where it is, with no weight to speak of
+                c.weight = f64::EPSILON;
+                continue;
+            }
+            let mean = xs.iter().enumerate().map(|(i, x)| resp[i * k + j] * x).sum::<f64>() / mass;
+            let variance = xs
+                .iter()
+                .enumerate()
+                .map(|(i, x)| resp[i * k + j] * (x - mean) * (x - mean))
+                .sum::<f64>()
+                / mass;
+            *c = Component {
+                weight: mass / xs.len() as f64,
+                mean,
+                variance: variance.max(MIN_VARIANCE),
+            };
+        }
+
+        let done = ll - log_likelihood < TOLERANCE;
+        log_likelihood = ll;
+        if done {
+            break;
+        }
+    }
+    components.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+    (components, log_likelihood)
+}
+
+// Evenly spaced quantiles as means, each starting as wide as the whole sample
+fn initial_components(xs: &[f64], k: usize) -> Vec<Component> {
+    let mut sorted = xs.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let (_, variance) = mean_and_variance(xs);
+    (0..k)
+        .map(|j| Component {
+            weight: 1.0 / k as f64,
+            mean: sorted[((2 * j + 1) * sorted.len() / (2 * k)).min(sorted.len() - 1)],
+            variance: variance.max(MIN_VARIANCE),
+        })
+        .collect()
+}
+
+// One-dimensional Gaussian mixture on log order size, where heavy-tailed sizes spread out
+// into roughly normal bumps. The number of components is chosen by BIC within the requested
+// range, warm-starting from the previous mixture when the count is unchanged. Components are
+// kept sorted by mean, so cluster ids run from the smallest orders to the largest.
+pub struct GaussianMixture {
+    components: Vec<Component>,
+}
+
+impl GaussianMixture {
+    pub fn new() -> Self {
+        Self { components: vec![] }
+    }
+
+    fn label(&self, x: f64) -> usize {
+        self.components
+            .iter()
+            .map(|c| c.log_density(x))
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |(j, _)| j)
+    }
+}
+
+impl Clusterer for GaussianMixture {
+    fn cluster(
+        &mut self,
+        orders: &[OrderSample],
+        _mid: Decimal,
+        _tick_size: Decimal,
+        params: &ClusterParams,
+    ) -> ClusterFit {
+        let xs: Vec<f64> = orders
+            .iter()
+            .filter(|order| order.qty > Decimal::ZERO)
+            .map(|order| order.qty.to_f64().unwrap_or(0.0).ln())
+            .collect();
+        if xs.is_empty() {
+            return ClusterFit { labels: vec![], num_clusters: 0, silhouette: 0.0 };
+        }
+
+        // Each component costs a weight, a mean and a variance; the weights sum to one
+        let n = xs.len() as f64;
+        let low = (*params.num_clusters.start()).clamp(1, xs.len());
+        let high = (*params.num_clusters.end()).min(xs.len() / 2).max(low);
+        let mut best: Option<(f64, Vec<Component>)> = None;
+        for k in low..=high {
+            let start = if self.components.len() == k {
+                self.components.clone()
+            } else {
+                initial_components(&xs, k)
+            };
+            let (components, log_likelihood) = em(&xs, start, params.max_iter);
+            let bic = (3 * k - 1) as f64 * n.ln() - 2.0 * log_likelihood;
+            if best.as_ref().is_none_or(|(best_bic, _)| bic < *best_bic) {
+                best = Some((bic, components));
+            }
+        }
+        if let Some((_, components)) = best {
+            self.components = components;
+        }
+
+        let labels: Vec<usize> = xs.iter().map(|&x| self.label(x)).collect();
+        let points: Vec<Point> = xs
+            .iter()
+            .map(|&x| {
+                let mut coords = [0.0; FEATURE_COUNT];
+                coords[0] = x;
+                Point { coords }
+            })
+            .collect();
+        ClusterFit {
+            silhouette: kmeans::silhouette(&points, &labels),
+            num_clusters: self.components.len(),
+            labels,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.components.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmeans::FeatureWeights;
+    use crate::model::Side;
+    use rust_decimal::dec;
+
+    fn params(num_clusters: std::ops::RangeInclusive<usize>) -> ClusterParams {
+        ClusterParams {
+            batch_size: 0,
+            max_iter: 200,
+            weights: FeatureWeights::default(),
+            num_clusters,
+            eps: 0.0,
+            min_points: 0,
+            seed: None,
+        }
+    }
+
+    #[test]
+    fn test_gmm_separates_heavy_tailed_sizes() {
+        // Retail-sized orders around 0.01..0.05 and a few blocks near 100, sizes varying a bit
+        let sizes = [dec!(0.01), dec!(0.02), dec!(0.03), dec!(0.05), dec!(0.02), dec!(0.04)]
+            .into_iter()
+            .chain([dec!(90), dec!(100), dec!(120), dec!(110)]);
+        let orders: Vec<OrderSample> = sizes
+            .map(|qty| OrderSample {
+                id: 0,
+                side: Side::Bid,
+                price: dec!(100),
+                qty,
+                age: 0,
+                queue_index: 0,
+            })
+            .collect();
+        let mut gmm = GaussianMixture::new();
+        let fit = gmm.cluster(&orders, dec!(100), dec!(1), &params(1..=4));
+        assert_eq!(fit.num_clusters, 2);
+        assert_eq!(fit.labels, [0, 0, 0, 0, 0, 0, 1, 1, 1, 1]);
+        assert!(fit.silhouette > 0.8);
+
+        // Refit from the previous mixture gives the same answer
+        let again = gmm.cluster(&orders, dec!(100), dec!(1), &params(2..=2));
+        assert_eq!(again.labels, fit.labels);
+    }
+
+    #[test]
+    fn test_em_recovers_components() {
+        let spread = |i: usize| (i % 5) as f64 * 0.1;
+        let xs: Vec<f64> = (0..50).map(spread).chain((0..50).map(|i| 10.0 + spread(i))).collect();
+        let (components, _) = em(&xs, initial_components(&xs, 2), 100);
+        assert!((components[0].mean - 0.2).abs() < 1e-6);
+        assert!((components[1].mean - 10.2).abs() < 1e-6);
+        assert!((components[0].weight - 0.5).abs() < 1e-6);
+    }
+}
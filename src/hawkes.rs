@@ -0,0 +1,258 @@
+use crate::ring::{LAMBDA_RING_CAP, LambdaRing};
+
+const EM_ITERATIONS: usize = 25;
+const MIN_BASELINE: f64 = 1e-9;
+const MAX_BRANCHING: f64 = 0.99; // keep the fitted process stationary
+const MIN_DECAY: f64 = 1e-3;
+const MAX_DECAY: f64 = 1e4;
+
+/// Parameters of an exponential-kernel Hawkes process
+/// `lambda(t) = baseline + excitation * sum(exp(-decay * (t - t_i)))`, all in seconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HawkesFit {
+    pub baseline: f64,
+    pub excitation: f64,
+    pub decay: f64,
+    pub branching_ratio: f64,
+    pub intensity: f64,
+}
+
+// Online Hawkes estimator over a rolling window of event timestamps (ms).
+// Each fit runs a few EM passes warm-started from the previous parameters. The exponential
+// kernel lets every pass run in O(N) using the usual recursive sums over past events. Fits run
+// at most once per refit interval; in between, the intensity comes from the last parameters and
+// a running kernel sum that every new event updates in O(1).
+pub struct HawkesEstimator {
+    events: LambdaRing,
+    window_ms: u64,
+    refit_interval_ms: u64,
+    last_fit_ms: Option<u64>,
+    fit: HawkesFit,
+    // Event times of the current fit in seconds, kept to avoid allocating on every refit
+    times: Vec<f64>,
+    // sum(exp(-decay * (kernel_at - t_i))) over the events so far, as of `kernel_at_ms`
+    kernel_sum: f64,
+    kernel_at_ms: u64,
+}
+
+impl HawkesEstimator {
+    pub fn new(window_ms: u64, refit_interval_ms: u64) -> Self {
+        Self {
+            events: LambdaRing::new(),
+            window_ms,
+            refit_interval_ms,
+            last_fit_ms: None,
+            fit: HawkesFit::default(),
+            times: Vec::new(),
+            kernel_sum: 0.0,
+            kernel_at_ms: 0,
+        }
+    }
+
+    pub fn push(&mut self, ts_ms: u64) {
+        self.events.push(ts_ms);
+        if ts_ms >= self.kernel_at_ms {
+            self.kernel_sum = self.kernel_sum * self.kernel_decay(ts_ms - self.kernel_at_ms) + 1.0;
+            self.kernel_at_ms = ts_ms;
+        } else {
+            self.kernel_sum += self.kernel_decay(self.kernel_at_ms - ts_ms);
+        }
+    }
+
+    fn kernel_decay(&self, elapsed_ms: u64) -> f64 {
+        (-self.fit.decay * elapsed_ms as f64 / 1000.0).exp()
+    }
+
+    /// Refits when the refit interval has passed since the last fit, otherwise carries the
+    /// last parameters forward to `now_ms` and only updates the intensity.
+    pub fn update(&mut self, now_ms: u64) -> HawkesFit {
+        if self.last_fit_ms.is_none_or(|last| now_ms >= last + self.refit_interval_ms) {
+            return self.fit(now_ms);
+        }
+        let kernel = self.kernel_sum * self.kernel_decay(now_ms.saturating_sub(self.kernel_at_ms));
+        HawkesFit {
+            intensity: self.fit.baseline + self.fit.excitation * kernel,
+            ..self.fit
+        }
+    }
+
+    /// Drops events older than the window ending at `now_ms` and refits the parameters.
+    pub fn fit(&mut self, now_ms: u64) -> HawkesFit {
+        self.events.reset(now_ms.saturating_sub(self.window_ms));
+
+        // If the ring wrapped, the oldest retained event bounds the observation window.
+        let mut start_ms = now_ms.saturating_sub(self.window_ms);
+        if self.events.len == LAMBDA_RING_CAP {
+            start_ms = start_ms.max(self.events.inner[self.events.head]);
+        }
+        self.last_fit_ms = Some(now_ms);
+        let mut times = std::mem::take(&mut self.times);
+        times.clear();
+        times.extend(self.events.iter().map(|ts| ts.saturating_sub(start_ms) as f64 / 1000.0));
+        let horizon = times
+            .last()
+            .copied()
+            .unwrap_or(0.0)
+            .max(now_ms.saturating_sub(start_ms) as f64 / 1000.0);
+
+        if times.len() < 2 || horizon <= 0.0 {
+            let baseline = if horizon > 0.0 { times.len() as f64 / horizon } else { 0.0 };
+            self.fit = HawkesFit {
+                baseline,
+                intensity: baseline,
+                ..HawkesFit::default()
+            };
+            self.times = times;
+            self.kernel_sum = 0.0;
+            self.kernel_at_ms = now_ms;
+            return self.fit;
+        }
+
+        let mut mu = self.fit.baseline;
+        let mut n = self.fit.branching_ratio;
+        let mut beta = self.fit.decay;
+        if beta <= 0.0 {
+            // Cold start: half of the flow exogenous, kernel decaying over one mean inter-arrival.
+            let rate = times.len() as f64 / horizon;
+            mu = 0.5 * rate;
+            n = 0.5;
+            beta = rate.clamp(MIN_DECAY, MAX_DECAY);
+        }
+
+        for _ in 0..EM_ITERATIONS {
+            // a = sum_j exp(-beta * (t_i - t_j)), b = sum_j (t_i - t_j) * exp(-beta * (t_i - t_j))
+            let mut a = 0.0;
+            let mut b = 0.0;
+            let mut prev: Option<f64> = None;
+            let mut background = 0.0;
+            let mut triggered = 0.0;
+            let mut triggered_lag = 0.0;
+            let mut compensator = 0.0;
+
+            for &t in &times {
+                if let Some(p) = prev {
+                    let dt = t - p;
+                    let e = (-beta * dt).exp();
+                    b = e * (b + dt * (1.0 + a));
+                    a = e * (1.0 + a);
+                }
+                let lambda = mu.max(MIN_BASELINE) + n * beta * a;
+                background += mu.max(MIN_BASELINE) / lambda;
+                triggered += n * beta * a / lambda;
+                triggered_lag += n * beta * b / lambda;
+                compensator += 1.0 - (-beta * (horizon - t)).exp();
+                prev = Some(t);
+            }
+
+            mu = (background / horizon).max(MIN_BASELINE);
+            n = if compensator > 0.0 {
+                (triggered / compensator).clamp(0.0, MAX_BRANCHING)
+            } else {
+                0.0
+            };
+            if triggered_lag > 0.0 {
+                beta = (triggered / triggered_lag).clamp(MIN_DECAY, MAX_DECAY);
+            }
+        }
+
+        // The kernel sum restarts from the fitted events, at the horizon's end
+        let decayed: f64 = times.iter().map(|&t| (-beta * (horizon - t)).exp()).sum();
+        self.fit = HawkesFit {
+            baseline: mu,
+            excitation: n * beta,
+            decay: beta,
+            branching_ratio: n,
+            intensity: mu + n * beta * decayed,
+        };
+        self.times = times;
+        self.kernel_sum = decayed;
+        self.kernel_at_ms = start_ms + (horizon * 1000.0).round() as u64;
+        self.fit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_window() {
+        let mut hawkes = HawkesEstimator::new(60_000, 250);
+        let fit = hawkes.fit(1_000_000);
+        assert_eq!(fit.baseline, 0.0);
+        assert_eq!(fit.intensity, 0.0);
+        assert_eq!(fit.branching_ratio, 0.0);
+    }
+
+    #[test]
+    fn test_regular_arrivals_have_no_excitation() {
+        let mut hawkes = HawkesEstimator::new(60_000, 250);
+        // 10 trades per second, evenly spaced: nothing self-exciting about it
+        for i in 0..600u64 {
+            hawkes.push(1_000_000 + i * 100);
+        }
+        let fit = hawkes.fit(1_060_000);
+        assert!(fit.branching_ratio < 0.1, "branching {}", fit.branching_ratio);
+        assert!((fit.baseline - 10.0).abs() < 1.5, "baseline {}", fit.baseline);
+    }
+
+    #[test]
+    fn test_clustered_arrivals_are_excited() {
+        let mut hawkes = HawkesEstimator::new(60_000, 250);
+        // One trade per second, each followed by a quick burst of three more
+        for i in 0..60u64 {
+            let t = 1_000_000 + i * 1_000;
+            for k in 0..4u64 {
+                hawkes.push(t + k * 5);
+            }
+        }
+        let fit = hawkes.fit(1_060_000);
+        assert!(fit.branching_ratio > 0.5, "branching {}", fit.branching_ratio);
+        assert!(fit.decay > 1.0, "decay {}", fit.decay);
+    }
+
+    #[test]
+    fn test_window_drops_old_events() {
+        let mut hawkes = HawkesEstimator::new(1_000, 250);
+        for i in 0..10u64 {
+            hawkes.push(i * 10);
+        }
+        let fit = hawkes.fit(100_000);
+        assert_eq!(fit.baseline, 0.0);
+    }
+
+    #[test]
+    fn test_update_refits_on_cadence() {
+        let mut hawkes = HawkesEstimator::new(60_000, 250);
+        for i in 0..60u64 {
+            let t = 1_000_000 + i * 1_000;
+            for k in 0..4u64 {
+                hawkes.push(t + k * 5);
+            }
+        }
+        // Just after the last burst, while its excitation is still decaying
+        let fit = hawkes.update(1_059_020);
+        // Within the interval the parameters stay, and the intensity decays without new events
+        let later = hawkes.update(1_059_100);
+        assert_eq!((later.baseline, later.decay), (fit.baseline, fit.decay));
+        assert!(later.intensity < fit.intensity);
+        // A new event bumps the intensity by one kernel's worth
+        hawkes.push(1_059_100);
+        let bumped = hawkes.update(1_059_100);
+        assert!((bumped.intensity - later.intensity - fit.excitation).abs() < 1e-9);
+        assert_eq!(bumped.branching_ratio, fit.branching_ratio);
+
+        // Carried forward it matches the kernel sum over every event with the same parameters
+        let carried = hawkes.update(1_059_200);
+        let decayed: f64 = hawkes
+            .events
+            .iter()
+            .map(|ts| (-fit.decay * (1_059_200 - ts) as f64 / 1000.0).exp())
+            .sum();
+        assert!((carried.intensity - (fit.baseline + fit.excitation * decayed)).abs() < 1e-6);
+
+        // Past the interval it refits with the new event included
+        let next = hawkes.update(1_059_270);
+        assert_ne!(next.baseline, fit.baseline);
+    }
+}
@@ -0,0 +1,171 @@
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::model::TradeUpdate;
+
+// One sample of the book: (price, resting qty) for the levels nearest the touch, ascending by price.
+pub struct HeatmapColumn {
+    pub ts: u64,
+    pub levels: Vec<(f64, f64)>,
+}
+
+pub struct HeatmapTrade {
+    pub ts: u64,
+    pub price: f64,
+    pub qty: f64,
+    pub is_buy: bool,
+}
+
+// Price x time record of resting liquidity, sampled at a fixed cadence, plus the trades
+// printed over the same window so they can be drawn on top of it.
+pub struct LiquidityHeatmap {
+    columns: VecDeque<HeatmapColumn>,
+    trades: VecDeque<HeatmapTrade>,
+    sample_interval_ms: u64,
+    window_ms: u64,
+    depth: usize,
+}
+
+impl LiquidityHeatmap {
+    pub fn new(sample_interval_ms: u64, window_ms: u64, depth: usize) -> Self {
+        Self {
+            columns: VecDeque::new(),
+            trades: VecDeque::new(),
+            sample_interval_ms,
+            window_ms,
+            depth,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.columns.clear();
+        self.trades.clear();
+    }
+
+    pub fn window_ms(&self) -> u64 {
+        self.window_ms
+    }
+
+    pub fn sample_interval_ms(&self) -> u64 {
+        self.sample_interval_ms
+    }
+
+    pub fn columns(&self) -> &VecDeque<HeatmapColumn> {
+        &self.columns
+    }
+
+    pub fn trades(&self) -> &VecDeque<HeatmapTrade> {
+        &self.trades
+    }
+
+    /// Records a trade and drops those older than the window, so trades stay bounded even
+    /// while the book is not being sampled, e.g. until a snapshot fetch succeeds.
+    pub fn push_trade(&mut self, trade: &TradeUpdate) {
+        self.trades.push_back(HeatmapTrade {
+            ts: trade.trade_time,
+            price: trade.p.to_f64().unwrap_or(0.0),
+            qty: trade.q.to_f64().unwrap_or(0.0),
+            is_buy: !trade.buyer_market_maker,
+        });
+        self.prune_trades(trade.trade_time.saturating_sub(self.window_ms));
+    }
+
+    fn prune_trades(&mut self, cutoff: u64) {
+        while self.trades.front().is_some_and(|t| t.ts < cutoff) {
+            self.trades.pop_front();
+        }
+    }
+
+    /// Records a column if the sampling interval has elapsed, then drops anything older than the window.
+    pub fn sample(
+        &mut self,
+        now_ms: u64,
+        bids: &BTreeMap<Decimal, VecDeque<Decimal>>,
+        asks: &BTreeMap<Decimal, VecDeque<Decimal>>,
+    ) {
+        if let Some(last) = self.columns.back()
+            && now_ms < last.ts + self.sample_interval_ms
+        {
+            return;
+        }
+
+        let level = |(price, qtys): (&Decimal, &VecDeque<Decimal>)| {
+            (
+                price.to_f64().unwrap_or(0.0),
+                qtys.iter().sum::<Decimal>().to_f64().unwrap_or(0.0),
+            )
+        };
+        let mut levels: Vec<(f64, f64)> = bids.iter().rev().take(self.depth).map(level).collect();
+        levels.reverse();
+        levels.extend(asks.iter().take(self.depth).map(level));
+        self.columns.push_back(HeatmapColumn { ts: now_ms, levels });
+
+        let cutoff = now_ms.saturating_sub(self.window_ms);
+        while self.columns.front().is_some_and(|c| c.ts < cutoff) {
+            self.columns.pop_front();
+        }
+        self.prune_trades(cutoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn book(levels: &[(Decimal, Decimal)]) -> BTreeMap<Decimal, VecDeque<Decimal>> {
+        levels
+            .iter()
+            .map(|&(price, qty)| (price, VecDeque::from(vec![qty, qty])))
+            .collect()
+    }
+
+    #[test]
+    fn test_sample_keeps_levels_near_touch() {
+        let mut heatmap = LiquidityHeatmap::new(100, 1_000, 2);
+        let bids = book(&[(dec!(7), dec!(1)), (dec!(8), dec!(2)), (dec!(9), dec!(3))]);
+        let asks = book(&[(dec!(10), dec!(4)), (dec!(11), dec!(5)), (dec!(12), dec!(6))]);
+        heatmap.sample(1_000, &bids, &asks);
+        let column = heatmap.columns().back().unwrap();
+        assert_eq!(
+            column.levels,
+            vec![(8.0, 4.0), (9.0, 6.0), (10.0, 8.0), (11.0, 10.0)]
+        );
+    }
+
+    #[test]
+    fn test_sample_cadence_and_window() {
+        let mut heatmap = LiquidityHeatmap::new(100, 1_000, 10);
+        let bids = book(&[(dec!(9), dec!(1))]);
+        let asks = book(&[(dec!(10), dec!(1))]);
+        for ts in (0..3_000).step_by(25) {
+            heatmap.sample(ts, &bids, &asks);
+        }
+        let ts: Vec<u64> = heatmap.columns().iter().map(|c| c.ts).collect();
+        assert_eq!(ts.len(), 11);
+        assert_eq!(ts.first(), Some(&1_900));
+        assert_eq!(ts.last(), Some(&2_900));
+    }
+
+    #[test]
+    fn test_trades_pruned_without_sampling() {
+        let mut heatmap = LiquidityHeatmap::new(100, 1_000, 10);
+        // The book never syncs, so nothing is sampled while trades keep coming
+        for ts in (0..5_000).step_by(10) {
+            heatmap.push_trade(&TradeUpdate {
+                e: "aggTrade".to_string(),
+                event_time: ts,
+                symbol: "DOGEUSDT".to_string(),
+                trade_id: ts,
+                p: dec!(0.1),
+                q: dec!(1),
+                trade_time: ts,
+                buyer_market_maker: false,
+            });
+        }
+        assert!(heatmap.columns().is_empty());
+        assert_eq!(heatmap.trades().len(), 101);
+        assert_eq!(heatmap.trades().front().map(|t| t.ts), Some(3_990));
+    }
+}
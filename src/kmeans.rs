@@ -1,137 +1,385 @@
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rust_decimal::Decimal;
 use rust_decimal::prelude::ToPrimitive;
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::RangeInclusive;
+
+use crate::clustering::{ClusterFit, ClusterParams, Clusterer, NOISE};
+use crate::model::{OrderSample, Side};
+
+pub const FEATURE_COUNT: usize = 5;
+// Points scored per silhouette; the score is quadratic in this
+const SILHOUETTE_SAMPLE: usize = 400;
+
+// What an order is described by when clustering; each feature is min-max scaled and then
+// multiplied by its weight, so a zero weight leaves it out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    LogSize,
+    Age,
+    QueueIndex,
+    DistanceFromMid,
+    Side,
+}
+
+impl Feature {
+    pub const ALL: [Feature; FEATURE_COUNT] = [
+        Feature::LogSize,
+        Feature::Age,
+        Feature::QueueIndex,
+        Feature::DistanceFromMid,
+        Feature::Side,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Feature::LogSize => "Log size",
+            Feature::Age => "Age",
+            Feature::QueueIndex => "Queue position",
+            Feature::DistanceFromMid => "Ticks from mid",
+            Feature::Side => "Side",
+        }
+    }
+
+    fn value(self, order: &OrderSample, mid: Decimal, tick_size: Decimal) -> f64 {
+        match self {
+            Feature::LogSize => order.qty.to_f64().unwrap_or(0.0).ln(),
+            Feature::Age => order.age as f64,
+            Feature::QueueIndex => order.queue_index as f64,
+            Feature::DistanceFromMid => {
+                if tick_size.is_zero() {
+                    0.0
+                } else {
+                    ((order.price - mid).abs() / tick_size).to_f64().unwrap_or(0.0)
+                }
+            }
+            Feature::Side => match order.side {
+                Side::Bid => 0.0,
+                Side::Ask => 1.0,
+            },
+        }
+    }
+}
+
+/// Per-feature scaling, indexed like [`Feature::ALL`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeatureWeights(pub [f64; FEATURE_COUNT]);
+
+impl Default for FeatureWeights {
+    fn default() -> Self {
+        // Size leads; the others split orders of similar size by how they behave
+        Self([1.0, 0.5, 0.5, 0.5, 1.0])
+    }
+}
 
-// Point structure for clustering (using qty only for simplicity)
+// Point structure for clustering: one scaled coordinate per feature
 #[derive(Clone, Copy, Debug)]
-struct Point {
-    qty: f64,
+pub(crate) struct Point {
+    pub(crate) coords: [f64; FEATURE_COUNT],
 }
 
-fn euclidean_distance(a: &Point, b: &Point) -> f64 {
-    (a.qty - b.qty).abs()
+pub(crate) fn euclidean_distance(a: &Point, b: &Point) -> f64 {
+    a.coords
+        .iter()
+        .zip(&b.coords)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f64>()
+        .sqrt()
 }
 
-fn normalize(points: &mut [Point]) {
+// Lexicographic by coordinate, size first, for deterministic ordering of points and centroids
+fn compare_points(a: &Point, b: &Point) -> Ordering {
+    a.coords
+        .iter()
+        .zip(&b.coords)
+        .map(|(x, y)| x.partial_cmp(y).unwrap_or(Ordering::Equal))
+        .find(|&ord| ord != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+fn normalize(points: &mut [Point], weights: &FeatureWeights) {
     if points.is_empty() {
         return;
     }
 
-    let mut min_q = f64::MAX;
-    let mut max_q = f64::MIN;
+    for dim in 0..FEATURE_COUNT {
+        let mut min_v = f64::MAX;
+        let mut max_v = f64::MIN;
 
-    for p in points.iter() {
-        min_q = min_q.min(p.qty);
-        max_q = max_q.max(p.qty);
-    }
+        for p in points.iter() {
+            min_v = min_v.min(p.coords[dim]);
+            max_v = max_v.max(p.coords[dim]);
+        }
 
-    let range_q = max_q - min_q;
+        let range = max_v - min_v;
+        let weight = weights.0[dim];
 
-    if range_q > 0.0 {
         for p in points.iter_mut() {
-            p.qty = (p.qty - min_q) / range_q;
+            // A constant feature carries no information
+            p.coords[dim] = if range > 0.0 {
+                (p.coords[dim] - min_v) / range * weight
+            } else {
+                0.0
+            };
         }
     }
 }
 
-// Mini-batch K-means with stability: uses previous centroids if provided, deterministic init if not, and label sorting
+// Greedily pairs each new centroid with the nearest unclaimed previous one and takes over its
+// id; centroids left without a partner get the lowest unused ids.
+fn match_centroids(previous: &[Point], previous_ids: &[usize], next: &[Point]) -> Vec<usize> {
+    let mut pairs: Vec<(f64, usize, usize)> = Vec::with_capacity(previous.len() * next.len());
+    for (n, b) in next.iter().enumerate() {
+        for (p, a) in previous.iter().enumerate() {
+            pairs.push((euclidean_distance(a, b), n, p));
+        }
+    }
+    pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    let mut ids: Vec<Option<usize>> = vec![None; next.len()];
+    let mut taken = vec![false; previous.len()];
+    for (_, n, p) in pairs {
+        if ids[n].is_none() && !taken[p] {
+            ids[n] = Some(previous_ids[p]);
+            taken[p] = true;
+        }
+    }
+    let used: Vec<usize> = ids.iter().flatten().copied().collect();
+    let mut free_ids = (0..).filter(|id| !used.contains(id));
+    ids.into_iter().map(|id| id.unwrap_or_else(|| free_ids.next().unwrap())).collect()
+}
+
+// Scaled feature vectors of the orders with a positive size
+pub(crate) fn feature_points(
+    orders: &[OrderSample],
+    mid: Decimal,
+    tick_size: Decimal,
+    weights: &FeatureWeights,
+) -> Vec<Point> {
+    let mut points: Vec<Point> = orders
+        .iter()
+        .filter(|order| order.qty > Decimal::ZERO)
+        .map(|order| Point {
+            coords: Feature::ALL.map(|feature| feature.value(order, mid, tick_size)),
+        })
+        .collect();
+    normalize(&mut points, weights);
+    points
+}
+
+// Mean silhouette over an evenly spaced sample of the clustered points: for each one, how much
+// closer it sits to its own cluster than to the nearest other one, from -1 (misplaced) to 1.
+// Noise takes no part.
+pub(crate) fn silhouette(points: &[Point], labels: &[usize]) -> f64 {
+    let clustered: Vec<usize> = (0..points.len()).filter(|&i| labels[i] != NOISE).collect();
+    let stride = clustered.len().div_ceil(SILHOUETTE_SAMPLE).max(1);
+    let sample: Vec<usize> = clustered.into_iter().step_by(stride).collect();
+    if sample.is_empty() {
+        return 0.0;
+    }
+    let mut total = 0.0;
+    for &i in &sample {
+        // Summed distance and count per other label
+        let mut by_label: BTreeMap<usize, (f64, usize)> = BTreeMap::new();
+        for &j in &sample {
+            if i != j {
+                let entry = by_label.entry(labels[j]).or_default();
+                entry.0 += euclidean_distance(&points[i], &points[j]);
+                entry.1 += 1;
+            }
+        }
+        // Alone in its cluster: scores 0 by convention
+        let Some(&(own_sum, own_count)) = by_label.get(&labels[i]) else {
+            continue;
+        };
+        let a = own_sum / own_count as f64;
+        let b = by_label
+            .iter()
+            .filter(|&(&label, _)| label != labels[i])
+            .map(|(_, &(sum, count))| sum / count as f64)
+            .fold(f64::INFINITY, f64::min);
+        if b.is_finite() && a.max(b) > 0.0 {
+            total += (b - a) / a.max(b);
+        }
+    }
+    total / sample.len() as f64
+}
+
+// Mini-batch K-means kept alive across fits: each fit warm-starts from the previous centroids,
+// and every centroid carries an id that survives refits and reinitialisation, so labels (and
+// the colours drawn from them) stay put while the book evolves.
+#[derive(Clone)]
 pub struct MiniBatchKMeans {
     num_clusters: usize,
     batch_size: usize,
     max_iter: usize,
+    weights: FeatureWeights,
     centroids: Vec<Point>,
+    // Stable label of each centroid
+    ids: Vec<usize>,
+    // Set when the centroids no longer fit the feature space and must be reinitialised
+    restart: bool,
+    // Draws the mini-batches; seed it for reproducible labels
+    rng: StdRng,
 }
 
 impl MiniBatchKMeans {
     pub fn new(num_clusters: usize, batch_size: usize, max_iter: usize) -> Self {
         Self {
-            num_clusters,
+            num_clusters: num_clusters.max(1),
             batch_size,
             max_iter,
+            weights: FeatureWeights::default(),
             centroids: vec![],
+            ids: vec![],
+            restart: false,
+            rng: StdRng::from_rng(&mut rand::rng()),
         }
     }
 
-    // Fit on data, using previous centroids if available
-    pub fn fit(&mut self, order_book: &BTreeMap<Decimal, VecDeque<Decimal>>) -> Vec<usize> {
-        let mut points: Vec<Point> = vec![];
-        let mut order_list: Vec<(Decimal, Decimal)> = vec![];
+    /// Draws mini-batches from a fixed seed, so the same sequence of books always gives the
+    /// same labels.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.reseed(seed);
+        self
+    }
 
-        for (&price, deq) in order_book.iter() {
-            for &qty in deq.iter() {
-                if qty > Decimal::ZERO {
-                    let qty_f64 = qty.to_f64().unwrap_or(0.0);
-                    points.push(Point { qty: qty_f64 });
-                    order_list.push((price, qty));
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    #[cfg(test)]
+    pub fn with_weights(mut self, weights: FeatureWeights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Updates the fit parameters between fits. Centroids are rescaled to new weights so the
+    /// model keeps its warm start; a feature switched on from zero forces a fresh start.
+    pub fn configure(&mut self, batch_size: usize, max_iter: usize, weights: FeatureWeights) {
+        self.batch_size = batch_size;
+        self.max_iter = max_iter;
+        if weights == self.weights {
+            return;
+        }
+        for dim in 0..FEATURE_COUNT {
+            let (old, new) = (self.weights.0[dim], weights.0[dim]);
+            if old > 0.0 {
+                for c in self.centroids.iter_mut() {
+                    c.coords[dim] *= new / old;
                 }
+            } else if new > 0.0 {
+                // Nothing to rescale from; matching against the old centroids still carries
+                // their ids over
+                self.restart = true;
             }
         }
+        self.weights = weights;
+    }
 
+    /// Forgets the centroids and ids, e.g. when the book switches symbol.
+    pub fn reset(&mut self) {
+        self.centroids.clear();
+        self.ids.clear();
+        self.restart = false;
+    }
+
+    // Fit on the orders with a positive size, using previous centroids if available.
+    // Labels come back in the same order as those orders.
+    pub fn fit(&mut self, orders: &[OrderSample], mid: Decimal, tick_size: Decimal) -> Vec<usize> {
+        let points = feature_points(orders, mid, tick_size, &self.weights);
+        self.fit_points(&points)
+    }
+
+    /// Fits once per cluster count in `range`, each warm-started from this model, and keeps
+    /// the one with the best silhouette; ties go to fewer clusters. Counts that leave fewer
+    /// than two orders per cluster are skipped, and with nothing left to compare the model
+    /// fits at the low end of the range.
+    pub fn fit_auto(
+        &mut self,
+        orders: &[OrderSample],
+        mid: Decimal,
+        tick_size: Decimal,
+        range: RangeInclusive<usize>,
+    ) -> ClusterFit {
+        let points = feature_points(orders, mid, tick_size, &self.weights);
         if points.is_empty() {
-            return vec![];
+            return ClusterFit { labels: vec![], num_clusters: 0, silhouette: 0.0 };
+        }
+        let (low, high) = ((*range.start()).max(1), (*range.end()).min(points.len() / 2));
+        let mut best: Option<(MiniBatchKMeans, ClusterFit)> = None;
+        for num_clusters in low.max(2)..=high {
+            let mut candidate = self.clone();
+            candidate.num_clusters = num_clusters;
+            let labels = candidate.fit_points(&points);
+            let score = silhouette(&points, &labels);
+            if best.as_ref().is_none_or(|(_, fit)| score > fit.silhouette) {
+                best = Some((candidate, ClusterFit { labels, num_clusters, silhouette: score }));
+            }
         }
+        match best {
+            Some((model, fit)) => {
+                *self = model;
+                fit
+            }
+            None => {
+                self.num_clusters = low;
+                let labels = self.fit_points(&points);
+                ClusterFit { labels, num_clusters: low, silhouette: 0.0 }
+            }
+        }
+    }
 
-        normalize(&mut points);
+    fn fit_points(&mut self, points: &[Point]) -> Vec<usize> {
+        if points.is_empty() {
+            return vec![];
+        }
 
-        // Initialize centroids if not already set
-        if self.centroids.is_empty() || self.centroids.len() != self.num_clusters {
-            self.centroids = self.initialize_centroids(&points);
+        // Initialize centroids if not already set, inheriting ids from any previous ones
+        if self.centroids.len() != self.num_clusters || self.restart {
+            let centroids = self.initialize_centroids(points);
+            self.ids = if self.centroids.is_empty() {
+                (0..self.num_clusters).collect()
+            } else {
+                match_centroids(&self.centroids, &self.ids, &centroids)
+            };
+            self.centroids = centroids;
+            self.restart = false;
         }
 
         // Mini-batch updates
-        let mut rng = rand::rng();
         for _ in 0..self.max_iter {
             // Select mini-batch
             let batch_indices: Vec<usize> = (0..self.batch_size.min(points.len()))
-                .map(|_| rng.random_range(0..points.len()))
+                .map(|_| self.rng.random_range(0..points.len()))
                 .collect();
 
             let mut counts = vec![0; self.num_clusters];
-            let mut sums = vec![0.0; self.num_clusters];
+            let mut sums = vec![[0.0; FEATURE_COUNT]; self.num_clusters];
 
             for &idx in &batch_indices {
                 let p = points[idx];
                 let closest = self.closest_centroid(&p);
-                sums[closest] += p.qty;
+                for (sum, x) in sums[closest].iter_mut().zip(p.coords) {
+                    *sum += x;
+                }
                 counts[closest] += 1;
             }
 
             for i in 0..self.num_clusters {
                 if counts[i] > 0 {
                     let lr = 1.0 / counts[i] as f64; // Learning rate
-                    self.centroids[i].qty =
-                        (1.0 - lr) * self.centroids[i].qty + lr * (sums[i] / counts[i] as f64);
+                    for (c, sum) in self.centroids[i].coords.iter_mut().zip(sums[i]) {
+                        *c = (1.0 - lr) * *c + lr * (sum / counts[i] as f64);
+                    }
                 }
             }
         }
 
-        // Assign labels
-        let mut labels = vec![0; points.len()];
-        for (i, p) in points.iter().enumerate() {
-            labels[i] = self.closest_centroid(p);
-        }
-
-        // Stabilize labels by sorting based on centroid qty
-        let mut centroid_indices: Vec<usize> = (0..self.num_clusters).collect();
-        centroid_indices.sort_by(|&a, &b| {
-            self.centroids[a]
-                .qty
-                .partial_cmp(&self.centroids[b].qty)
-                .unwrap_or(Ordering::Equal)
-        });
-
-        let mut label_map = HashMap::new();
-        for (new_label, &old_label) in centroid_indices.iter().enumerate() {
-            label_map.insert(old_label, new_label);
-        }
-
-        for label in labels.iter_mut() {
-            *label = *label_map.get(label).unwrap_or(&0);
-        }
-
-        labels
+        // Assign labels by centroid id
+        points.iter().map(|p| self.ids[self.closest_centroid(p)]).collect()
     }
 
     fn closest_centroid(&self, p: &Point) -> usize {
@@ -148,71 +396,262 @@ impl MiniBatchKMeans {
     }
 
     fn initialize_centroids(&self, points: &[Point]) -> Vec<Point> {
-        let mut centroids = vec![];
+        let mut centroids = Vec::with_capacity(self.num_clusters);
 
-        // Deterministic initialization: sort by qty and pick evenly spaced points
+        // Deterministic initialization: sort the points and pick evenly spaced ones, from the
+        // smallest to the largest. With fewer points than clusters some points are picked
+        // twice; the duplicate centroids never win a point and stay empty.
         let mut sorted: Vec<Point> = points.to_vec();
-        sorted.sort_by(|a, b| a.qty.partial_cmp(&b.qty).unwrap_or(Ordering::Equal));
+        sorted.sort_by(compare_points);
 
-        let step = (sorted.len() - 1) / (self.num_clusters.max(1) - 1).max(1);
+        let last = sorted.len() - 1;
         for i in 0..self.num_clusters {
-            let idx = (i * step).min(sorted.len() - 1);
+            let idx = (i * last).checked_div(self.num_clusters - 1).unwrap_or(last / 2);
             centroids.push(sorted[idx]);
         }
 
-        while centroids.len() < self.num_clusters && !sorted.is_empty() {
-            centroids.push(sorted[0]); // Fill remaining with first point if needed
+        centroids
+    }
+}
+
+impl Clusterer for MiniBatchKMeans {
+    fn cluster(
+        &mut self,
+        orders: &[OrderSample],
+        mid: Decimal,
+        tick_size: Decimal,
+        params: &ClusterParams,
+    ) -> ClusterFit {
+        if let Some(seed) = params.seed {
+            self.reseed(seed);
         }
+        self.configure(params.batch_size, params.max_iter, params.weights);
+        self.fit_auto(orders, mid, tick_size, params.num_clusters.clone())
+    }
 
-        centroids
+    fn reset(&mut self) {
+        MiniBatchKMeans::reset(self);
     }
 }
 
 // Usage in cluster_order_book
 #[allow(dead_code)]
 pub fn cluster_order_book(
-    order_book: &BTreeMap<Decimal, VecDeque<Decimal>>,
+    orders: &[OrderSample],
+    mid: Decimal,
+    tick_size: Decimal,
     num_classes: usize,
     batch_size: usize,
     max_iter: usize,
 ) -> BTreeMap<Decimal, VecDeque<(Decimal, usize)>> {
     let mut kmeans = MiniBatchKMeans::new(num_classes, batch_size, max_iter);
 
-    let labels = kmeans.fit(order_book);
-
-    let mut clustered_orders: BTreeMap<Decimal, VecDeque<(Decimal, usize)>> = BTreeMap::new();
+    let labels = kmeans.fit(orders, mid, tick_size);
 
-    let mut idx = 0;
-    for (&price, deq) in order_book.iter() {
-        let entry = clustered_orders.entry(price).or_default();
-        for &qty in deq.iter() {
-            if qty > Decimal::ZERO {
-                entry.push_back((qty, labels[idx]));
-                idx += 1;
-            }
-        }
-    }
-
-    clustered_orders
+    build_clustered_orders(orders, &labels)
 }
 
-// Helper function to build clustered orders
+// Helper function to build clustered orders, per price in queue order
 pub fn build_clustered_orders(
-    order_book: &BTreeMap<Decimal, VecDeque<Decimal>>,
+    orders: &[OrderSample],
     labels: &[usize],
 ) -> BTreeMap<Decimal, VecDeque<(Decimal, usize)>> {
     let mut clustered_orders: BTreeMap<Decimal, VecDeque<(Decimal, usize)>> = BTreeMap::new();
     let mut idx = 0;
 
-    for (&price, deq) in order_book.iter() {
-        let entry = clustered_orders.entry(price).or_default();
-        for &qty in deq.iter() {
-            if qty > Decimal::ZERO {
-                entry.push_back((qty, labels[idx]));
-                idx += 1;
-            }
+    for order in orders {
+        let entry = clustered_orders.entry(order.price).or_default();
+        if order.qty > Decimal::ZERO {
+            entry.push_back((order.qty, labels[idx]));
+            idx += 1;
         }
     }
 
     clustered_orders
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn order(price: Decimal, qty: Decimal, age: u64, queue_index: usize) -> OrderSample {
+        OrderSample { id: 0, side: Side::Bid, price, qty, age, queue_index }
+    }
+
+    #[test]
+    fn test_normalize_scales_each_feature() {
+        let mut points = vec![
+            Point { coords: [1.0, 10.0, 0.0, 5.0, 1.0] },
+            Point { coords: [3.0, 30.0, 0.0, 1.0, 1.0] },
+        ];
+        normalize(&mut points, &FeatureWeights([1.0, 2.0, 1.0, 0.0, 1.0]));
+        assert_eq!(points[0].coords, [0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(points[1].coords, [1.0, 2.0, 0.0, 0.0, 0.0]);
+        assert_eq!(euclidean_distance(&points[0], &points[1]), 5.0f64.sqrt());
+    }
+
+    #[test]
+    fn test_features_separate_equal_sizes() {
+        // Same sizes everywhere: only age tells the two groups apart
+        let orders: Vec<OrderSample> = (0..20)
+            .map(|i| order(dec!(100) - Decimal::from(i % 4), dec!(1), if i < 10 { 0 } else { 1_000 }, 0))
+            .collect();
+        let weights = FeatureWeights([1.0, 1.0, 0.0, 0.0, 0.0]);
+        let mut kmeans = MiniBatchKMeans::new(2, 64, 32).with_weights(weights);
+        let labels = kmeans.fit(&orders, dec!(100.5), dec!(1));
+        assert!(labels[..10].iter().all(|&l| l == labels[0]));
+        assert!(labels[10..].iter().all(|&l| l == labels[10]));
+        assert_ne!(labels[0], labels[10]);
+    }
+
+    #[test]
+    fn test_match_centroids_keeps_ids() {
+        let point = |x: f64| Point { coords: [x, 0.0, 0.0, 0.0, 0.0] };
+        let previous = [point(0.0), point(0.5), point(1.0)];
+        // Reordered and nudged: each keeps the id of the centroid it came from
+        let next = [point(0.9), point(0.1), point(0.55)];
+        assert_eq!(match_centroids(&previous, &[7, 3, 5], &next), vec![5, 7, 3]);
+        // More centroids than before: the extra one takes the lowest free id
+        let next = [point(1.0), point(0.0), point(0.5), point(0.25)];
+        assert_eq!(match_centroids(&previous, &[2, 0, 1], &next), vec![1, 2, 0, 3]);
+    }
+
+    #[test]
+    fn test_labels_stable_across_warm_fits() {
+        let small = |i: usize| order(dec!(100) - Decimal::from(i), dec!(1), 0, 0);
+        let large = |i: usize| order(dec!(100) - Decimal::from(i), dec!(50), 0, 1);
+        let mut orders: Vec<OrderSample> = (0..10).map(small).chain((0..10).map(large)).collect();
+        let weights = FeatureWeights([1.0, 0.0, 0.0, 0.0, 0.0]);
+        let mut kmeans = MiniBatchKMeans::new(2, 64, 16).with_weights(weights);
+        let first = kmeans.fit(&orders, dec!(100.5), dec!(1));
+        assert_ne!(first[0], first[10]);
+
+        // The book shifts: sizes drift and the large orders now come first
+        orders.reverse();
+        for o in orders.iter_mut() {
+            o.qty *= dec!(1.2);
+        }
+        let second = kmeans.fit(&orders, dec!(100.5), dec!(1));
+        assert_eq!(second[0], first[10]);
+        assert_eq!(second[10], first[0]);
+
+        // Switching a feature on restarts the centroids but keeps the ids
+        kmeans.configure(64, 16, FeatureWeights([1.0, 0.0, 0.5, 0.0, 0.0]));
+        let third = kmeans.fit(&orders, dec!(100.5), dec!(1));
+        assert_eq!(third, second);
+    }
+
+    #[test]
+    fn test_fit_auto_picks_separated_groups() {
+        // Three well separated sizes, a handful of orders each
+        let orders: Vec<OrderSample> = [dec!(1), dec!(40), dec!(2000)]
+            .iter()
+            .flat_map(|&qty| (0..6).map(move |i| order(dec!(100) - Decimal::from(i), qty, 0, 0)))
+            .collect();
+        let weights = FeatureWeights([1.0, 0.0, 0.0, 0.0, 0.0]);
+        let mut kmeans = MiniBatchKMeans::new(2, 64, 16).with_weights(weights);
+        let fit = kmeans.fit_auto(&orders, dec!(100.5), dec!(1), 2..=6);
+        assert_eq!(fit.num_clusters, 3);
+        assert!(fit.silhouette > 0.9);
+        for group in fit.labels.chunks(6) {
+            assert!(group.iter().all(|&l| l == group[0]));
+        }
+
+        // Too few orders to compare two clusters: falls back to the low end
+        let fit = kmeans.fit_auto(&orders[..3], dec!(100.5), dec!(1), 2..=6);
+        assert_eq!((fit.num_clusters, fit.silhouette), (2, 0.0));
+        assert_eq!(fit.labels.len(), 3);
+    }
+
+    #[test]
+    fn test_empty_book_gives_no_labels() {
+        let mut kmeans = MiniBatchKMeans::new(3, 64, 16).with_seed(1);
+        assert!(kmeans.fit(&[], dec!(100), dec!(1)).is_empty());
+        // Orders with nothing left resting are not clustered either
+        let gone = [order(dec!(99), dec!(0), 0, 0), order(dec!(98), dec!(-2), 0, 0)];
+        assert!(kmeans.fit(&gone, dec!(100), dec!(1)).is_empty());
+        let fit = kmeans.fit_auto(&gone, dec!(100), dec!(1), 2..=6);
+        assert!(fit.labels.is_empty());
+        assert_eq!((fit.num_clusters, fit.silhouette), (0, 0.0));
+    }
+
+    #[test]
+    fn test_fewer_points_than_clusters() {
+        let orders = [
+            order(dec!(99), dec!(5), 0, 0),
+            order(dec!(98), dec!(1), 0, 0),
+            order(dec!(97), dec!(20), 0, 0),
+        ];
+        let weights = FeatureWeights([1.0, 0.0, 0.0, 0.0, 0.0]);
+        let mut kmeans = MiniBatchKMeans::new(5, 64, 16).with_weights(weights).with_seed(1);
+        // Every order gets a cluster of its own, in size order; the spare ids stay unused
+        assert_eq!(kmeans.fit(&orders, dec!(100), dec!(1)), vec![2, 0, 4]);
+
+        // A single order, with one or several clusters
+        for num_clusters in [1, 4] {
+            let mut kmeans = MiniBatchKMeans::new(num_clusters, 64, 16).with_seed(1);
+            assert_eq!(kmeans.fit(&orders[..1], dec!(100), dec!(1)), vec![0]);
+        }
+    }
+
+    #[test]
+    fn test_cold_start_labels_follow_size() {
+        // Whatever order the book lists them in, a fresh model numbers clusters by size
+        let sizes = [dec!(30), dec!(1), dec!(300), dec!(1.1), dec!(29), dec!(310)];
+        let orders: Vec<OrderSample> =
+            sizes.iter().map(|&qty| order(dec!(99), qty, 0, 0)).collect();
+        let weights = FeatureWeights([1.0, 0.0, 0.0, 0.0, 0.0]);
+        let mut kmeans = MiniBatchKMeans::new(3, 64, 16).with_weights(weights).with_seed(1);
+        assert_eq!(kmeans.fit(&orders, dec!(100), dec!(1)), vec![1, 0, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_seeded_fits_repeat() {
+        // Sizes and ages scattered enough that small batches land differently per draw
+        let orders: Vec<OrderSample> = (0..200u64)
+            .map(|i| {
+                let qty = Decimal::from((i * 7919) % 97 + 1);
+                order(dec!(100) - Decimal::from(i % 20), qty, (i * 31) % 50, (i % 5) as usize)
+            })
+            .collect();
+        let fit_twice = |seed: u64| {
+            let mut kmeans = MiniBatchKMeans::new(4, 8, 4).with_seed(seed);
+            let first = kmeans.fit(&orders, dec!(100.5), dec!(1));
+            let second = kmeans.fit(&orders[50..], dec!(100.5), dec!(1));
+            (first, second)
+        };
+        assert_eq!(fit_twice(42), fit_twice(42));
+
+        // Reseeding through the params replays the same draws on the same model state
+        let params = ClusterParams {
+            batch_size: 8,
+            max_iter: 4,
+            weights: FeatureWeights::default(),
+            num_clusters: 2..=5,
+            eps: 0.0,
+            min_points: 0,
+            seed: Some(42),
+        };
+        let mut kmeans = MiniBatchKMeans::new(1, 0, 0);
+        let mut fresh = kmeans.clone();
+        let first = kmeans.cluster(&orders, dec!(100.5), dec!(1), &params);
+        let again = fresh.cluster(&orders, dec!(100.5), dec!(1), &params);
+        assert_eq!(first.labels, again.labels);
+        assert_eq!(first.num_clusters, again.num_clusters);
+    }
+
+    #[test]
+    fn test_build_clustered_orders_skips_empty_orders() {
+        let orders = [
+            order(dec!(99), dec!(2), 0, 0),
+            order(dec!(99), dec!(-1), 0, 1),
+            order(dec!(98), dec!(0), 0, 0),
+            order(dec!(97), dec!(5), 0, 0),
+        ];
+        let clustered = build_clustered_orders(&orders, &[1, 0]);
+        assert_eq!(clustered[&dec!(99)], [(dec!(2), 1)]);
+        assert!(clustered[&dec!(98)].is_empty());
+        assert_eq!(clustered[&dec!(97)], [(dec!(5), 0)]);
+    }
+}
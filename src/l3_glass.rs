@@ -0,0 +1,383 @@
+use ahash::AHashMap as HashMap;
+use std::collections::VecDeque;
+
+use crate::glass::{Glass, GlassConfig, SnapshotError};
+
+/// One estimated resting order. Sizes are signed because a reduction larger than any single
+/// order is taken out of the largest one, which can leave it below zero.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EstimatedOrder {
+    pub id: u64,
+    pub size: i64,
+}
+
+/// Part of an estimated order taken by a simulated market order.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrderFill {
+    pub key: u32,
+    pub id: u64,
+    pub filled: u64,
+    /// What is left of the order after the fill.
+    pub remaining: u64,
+}
+
+/// Result of a simulated market order.
+#[cfg(test)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Execution {
+    pub filled: u64,
+    /// Exact, like [`crate::glass::Sweep::notional`].
+    pub notional: u128,
+    /// Fills in the order they happened: best level first, FIFO within a level.
+    pub fills: Vec<OrderFill>,
+}
+
+// L3 view of a book side: the Glass holds each level's total, and every level the Glass knows
+// owns a FIFO of estimated orders under the same key. The queue sizes always sum to the level
+// total, so simulated fills and the L3 estimate stay in step.
+pub struct L3Glass {
+    levels: Glass,
+    queues: HashMap<u32, VecDeque<EstimatedOrder>>,
+    next_id: u64,
+}
+
+impl Default for L3Glass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl L3Glass {
+    pub fn new() -> Self {
+        Self::with_config(GlassConfig::default())
+    }
+
+    pub fn with_config(config: GlassConfig) -> Self {
+        Self {
+            levels: Glass::with_config(config),
+            queues: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Level totals, for the read-only queries (iteration, sweeps, best prices).
+    pub fn levels(&self) -> &Glass {
+        &self.levels
+    }
+
+    pub fn get(&self, key: u32) -> Option<u64> {
+        self.levels.get(key)
+    }
+
+    /// How many orders joined this side after `order`.
+    pub fn age(&self, order: &EstimatedOrder) -> u64 {
+        self.next_id - 1 - order.id
+    }
+
+    /// Estimated orders at `key`, oldest first.
+    pub fn orders(&self, key: u32) -> Option<&VecDeque<EstimatedOrder>> {
+        self.queues.get(&key)
+    }
+
+    fn new_order(&mut self, size: i64) -> EstimatedOrder {
+        let id = self.next_id;
+        self.next_id += 1;
+        EstimatedOrder { id, size }
+    }
+
+    /// Replaces the level with a single order of `size`; zero removes it.
+    pub fn set_level(&mut self, key: u32, size: u64) {
+        self.levels.insert(key, size);
+        if size == 0 {
+            self.queues.remove(&key);
+        } else {
+            let order = self.new_order(size as i64);
+            self.queues.insert(key, VecDeque::from([order]));
+        }
+    }
+
+    /// Moves the level to `size`, estimating which orders joined or left: growth is a new
+    /// order at the back, a reduction cancels the newest order of exactly that size or else
+    /// shrinks the largest one.
+    pub fn apply_level(&mut self, key: u32, size: u64) {
+        let old_size = match self.levels.get(key) {
+            Some(old_size) if size > 0 => old_size,
+            _ => return self.set_level(key, size),
+        };
+        if old_size == size {
+            return;
+        }
+        if old_size > size {
+            let queue = self.queues.get_mut(&key).expect("every level has a queue");
+            let change = (old_size - size) as i64;
+            if let Some(pos) = queue.iter().rposition(|order| order.size == change) {
+                queue.remove(pos); // Removes the last occurrence of the value
+            } else {
+                let largest = queue.iter().map(|order| order.size).max().unwrap();
+                let largest_pos = queue.iter().position(|order| order.size == largest).unwrap();
+                let mut order = queue.remove(largest_pos).unwrap();
+                order.size -= change;
+                queue.push_back(order);
+            }
+        } else {
+            let order = self.new_order((size - old_size) as i64);
+            self.queues.get_mut(&key).expect("every level has a queue").push_back(order);
+        }
+        self.levels.update_value(key, |v| *v = size);
+    }
+}
+
+// Checkpointing. Little-endian: the level Glass as len: u64 followed by its own checkpoint,
+// next_id: u64, then one queue per level in key order, each as len: u64 followed by
+// (id: u64, size: i64) pairs from the front. Restoring gives back the same estimate, ids and
+// ages included.
+impl L3Glass {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let glass = self.levels.to_bytes();
+        let mut out = Vec::with_capacity(16 + glass.len() + self.queues.len() * 24);
+        out.extend_from_slice(&(glass.len() as u64).to_le_bytes());
+        out.extend_from_slice(&glass);
+        out.extend_from_slice(&self.next_id.to_le_bytes());
+        for (key, _) in self.levels.iter() {
+            let queue = &self.queues[&key];
+            out.extend_from_slice(&(queue.len() as u64).to_le_bytes());
+            for order in queue {
+                out.extend_from_slice(&order.id.to_le_bytes());
+                out.extend_from_slice(&order.size.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// Restores a side written by [`L3Glass::to_bytes`]. Every level needs a non-empty queue
+    /// summing to its total, with ids below `next_id`.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<L3Glass, SnapshotError> {
+        fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], SnapshotError> {
+            let (head, rest) = bytes.split_first_chunk::<N>().ok_or(SnapshotError::Truncated)?;
+            *bytes = rest;
+            Ok(*head)
+        }
+        fn len(bytes: &mut &[u8]) -> Result<usize, SnapshotError> {
+            usize::try_from(u64::from_le_bytes(take(bytes)?)).map_err(|_| SnapshotError::Corrupt)
+        }
+
+        let glass_len = len(&mut bytes)?;
+        let (glass, rest) = bytes.split_at_checked(glass_len).ok_or(SnapshotError::Truncated)?;
+        bytes = rest;
+        let levels = Glass::from_bytes(glass)?;
+        let next_id = u64::from_le_bytes(take(&mut bytes)?);
+        let mut queues = HashMap::new();
+        for (key, total) in levels.iter() {
+            let queue_len = len(&mut bytes)?;
+            if queue_len == 0 {
+                return Err(SnapshotError::Corrupt);
+            }
+            // Each order takes 16 bytes; checked before allocating for a bogus length
+            if queue_len > bytes.len() / 16 {
+                return Err(SnapshotError::Truncated);
+            }
+            let mut queue = VecDeque::with_capacity(queue_len);
+            let mut sum = 0i128;
+            for _ in 0..queue_len {
+                let id = u64::from_le_bytes(take(&mut bytes)?);
+                let size = i64::from_le_bytes(take(&mut bytes)?);
+                if id >= next_id {
+                    return Err(SnapshotError::Corrupt);
+                }
+                sum += size as i128;
+                queue.push_back(EstimatedOrder { id, size });
+            }
+            if sum != total as i128 {
+                return Err(SnapshotError::Corrupt);
+            }
+            queues.insert(key, queue);
+        }
+        if !bytes.is_empty() {
+            return Err(SnapshotError::Corrupt);
+        }
+        Ok(Self { levels, queues, next_id })
+    }
+}
+
+// Simulated market orders, reporting which estimated orders each one fills. Nothing in the app
+// executes against the estimate yet, so they are only built for the tests.
+#[cfg(test)]
+impl L3Glass {
+    /// Buys `shares` from the lowest key up, taking orders FIFO within each level.
+    pub fn buy_shares(&mut self, shares: u64) -> Execution {
+        self.execute(shares, true)
+    }
+
+    /// Sells `shares` from the highest key down, taking orders FIFO within each level.
+    pub fn sell_shares(&mut self, shares: u64) -> Execution {
+        self.execute(shares, false)
+    }
+
+    fn execute(&mut self, mut shares: u64, ascending: bool) -> Execution {
+        let mut execution = Execution::default();
+        while shares > 0 {
+            let best = if ascending { self.levels.min() } else { self.levels.max() };
+            let Some((key, avail)) = best else {
+                break;
+            };
+            let take = avail.min(shares);
+            self.take_from_queue(key, take, &mut execution.fills);
+            if take == avail {
+                self.levels.remove(key);
+                self.queues.remove(&key);
+            } else {
+                self.levels.update_value(key, |v| *v -= take);
+            }
+            shares -= take;
+            execution.filled += take;
+            execution.notional += key as u128 * take as u128;
+        }
+        execution
+    }
+
+    // Orders estimated at or below zero hold nothing and are passed over; they stay queued so
+    // the sizes keep summing to the level total.
+    fn take_from_queue(&mut self, key: u32, mut take: u64, fills: &mut Vec<OrderFill>) {
+        let queue = self.queues.get_mut(&key).expect("every level has a queue");
+        let mut pos = 0;
+        while take > 0 && pos < queue.len() {
+            let order = &mut queue[pos];
+            if order.size <= 0 {
+                pos += 1;
+                continue;
+            }
+            let hit = (order.size as u64).min(take);
+            order.size -= hit as i64;
+            take -= hit;
+            fills.push(OrderFill {
+                key,
+                id: order.id,
+                filled: hit,
+                remaining: order.size as u64,
+            });
+            if order.size == 0 {
+                queue.remove(pos);
+            } else {
+                pos += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizes(book: &L3Glass, key: u32) -> Vec<i64> {
+        book.orders(key).unwrap().iter().map(|order| order.size).collect()
+    }
+
+    #[test]
+    fn test_apply_level_estimates_queue() {
+        let mut book = L3Glass::new();
+        book.apply_level(100, 5);
+        book.apply_level(100, 8);
+        book.apply_level(100, 10);
+        assert_eq!(sizes(&book, 100), vec![5, 3, 2]);
+        // Exact match cancels the newest order of that size
+        book.apply_level(100, 7);
+        assert_eq!(sizes(&book, 100), vec![5, 2]);
+        // Otherwise the largest order shrinks and moves to the back
+        book.apply_level(100, 3);
+        assert_eq!(sizes(&book, 100), vec![2, 1]);
+        assert_eq!(book.get(100), Some(3));
+        book.apply_level(100, 0);
+        assert_eq!(book.orders(100), None);
+        assert_eq!(book.get(100), None);
+    }
+
+    #[test]
+    fn test_checkpoint_keeps_queues() {
+        let mut book = L3Glass::with_config(GlassConfig { hot_levels: 4, ..GlassConfig::default() });
+        for key in 0..10 {
+            book.apply_level(key, 5);
+            book.apply_level(key, 9);
+            book.apply_level(key, 6);
+        }
+        let bytes = book.to_bytes();
+        let restored = L3Glass::from_bytes(&bytes).unwrap();
+        for key in 0..10 {
+            assert_eq!(restored.orders(key), book.orders(key));
+            let order = &restored.orders(key).unwrap()[0];
+            assert_eq!(restored.age(order), book.age(order));
+        }
+        assert_eq!(restored.to_bytes(), bytes);
+
+        assert_eq!(L3Glass::from_bytes(&bytes[..bytes.len() - 1]).err(), Some(SnapshotError::Truncated));
+        // The last order's size no longer sums to its level total
+        let mut miscounted = bytes;
+        let last = miscounted.len() - 8;
+        miscounted[last..].copy_from_slice(&7i64.to_le_bytes());
+        assert_eq!(L3Glass::from_bytes(&miscounted).err(), Some(SnapshotError::Corrupt));
+    }
+
+    #[test]
+    fn test_buy_fills_fifo_and_reports_orders() {
+        let mut book = L3Glass::new();
+        book.apply_level(10, 4);
+        book.apply_level(10, 10);
+        book.set_level(20, 5);
+        let first = book.orders(10).unwrap()[0].id;
+        let second = book.orders(10).unwrap()[1].id;
+        let third = book.orders(20).unwrap()[0].id;
+
+        let execution = book.buy_shares(7);
+        assert_eq!(execution.filled, 7);
+        assert_eq!(execution.notional, 70);
+        assert_eq!(
+            execution.fills,
+            vec![
+                OrderFill { key: 10, id: first, filled: 4, remaining: 0 },
+                OrderFill { key: 10, id: second, filled: 3, remaining: 3 },
+            ]
+        );
+        assert_eq!(book.get(10), Some(3));
+
+        let execution = book.buy_shares(100);
+        assert_eq!(execution.filled, 8);
+        assert_eq!(execution.notional, 3 * 10 + 5 * 20);
+        assert_eq!(execution.fills.last(), Some(&OrderFill { key: 20, id: third, filled: 5, remaining: 0 }));
+        assert_eq!(book.levels().min(), None);
+        assert_eq!(book.orders(10), None);
+    }
+
+    #[test]
+    fn test_fills_skip_overdrawn_orders() {
+        let mut book = L3Glass::new();
+        book.apply_level(10, 2);
+        book.apply_level(10, 5);
+        book.apply_level(10, 9);
+        // No order of size 7: the largest (4) absorbs it and goes negative
+        book.apply_level(10, 2);
+        assert_eq!(sizes(&book, 10), vec![2, 3, -3]);
+        let execution = book.sell_shares(1);
+        assert_eq!(execution.fills[0].filled, 1);
+        assert_eq!(sizes(&book, 10), vec![1, 3, -3]);
+        let execution = book.sell_shares(1);
+        assert_eq!(execution.filled, 1);
+        assert_eq!(book.orders(10), None);
+    }
+
+    #[test]
+    fn test_fills_through_preempt() {
+        let mut book = L3Glass::with_config(GlassConfig { hot_levels: 4, ..GlassConfig::default() });
+        for key in 0..10 {
+            book.set_level(key, 2);
+            book.apply_level(key, 3);
+        }
+        let execution = book.sell_shares(9);
+        let keys: Vec<u32> = execution.fills.iter().map(|fill| fill.key).collect();
+        assert_eq!(keys, vec![9, 9, 8, 8, 7, 7]);
+        assert_eq!(execution.notional, 3 * (9 + 8 + 7));
+        let execution = book.buy_shares(4);
+        assert_eq!(execution.fills.iter().map(|fill| fill.filled).collect::<Vec<_>>(), vec![2, 1, 1]);
+        assert_eq!(sizes(&book, 1), vec![1, 1]);
+        assert_eq!(book.levels().min(), Some((1, 2)));
+    }
+}
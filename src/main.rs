@@ -1,23 +1,55 @@
+mod book_feed;
+mod cluster_summary;
+mod cluster_worker;
+mod clustering;
+mod dbscan;
+mod gmm;
 mod kmeans;
 mod model;
 mod ring;
 mod strategy;
 mod glass;
+mod l3_glass;
 mod exchange_manager;
-
+mod tick_book;
+mod hawkes;
+mod heatmap;
+mod metrics_history;
+mod order_flow;
+mod order_tooltip;
+mod publish;
+mod replay;
+mod trade_flow;
+
+use crate::book_feed::{BookFeed, BookView, FeedConfig, FeedMessage, TouchEvent};
+use crate::cluster_worker::{ClusterJob, ClusterWorker, ClusteredOrders, Labelling, SideLabelling};
+use crate::cluster_summary::Participant;
+use crate::clustering::{ClusterMethod, ClusterParams, NOISE};
+use crate::glass::BitScan;
+use crate::hawkes::HawkesEstimator;
+use crate::kmeans::{Feature, FeatureWeights};
+use crate::heatmap::LiquidityHeatmap;
+use crate::metrics_history::{MetricSeries, MetricsHistory, SERIES_COUNT};
+use crate::model::*;
+use crate::order_flow::OrderFlowImbalance;
+use crate::order_tooltip::{OrderTooltip, QueueAhead};
+use crate::ring::*;
+use crate::trade_flow::TradeFlow;
 use eframe::egui;
 use egui::{Align2, Color32};
-use egui_plot::{Bar, BarChart, Plot, PlotPoint, Text};
+use egui_plot::{Bar, BarChart, Line, Plot, PlotPoint, PlotPoints, Text};
 use futures_util::{SinkExt, StreamExt};
-use once_cell::sync::Lazy;
 use reqwest::blocking;
 use rust_decimal::Decimal;
 use rust_decimal::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self as std_mpsc, Receiver as StdReceiver, Sender as StdSender};
 use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message as WsMessage};
 
@@ -45,21 +77,13 @@ struct Filter {
 #[derive(Deserialize, Serialize, Clone)]
 struct BinanceSubscriptionMessage {
     method: String,
-    params: Vec<String>
-}
-
-#[derive(Deserialize)]
-struct OrderBookSnapshot {
-    #[serde(rename = "lastUpdateId")]
-    last_update_id: u64,
-    bids: Vec<Vec<Decimal>>,
-    asks: Vec<Vec<Decimal>>,
+    params: Vec<String>,
+    id: u64,
 }
 
+// Depth goes to the book feed; what is left for the UI thread
 enum AppMessage {
-    Snapshot(OrderBookSnapshot),
-    Update(DepthUpdate),
-    //TradeUpdate(TradeUpdate)
+    TradeUpdate(TradeUpdate),
 }
 
 enum Control {
@@ -67,39 +91,46 @@ enum Control {
     ChangeSymbol(String),
 }
 
-static BID_COLORS: Lazy<Vec<Color32>> = Lazy::new(|| {
-    vec![
-        Color32::from_rgb(222, 235, 247), // Light Blue
-        Color32::from_rgb(204, 227, 245), // Lighter Blue
-        Color32::from_rgb(158, 202, 225), // Blue
-        Color32::from_rgb(129, 189, 231), // Light Medium Blue
-        Color32::from_rgb(107, 174, 214), // Medium Blue
-        Color32::from_rgb(78, 157, 202),  // Medium Deep Blue
-        Color32::from_rgb(49, 130, 189),  // Deep Blue
-        Color32::from_rgb(33, 113, 181),  // Darker Deep Blue
-        Color32::from_rgb(16, 96, 168),   // Dark Blue
-        Color32::from_rgb(8, 81, 156),    // Darkest Blue
-    ]
-});
-
-static ASK_COLORS: Lazy<Vec<Color32>> = Lazy::new(|| {
-    vec![
-        Color32::from_rgb(254, 230, 206), // Light Orange
-        Color32::from_rgb(253, 216, 186), // Lighter Orange
-        Color32::from_rgb(253, 174, 107), // Orange
-        Color32::from_rgb(253, 159, 88),  // Light Deep Orange
-        Color32::from_rgb(253, 141, 60),  // Deep Orange
-        Color32::from_rgb(245, 126, 47),  // Medium Red-Orange
-        Color32::from_rgb(230, 85, 13),   // Red-Orange
-        Color32::from_rgb(204, 75, 12),   // Darker Red-Orange
-        Color32::from_rgb(179, 65, 10),   // Dark Red
-        Color32::from_rgb(166, 54, 3),    // Darkest Red
-    ]
-});
+// Light to dark ends of the cluster palettes; cluster ids get evenly spaced slots between them
+const BID_PALETTE: ([f32; 3], [f32; 3]) = ([222.0, 235.0, 247.0], [8.0, 81.0, 156.0]);
+const ASK_PALETTE: ([f32; 3], [f32; 3]) = ([254.0, 230.0, 206.0], [166.0, 54.0, 3.0]);
+// Shared by both sides when they are clustered together, so equal ids get equal colours.
+// Teal to purple, with no yellow that could pass for the gold largest-order marker
+const JOINT_PALETTE: ([f32; 3], [f32; 3]) = ([178.0, 226.0, 226.0], [84.0, 39.0, 143.0]);
+
+// Rolling window for the buy/sell trade arrival Hawkes fits
+const HAWKES_WINDOW_MS: u64 = 60_000;
+// How often the Hawkes parameters are refitted; frames in between only update the intensity
+const HAWKES_REFIT_MS: u64 = 250;
+// How far back the cumulative volume delta chart reaches
+const CVD_HISTORY_MS: u64 = 15 * 60_000;
+const CVD_PLOT_HEIGHT: f32 = 160.0;
+// Metric history charts: sampling cadence and the longest selectable lookback
+const HISTORY_SAMPLE_MS: u64 = 250;
+const HISTORY_MAX_AGE_MS: u64 = 60 * 60_000;
+// Liquidity heatmap: sampling cadence, visible time span and levels kept per side
+const HEATMAP_SAMPLE_MS: u64 = 250;
+const HEATMAP_WINDOW_MS: u64 = 2 * 60_000;
+const HEATMAP_DEPTH: usize = 100;
+// Levels per side copied out of the book each frame for the table, chart and clustering
+const BOOK_VIEW_DEPTH: usize = 100;
+// Default range the cluster count is chosen from
+const KMEANS_CLUSTERS: RangeInclusive<usize> = 2..=10;
+// Fastest cadence at which book snapshots are handed to the clustering worker
+const KMEANS_INTERVAL_MS: u64 = 500;
 
 fn main() -> eframe::Result {
     // Fetch the symbol from command-line arguments or default to DOGEUSDT
     let args: Vec<String> = env::args().collect();
+    if args.len() > 2 && args[1] == "--replay" {
+        // The live feed's depth, so the replay clusters the same orders the app does
+        let depth = BOOK_VIEW_DEPTH.max(HEATMAP_DEPTH);
+        if let Err(err) = replay::run(Path::new(&args[2]), depth, KMEANS_CLUSTERS) {
+            println!("Error: {}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     let symbol: String = if args.len() > 1 {
         args[1].to_ascii_lowercase()
     } else {
@@ -114,65 +145,166 @@ fn main() -> eframe::Result {
     )
 }
 
+// GLASS_BIT_SCAN=portable swaps the BMI/LZCNT intrinsics for plain bit operations, e.g. to
+// compare the two on a live book or to rule the intrinsics out when chasing a bug
+fn glass_bit_scan() -> BitScan {
+    match env::var("GLASS_BIT_SCAN").as_deref() {
+        Err(_) | Ok("detect") => BitScan::Detect,
+        Ok("portable") => BitScan::Portable,
+        Ok(other) => {
+            println!("Warning: GLASS_BIT_SCAN={other} is neither detect nor portable, detecting CPU features");
+            BitScan::Detect
+        }
+    }
+}
+
 struct MyApp {
     symbol: String,
     edited_symbol: String,
-    bids: BTreeMap<Decimal, VecDeque<Decimal>>,
-    asks: BTreeMap<Decimal, VecDeque<Decimal>>,
-    last_applied_u: u64,
-    is_synced: bool,
+    // Owns the book on its own thread; the UI reads the views it publishes
+    feed: BookFeed,
     rx: StdReceiver<AppMessage>,
-    update_buffer: VecDeque<DepthUpdate>,
     orderbook_metrics: OrderbookMetrics,
     order_arrival_ring: LambdaRing,
     trade_metrics: TradeMetrics,
     trades_ring: LambdaRing,
+    buy_hawkes: HawkesEstimator,
+    sell_hawkes: HawkesEstimator,
+    last_trade_time: u64,
+    trade_flow: TradeFlow,
+    imbalance_windows_secs: [u64; 3],
+    order_flow: OrderFlowImbalance,
+    ofi_windows_secs: [u64; 3],
+    sweep_qty: f64,
+    depth_near_ticks: u32,
+    metrics_history: MetricsHistory,
+    history_selected: [bool; SERIES_COUNT],
+    history_lookback_secs: u64,
+    heatmap: LiquidityHeatmap,
+    show_heatmap: bool,
     control_tx: Sender<Control>,
     kmeans_mode: bool,
+    cluster_method: ClusterMethod,
+    joint_clusters: bool,
     price_prec: usize,
     qty_prec: usize,
+    tick_size: Decimal,
+    step_size: Decimal,
     brighter_step: usize,
     batch_size: usize,
     max_iter: usize,
+    feature_weights: FeatureWeights,
+    min_clusters: usize,
+    max_clusters: usize,
+    density_eps: f64,
+    density_min_points: usize,
+    // From KMEANS_SEED, for reproducible labels when replaying or comparing runs
+    cluster_seed: Option<u64>,
+    // Fits off the UI thread; the chart draws its last completed labelling
+    cluster_worker: ClusterWorker,
 }
 
 impl MyApp {
     fn new(cc: &eframe::CreationContext<'_>, symbol: String) -> Self {
+        let mut price_prec = 2;
+        let mut qty_prec = 2;
+        let mut tick_size = dec!(0.01);
+        let mut step_size = dec!(0.01);
+        Self::fetch_precision(
+            &symbol.to_uppercase(),
+            &mut price_prec,
+            &mut qty_prec,
+            &mut tick_size,
+            &mut step_size,
+        );
+
         let (tx, rx) = std_mpsc::channel();
         let (control_tx, control_rx) = mpsc::channel(1);
+        let gap_tx = control_tx.clone();
+        let publish_ctx = cc.egui_ctx.clone();
+        let feed = BookFeed::spawn(
+            FeedConfig {
+                tick_size,
+                step_size,
+                bit_scan: glass_bit_scan(),
+                depth: BOOK_VIEW_DEPTH.max(HEATMAP_DEPTH),
+                // BOOK_RECORD_DIR keeps the depth stream and book checkpoints for --replay
+                record_dir: env::var_os("BOOK_RECORD_DIR").map(PathBuf::from),
+            },
+            move || {
+                let _ = gap_tx.try_send(Control::Refetch);
+            },
+            move || publish_ctx.request_repaint(),
+        );
+        let depth_tx = feed.sender();
         let ctx = cc.egui_ctx.clone();
+        let repaint_ctx = cc.egui_ctx.clone();
         let s = symbol.clone();
         thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
             rt.block_on(async {
-                Self::fetch_and_stream_loop(&tx, &ctx, control_rx, s).await;
+                Self::fetch_and_stream_loop(&tx, &depth_tx, &ctx, control_rx, s).await;
             });
         });
 
-        let mut price_prec = 2;
-        let mut qty_prec = 2;
-        Self::fetch_precision(&symbol.to_uppercase(), &mut price_prec, &mut qty_prec);
-
         Self {
             symbol: symbol.clone(),
             edited_symbol: symbol,
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
-            last_applied_u: 0,
-            is_synced: false,
+            feed,
             rx,
-            update_buffer: VecDeque::new(),
+            orderbook_metrics: OrderbookMetrics::default(),
+            order_arrival_ring: LambdaRing::new(),
+            trade_metrics: TradeMetrics::default(),
+            trades_ring: LambdaRing::new(),
+            buy_hawkes: HawkesEstimator::new(HAWKES_WINDOW_MS, HAWKES_REFIT_MS),
+            sell_hawkes: HawkesEstimator::new(HAWKES_WINDOW_MS, HAWKES_REFIT_MS),
+            last_trade_time: 0,
+            trade_flow: TradeFlow::new(CVD_HISTORY_MS),
+            imbalance_windows_secs: [1, 10, 60],
+            order_flow: OrderFlowImbalance::new(),
+            ofi_windows_secs: [1, 10, 60],
+            sweep_qty: 0.0,
+            depth_near_ticks: 10,
+            metrics_history: MetricsHistory::new(HISTORY_SAMPLE_MS, HISTORY_MAX_AGE_MS),
+            history_selected: MetricSeries::ALL.map(|series| {
+                matches!(
+                    series,
+                    MetricSeries::MidPrice | MetricSeries::Spread | MetricSeries::BookImbalance
+                )
+            }),
+            history_lookback_secs: 300,
+            heatmap: LiquidityHeatmap::new(HEATMAP_SAMPLE_MS, HEATMAP_WINDOW_MS, HEATMAP_DEPTH),
+            show_heatmap: false,
             control_tx,
             kmeans_mode: false,
+            cluster_method: ClusterMethod::KMeans,
+            joint_clusters: false,
             price_prec,
             qty_prec,
+            tick_size,
+            step_size,
             brighter_step: 5,
             batch_size: 1024,
             max_iter: 1024,
+            feature_weights: FeatureWeights::default(),
+            min_clusters: *KMEANS_CLUSTERS.start(),
+            max_clusters: *KMEANS_CLUSTERS.end(),
+            density_eps: 0.05,
+            density_min_points: 5,
+            cluster_seed: clustering::env_seed(),
+            cluster_worker: ClusterWorker::spawn(KMEANS_INTERVAL_MS, move || {
+                repaint_ctx.request_repaint()
+            }),
         }
     }
 
-    fn fetch_precision(symbol: &str, price_prec: &mut usize, qty_prec: &mut usize) {
+    fn fetch_precision(
+        symbol: &str,
+        price_prec: &mut usize,
+        qty_prec: &mut usize,
+        tick_size_out: &mut Decimal,
+        step_size_out: &mut Decimal,
+    ) {
         let url = "https://fapi.binance.com/fapi/v1/exchangeInfo".to_string();
         if let Ok(resp) = blocking::get(&url) {
             if let Ok(info) = resp.json::<ExchangeInfo>() {
@@ -181,6 +313,9 @@ impl MyApp {
             for filter in sym_info.filters {
                 if filter.filter_type == "PRICE_FILTER" {
                     if let Some(ts) = filter.tick_size {
+                        if let Ok(tick) = ts.parse::<Decimal>() && tick > Decimal::ZERO {
+                            *tick_size_out = tick.normalize();
+                        }
                         let tick_size = ts.parse::<f64>().unwrap_or(1.0);
                         if tick_size > 0.0 {
                             *price_prec = (-tick_size.log10()).ceil() as usize;
@@ -189,6 +324,9 @@ impl MyApp {
                 } else if filter.filter_type == "LOT_SIZE" {
                     if let Some(ss) = filter.step_size {
                 {
+                    if let Ok(step) = ss.parse::<Decimal>() && step > Decimal::ZERO {
+                        *step_size_out = step.normalize();
+                    }
                     let step_size = ss.parse::<f64>().unwrap_or(1.0);
                     if step_size > 0.0 {
                         *qty_prec = (-step_size.log10()).ceil() as usize;
@@ -200,6 +338,7 @@ impl MyApp {
 
     async fn fetch_and_stream_loop(
         tx: &StdSender<AppMessage>,
+        depth_tx: &StdSender<FeedMessage>,
         ctx: &egui::Context,
         mut control_rx: Receiver<Control>,
         mut symbol: String,
@@ -218,26 +357,37 @@ impl MyApp {
 
             let trade_sub_message = BinanceSubscriptionMessage {
                 method: "SUBSCRIBE".to_owned(),
-                params: vec![format!("{symbol}@aggTrade")]
+                params: vec![format!("{symbol}@aggTrade")],
+                id: 1,
             };
 
-            ws_stream.send(WsMessage::Text(serde_json::to_string(&trade_sub_message).into())).await;
-
-            println!("Binance trade stream added");
+            let sub_text = serde_json::to_string(&trade_sub_message).unwrap();
+            if let Err(e) = ws_stream.send(WsMessage::Text(sub_text.into())).await {
+                println!("Trade subscription error: {e:?}");
+            } else {
+                println!("Binance trade stream added");
+            }
 
             let tx_clone = tx.clone();
+            let depth_tx_clone = depth_tx.clone();
             let ctx_clone = ctx.clone();
             let ws_handle = tokio::spawn(async move {
                 while let Some(result) = ws_stream.next().await {
                     match result {
                         Ok(message) => match message {
                             WsMessage::Text(text) => {
-                                match serde_json::from_str::<DepthUpdate>(&text) {
-                                    Ok(update) => {
-                                        tx_clone.send(AppMessage::Update(update)).unwrap();
-                                        ctx_clone.request_repaint();
+                                if let Ok(update) = serde_json::from_str::<DepthUpdate>(&text) {
+                                    depth_tx_clone.send(FeedMessage::Update(update)).unwrap();
+                                } else if text.contains("\"aggTrade\"") {
+                                    match serde_json::from_str::<TradeUpdate>(&text) {
+                                        Ok(trade) => {
+                                            tx_clone.send(AppMessage::TradeUpdate(trade)).unwrap();
+                                            ctx_clone.request_repaint();
+                                        }
+                                        Err(e) => println!("Trade JSON error: {e:?}"),
                                     }
-                                    Err(e) => println!("Update JSON error: {e:?}"),
+                                } else if !text.contains("\"result\"") {
+                                    println!("Unhandled message: {text}");
                                 }
                             }
                             WsMessage::Ping(payload) => {
@@ -270,7 +420,7 @@ impl MyApp {
                 Ok(resp) => match resp.json::<OrderBookSnapshot>().await {
                     Ok(snap) => {
                         println!("Snapshot fetched successfully.");
-                        tx.send(AppMessage::Snapshot(snap)).unwrap();
+                        depth_tx.send(FeedMessage::Snapshot(snap)).unwrap();
                     }
                     Err(e) => println!("Snapshot JSON error: {e:?}"),
                 },
@@ -293,85 +443,111 @@ impl MyApp {
             }
         }
     }
-
-    fn process_update(&mut self, update: DepthUpdate) {
-        if update.small_u < self.last_applied_u {
-            return;
-        }
-
-        if self.is_synced {
-            if (update.pu as u64) != self.last_applied_u {
-                println!(
-                    "Warning: Message gap detected! pu: {}, last: {}",
-                    update.pu, self.last_applied_u
-                );
-                self.update_buffer.clear();
-                let _ = self.control_tx.try_send(Control::Refetch);
-                return;
-            }
-            self.apply_update(&update);
-            self.last_applied_u = update.small_u;
-        } else if update.capital_u <= self.last_applied_u && self.last_applied_u <= update.small_u {
-            self.apply_update(&update);
-            self.last_applied_u = update.small_u;
-            self.is_synced = true;
-        } else {
-            println!(
-                "Initial gap detected! U: {}, u: {}, last: {}",
-                update.capital_u, update.small_u, self.last_applied_u
-            );
-            self.update_buffer.clear();
-            let _ = self.control_tx.try_send(Control::Refetch);
-        }
-    }
 }
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         while let Ok(msg) = self.rx.try_recv() {
             match msg {
-                AppMessage::Snapshot(snap) => {
-                    self.bids.clear();
-                    self.asks.clear();
-                    for bid in &snap.bids {
-                        let price = bid[0];
-                        let qty = bid[1];
-                        if qty > Decimal::ZERO {
-                            self.bids.insert(price, VecDeque::from(vec![qty]));
-                        }
-                    }
-                    for ask in &snap.asks {
-                        let price = ask[0];
-                        let qty = ask[1];
-                        if qty > Decimal::ZERO {
-                            self.asks.insert(price, VecDeque::from(vec![qty]));
-                        }
-                    }
-                    self.last_applied_u = snap.last_update_id;
-                    self.is_synced = false;
-
-                    while let Some(update) = self.update_buffer.pop_front() {
-                        self.process_update(update);
-                    }
+                AppMessage::TradeUpdate(trade) => {
+                    self.process_trade(&trade);
                 }
-                AppMessage::Update(update) => {
-                    if self.last_applied_u == 0 {
-                        self.update_buffer.push_back(update);
-                    } else {
-                        self.process_update(update);
-                    }
+            }
+        }
+        for touch in self.feed.touches() {
+            match touch {
+                TouchEvent::Moved(event_time, best) => self.order_flow.update(event_time, best),
+                TouchEvent::Resynced => {
+                    self.order_flow.reset();
+                    // Order ids start over with the new snapshot
+                    self.cluster_worker.resync();
                 }
             }
         }
+        let view = self.feed.view();
+        self.calculate_orderbook_metrics(&view);
+        let now_ms = self.now_ms();
+        self.calculate_trade_metrics(now_ms);
+        self.calculate_ofi_metrics();
+        self.metrics_history
+            .record(now_ms, &self.orderbook_metrics, &self.trade_metrics);
+        let bids = &view.bids.queues;
+        let asks = &view.asks.queues;
+        if view.synced {
+            self.heatmap.sample(now_ms, bids, asks);
+        }
         ctx.set_pixels_per_point(1.0); // temp zoom out option. could add a slider to control this or allow scrolling
+        egui::TopBottomPanel::bottom("metric_history_panel")
+            .resizable(true)
+            .default_height(280.0)
+            .show(ctx, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("History lookback:");
+                    ui.add(
+                        egui::Slider::new(
+                            &mut self.history_lookback_secs,
+                            10..=self.metrics_history.max_age_ms() / 1000,
+                        )
+                        .suffix("s"),
+                    );
+                    for (selected, series) in self.history_selected.iter_mut().zip(MetricSeries::ALL) {
+                        ui.checkbox(selected, series.label());
+                    }
+                });
+
+                let selected: Vec<MetricSeries> = MetricSeries::ALL
+                    .into_iter()
+                    .zip(self.history_selected)
+                    .filter_map(|(series, on)| on.then_some(series))
+                    .collect();
+                if selected.is_empty() {
+                    return;
+                }
+                let lookback_ms = self.history_lookback_secs * 1000;
+                let plot_height = (ui.available_height() / selected.len() as f32 - 4.0).max(60.0);
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    // One chart per series so each keeps its own y scale, all on the same time axis
+                    for series in selected {
+                        let points: PlotPoints =
+                            self.metrics_history.points(series, now_ms, lookback_ms).into();
+                        Plot::new(("metric_history", series.label()))
+                            .height(plot_height)
+                            .link_axis("metric_history", [true, false])
+                            .link_cursor("metric_history", [true, false])
+                            .include_x(-(self.history_lookback_secs as f64))
+                            .include_x(0.0)
+                            .allow_drag(false)
+                            .allow_scroll(false)
+                            .allow_zoom(false)
+                            .y_axis_label(series.label())
+                            .show(ui, |plot_ui| {
+                                plot_ui.line(Line::new(series.label(), points));
+                            });
+                    }
+                });
+            });
+        if self.show_heatmap {
+            egui::SidePanel::right("liquidity_heatmap_panel")
+                .resizable(true)
+                .default_width(520.0)
+                .show(ctx, |ui| {
+                    ui.heading("Liquidity heatmap");
+                    self.draw_heatmap(ui, now_ms);
+                });
+        }
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading(format!(
                 "{} Perpetual Order Book",
                 self.symbol.to_uppercase()
             ));
-            if ui.button("Toggle K-Means Mode").clicked() {
-                self.kmeans_mode = !self.kmeans_mode;
-            }
+            ui.horizontal(|ui| {
+                if ui.button("Toggle K-Means Mode").clicked() {
+                    self.kmeans_mode = !self.kmeans_mode;
+                }
+                if ui.button("Toggle Heatmap").clicked() {
+                    self.show_heatmap = !self.show_heatmap;
+                }
+            });
             ui.horizontal(|ui| {
                 ui.label("Symbol:");
                 ui.text_edit_singleline(&mut self.edited_symbol);
@@ -380,15 +556,23 @@ impl eframe::App for MyApp {
                         &self.edited_symbol.to_uppercase(),
                         &mut self.price_prec,
                         &mut self.qty_prec,
+                        &mut self.tick_size,
+                        &mut self.step_size,
                     );
                     let _ = self
                         .control_tx
                         .try_send(Control::ChangeSymbol(self.edited_symbol.clone()));
                     self.symbol = self.edited_symbol.clone();
-                    self.bids.clear();
-                    self.asks.clear();
-                    self.last_applied_u = 0;
-                    self.is_synced = false;
+                    self.feed.reset(self.tick_size, self.step_size);
+                    self.trades_ring = LambdaRing::new();
+                    self.buy_hawkes = HawkesEstimator::new(HAWKES_WINDOW_MS, HAWKES_REFIT_MS);
+                    self.sell_hawkes = HawkesEstimator::new(HAWKES_WINDOW_MS, HAWKES_REFIT_MS);
+                    self.last_trade_time = 0;
+                    self.trade_flow = TradeFlow::new(CVD_HISTORY_MS);
+                    self.order_flow = OrderFlowImbalance::new();
+                    self.metrics_history.clear();
+                    self.heatmap.clear();
+                    self.cluster_worker.reset();
                 }
             });
 
@@ -399,13 +583,73 @@ impl eframe::App for MyApp {
 
             if self.kmeans_mode {
                 ui.horizontal(|ui| {
-                    ui.label("K-means Batch Size:");
-                    ui.add(egui::Slider::new(&mut self.batch_size, 32..=2048));
+                    ui.label("Clustering:");
+                    egui::ComboBox::from_id_salt("cluster_method")
+                        .selected_text(self.cluster_method.label())
+                        .show_ui(ui, |ui| {
+                            for method in ClusterMethod::ALL {
+                                ui.selectable_value(&mut self.cluster_method, method, method.label());
+                            }
+                        });
+                    ui.checkbox(&mut self.joint_clusters, "Joint bid/ask").on_hover_text(
+                        "One model over both sides, so colours compare across the spread. \
+                         Lower the Side weight to let alike orders pair up across sides.",
+                    );
                 });
+                if self.cluster_method == ClusterMethod::KMeans {
+                    ui.horizontal(|ui| {
+                        ui.label("K-means Batch Size:");
+                        ui.add(egui::Slider::new(&mut self.batch_size, 32..=2048));
+                    });
+                }
+                if self.cluster_method != ClusterMethod::Density {
+                    ui.horizontal(|ui| {
+                        ui.label("Max Iter:");
+                        ui.add(egui::Slider::new(&mut self.max_iter, 64..=2048));
+                    });
+                }
                 ui.horizontal(|ui| {
-                    ui.label("K-means Max Iter:");
-                    ui.add(egui::Slider::new(&mut self.max_iter, 64..=2048));
+                    if self.cluster_method == ClusterMethod::Density {
+                        ui.label("Radius:");
+                        ui.add(egui::DragValue::new(&mut self.density_eps).range(0.001..=1.0).speed(0.001));
+                        ui.label("Min orders:");
+                        ui.add(egui::DragValue::new(&mut self.density_min_points).range(1..=100));
+                    } else {
+                        ui.label("Clusters:");
+                        ui.add(egui::DragValue::new(&mut self.min_clusters).range(1..=self.max_clusters));
+                        ui.label("to");
+                        ui.add(egui::DragValue::new(&mut self.max_clusters).range(self.min_clusters..=32));
+                    }
+                    if let Some(labelling) = self.cluster_worker.latest() {
+                        let sides = if labelling.joint {
+                            vec![("both sides", &labelling.bids)]
+                        } else {
+                            vec![("bids", &labelling.bids), ("asks", &labelling.asks)]
+                        };
+                        for (name, side) in sides {
+                            ui.label(format!(
+                                "{name}: k = {}, silhouette {:.2}",
+                                side.num_clusters, side.silhouette
+                            ));
+                        }
+                    }
+                    if self.cluster_worker.restarts() > 0 {
+                        ui.label(format!("worker restarted {} times after a failed fit", self.cluster_worker.restarts()));
+                    }
                 });
+                // The mixture only looks at log size
+                if self.cluster_method != ClusterMethod::GaussianMixture {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label("Feature weights:");
+                        for (weight, feature) in self.feature_weights.0.iter_mut().zip(Feature::ALL) {
+                            ui.label(feature.label());
+                            ui.add(egui::DragValue::new(weight).range(0.0..=5.0).speed(0.05));
+                        }
+                    });
+                }
+                egui::CollapsingHeader::new("Cluster summary")
+                    .default_open(true)
+                    .show(ui, |ui| self.draw_cluster_summary(ui));
             } else {
                 ui.horizontal(|ui| {
                     ui.label("Age mode brighter step %:");
@@ -442,20 +686,57 @@ impl eframe::App for MyApp {
                                 egui::Grid::new("trade_imbalance_periods")
                                     .striped(false)
                                     .show(ui, |ui| {
-                                        ui.label("Imbalance - ");
-                                        ui.label("Imbalance - ");
-                                        ui.label("Imbalance - ");
+                                        for window_secs in self.imbalance_windows_secs.iter_mut() {
+                                            ui.horizontal(|ui| {
+                                                ui.label("Imbalance -");
+                                                ui.add(
+                                                    egui::DragValue::new(window_secs)
+                                                        .range(1..=3600)
+                                                        .suffix("s"),
+                                                );
+                                            });
+                                        }
+                                        ui.label("CVD");
                                         ui.end_row();
 
-                                        ui.label(format!("{:.1$}", self.trade_metrics.imbalance, self.price_prec));
-                                        ui.label(format!("{:.1$}", self.trade_metrics.imbalance, self.price_prec));
-                                        ui.label(format!("{:.1$}", self.trade_metrics.imbalance, self.price_prec));
+                                        for (_, imbalance) in &self.trade_metrics.imbalance {
+                                            ui.label(format!("{imbalance:.3}"));
+                                        }
+                                        ui.label(format!("{:.1$}", self.trade_metrics.cvd, self.qty_prec));
                                         ui.end_row();
                                     })
                             })
                         })
                 });
 
+                ui.horizontal(|ui| {
+                    ui.label("Hawkes fit (60s):");
+                    egui::Grid::new("trade_hawkes")
+                        .striped(false)
+                        .show(ui, |ui| {
+                            ui.label("");
+                            ui.label("Intensity /s");
+                            ui.label("Branching ratio");
+                            ui.label("Baseline /s");
+                            ui.label("Excitation");
+                            ui.label("Decay /s");
+                            ui.end_row();
+
+                            for (side, fit) in [
+                                ("Buy", &self.trade_metrics.buy_hawkes),
+                                ("Sell", &self.trade_metrics.sell_hawkes),
+                            ] {
+                                ui.label(side);
+                                ui.label(format!("{:.3}", fit.intensity));
+                                ui.label(format!("{:.3}", fit.branching_ratio));
+                                ui.label(format!("{:.3}", fit.baseline));
+                                ui.label(format!("{:.3}", fit.excitation));
+                                ui.label(format!("{:.3}", fit.decay));
+                                ui.end_row();
+                            }
+                        })
+                });
+
                 ui.horizontal(|ui| {
                     ui.label("Trade descriptor:");
                     egui::Grid::new("trade_descriptor")
@@ -475,11 +756,60 @@ impl eframe::App for MyApp {
                         ui.label("Orderbook imbalance");
                         ui.label("Bid VWAP");
                         ui.label("Ask VWAP");
+                        for window_secs in self.ofi_windows_secs.iter_mut() {
+                            ui.horizontal(|ui| {
+                                ui.label("OFI -");
+                                ui.add(
+                                    egui::DragValue::new(window_secs)
+                                        .range(1..=3600)
+                                        .suffix("s"),
+                                );
+                            });
+                        }
+                        ui.horizontal(|ui| {
+                            ui.label("Sweep");
+                            ui.add(
+                                egui::DragValue::new(&mut self.sweep_qty)
+                                    .range(0.0..=f64::MAX)
+                                    .max_decimals(self.qty_prec),
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Depth ±");
+                            ui.add(
+                                egui::DragValue::new(&mut self.depth_near_ticks)
+                                    .range(0..=10_000)
+                                    .suffix(" ticks"),
+                            );
+                        });
                         ui.end_row();
 
                         ui.label(format!("{:.1$}", self.orderbook_metrics.imbalance, self.price_prec));
                         ui.label(format!("{:.1$}", self.orderbook_metrics.bid_vwap, self.price_prec));
                         ui.label(format!("{:.1$}", self.orderbook_metrics.ask_vwap, self.price_prec));
+                        for (_, ofi) in &self.orderbook_metrics.ofi {
+                            ui.label(format!("{:.1$}", ofi, self.qty_prec));
+                        }
+                        let sweep = |sweep: Option<(Decimal, Decimal)>| {
+                            sweep.map_or("-".to_string(), |(average, worst)| {
+                                format!("{:.2$} ({:.2$})", average, worst, self.price_prec)
+                            })
+                        };
+                        ui.label(format!(
+                            "{} / {}",
+                            sweep(self.orderbook_metrics.sell_sweep),
+                            sweep(self.orderbook_metrics.buy_sweep)
+                        ))
+                        .on_hover_text("Average (worst) price of a market sell / buy of this size");
+                        let depth = |depth: Option<Decimal>| {
+                            depth.map_or("overflow".to_string(), |depth| format!("{:.1$}", depth, self.qty_prec))
+                        };
+                        ui.label(format!(
+                            "{} / {}",
+                            depth(self.orderbook_metrics.bid_depth_near),
+                            depth(self.orderbook_metrics.ask_depth_near)
+                        ))
+                        .on_hover_text("Bid / ask quantity resting within this many ticks of the touch");
                         ui.end_row();
                     })
             });
@@ -511,7 +841,7 @@ impl eframe::App for MyApp {
                             ui.label("Quantity");
                             ui.end_row();
 
-                            for (price, qty) in self.asks.iter().take(20).rev() {
+                            for (price, qty) in asks.iter().take(20).rev() {
                                 ui.label("");
                                 ui.label(format!(
                                     "{:.1$}",
@@ -531,7 +861,7 @@ impl eframe::App for MyApp {
                             ui.label("Quantity");
                             ui.end_row();
 
-                            for (price, qty) in self.bids.iter().rev().take(20) {
+                            for (price, qty) in bids.iter().rev().take(20) {
                                 ui.label("");
                                 ui.label(format!(
                                     "{:.1$}",
@@ -549,8 +879,7 @@ impl eframe::App for MyApp {
                 });
 
                 ui.vertical(|ui| {
-                    let bid_levels: Vec<(&Decimal, Decimal)> = self
-                        .bids
+                    let bid_levels: Vec<(&Decimal, Decimal)> = bids
                         .iter()
                         .rev()
                         .take(100)
@@ -559,8 +888,7 @@ impl eframe::App for MyApp {
                             (key, sum)
                         })
                         .collect();
-                    let ask_levels: Vec<(&Decimal, Decimal)> = self
-                        .asks
+                    let ask_levels: Vec<(&Decimal, Decimal)> = asks
                         .iter()
                         .take(100)
                         .map(|(key, deque)| {
@@ -568,6 +896,9 @@ impl eframe::App for MyApp {
                             (key, sum)
                         })
                         .collect();
+                    // Axis labels follow whichever levels the bars are drawn from
+                    let mut bid_prices: Vec<Decimal> = bid_levels.iter().map(|(price, _)| **price).collect();
+                    let mut ask_prices: Vec<Decimal> = ask_levels.iter().map(|(price, _)| **price).collect();
                     let mut max_qty: f64 = 0.0;
                     for (_, qty) in &bid_levels {
                         max_qty = max_qty.max(qty.to_f64().unwrap_or(0.0));
@@ -579,8 +910,7 @@ impl eframe::App for MyApp {
                     let step = 1.0;
                     let mut bars: Vec<Bar> = Vec::new();
 
-                    let max_bid_order: Decimal = self
-                        .bids
+                    let max_bid_order: Decimal = bids
                         .values()
                         .rev()
                         .take(100)
@@ -588,8 +918,7 @@ impl eframe::App for MyApp {
                         .cloned()
                         .max()
                         .unwrap_or(Decimal::ZERO);
-                    let max_ask_order: Decimal = self
-                        .asks
+                    let max_ask_order: Decimal = asks
                         .values()
                         .take(100)
                         .flat_map(|dq| dq.iter())
@@ -597,8 +926,7 @@ impl eframe::App for MyApp {
                         .max()
                         .unwrap_or(Decimal::ZERO);
                     let second_max_bid_order = {
-                        let mut orders: Vec<_> = self
-                            .bids
+                        let mut orders: Vec<_> = bids
                             .values()
                             .rev()
                             .take(100)
@@ -609,8 +937,7 @@ impl eframe::App for MyApp {
                         orders.get(1).cloned().unwrap_or(Decimal::ZERO)
                     };
                     let second_max_ask_order = {
-                        let mut orders: Vec<_> = self
-                            .asks
+                        let mut orders: Vec<_> = asks
                             .values()
                             .take(100)
                             .flat_map(|dq| dq.iter())
@@ -620,22 +947,46 @@ impl eframe::App for MyApp {
                         orders.get(1).cloned().unwrap_or(Decimal::ZERO)
                     };
 
+                    // Numbers only, keyed by where their bar sits; the chart's formatter builds
+                    // the text for the hovered bar alone
+                    let mut tooltips: Vec<((f64, f64), OrderTooltip)> = Vec::new();
+                    let mut bid_participants: HashMap<usize, Participant> = HashMap::new();
+                    let mut ask_participants: HashMap<usize, Participant> = HashMap::new();
+
                     if !self.kmeans_mode {
-                        for (i, (_, qty_deq)) in self.asks.iter().take(100).enumerate() {
+                        let mut ahead = QueueAhead::default();
+                        let ask_levels_by_price = view.asks.orders.chunk_by(|a, b| a.price == b.price);
+                        for (i, level) in ask_levels_by_price.take(100).enumerate() {
+                            ahead.next_level();
                             let x = (i as f64 + 0.5) * step + 0.5;
                             let mut offset = 0.0;
 
-                            for (j, &qty) in qty_deq.iter().enumerate() {
+                            for order in level {
+                                let qty = order.qty;
                                 if qty <= dec!(0.0) {
                                     continue;
                                 }
+                                let (ahead_at_level, ahead_from_touch) = ahead.push(qty);
+                                tooltips.push((
+                                    (x, offset),
+                                    OrderTooltip {
+                                        side: Side::Ask,
+                                        price: order.price,
+                                        qty,
+                                        queue_index: order.queue_index,
+                                        age: Some(order.age),
+                                        cluster: None,
+                                        ahead_at_level,
+                                        ahead_from_touch,
+                                    },
+                                ));
                                 let color = if qty == max_ask_order {
                                     Color32::GOLD
                                 } else if qty == second_max_ask_order {
                                     Color32::from_rgb(184, 134, 11)
                                 } else {
                                     self.get_order_color(
-                                        j,
+                                        order.queue_index,
                                         Color32::DARK_RED,
                                         self.brighter_step as f32 / 100.0,
                                     )
@@ -650,21 +1001,39 @@ impl eframe::App for MyApp {
                         }
 
                         // Color Mapping for Bids
-                        for (i, (_, qty_deq)) in self.bids.iter().rev().take(100).enumerate() {
+                        let mut ahead = QueueAhead::default();
+                        let bid_levels_by_price = view.bids.orders.chunk_by(|a, b| a.price == b.price);
+                        for (i, level) in bid_levels_by_price.take(100).enumerate() {
+                            ahead.next_level();
                             let x = -(i as f64 + 0.5) * step - 0.5;
                             let mut offset = 0.0;
 
-                            for (j, &qty) in qty_deq.iter().enumerate() {
+                            for order in level {
+                                let qty = order.qty;
                                 if qty <= dec!(0.0) {
                                     continue;
                                 }
+                                let (ahead_at_level, ahead_from_touch) = ahead.push(qty);
+                                tooltips.push((
+                                    (x, offset),
+                                    OrderTooltip {
+                                        side: Side::Bid,
+                                        price: order.price,
+                                        qty,
+                                        queue_index: order.queue_index,
+                                        age: Some(order.age),
+                                        cluster: None,
+                                        ahead_at_level,
+                                        ahead_from_touch,
+                                    },
+                                ));
                                 let color = if qty == max_bid_order {
                                     Color32::GOLD
                                 } else if qty == second_max_bid_order {
                                     Color32::from_rgb(184, 134, 11)
                                 } else {
                                     self.get_order_color(
-                                        j,
+                                        order.queue_index,
                                         Color32::DARK_GREEN,
                                         self.brighter_step as f32 / 100.0,
                                     )
@@ -678,48 +1047,80 @@ impl eframe::App for MyApp {
                             }
                         }
                     } else {
-                        let asks_for_cluster: BTreeMap<Decimal, VecDeque<Decimal>> = self
-                            .asks
-                            .iter()
-                            .take(100)
-                            .map(|(&k, v)| (k, v.clone()))
-                            .collect();
-                        let mut kmeans_asks =
-                            kmeans::MiniBatchKMeans::new(10, self.batch_size, self.max_iter);
-                        let labels_asks = kmeans_asks.fit(&asks_for_cluster);
-                        let clustered_asks =
-                            kmeans::build_clustered_orders(&asks_for_cluster, &labels_asks);
-
-                        let bids_for_cluster: BTreeMap<Decimal, VecDeque<Decimal>> = self
-                            .bids
-                            .iter()
-                            .rev()
-                            .take(100)
-                            .map(|(&k, v)| (k, v.clone()))
-                            .collect();
-                        let mut kmeans_bids =
-                            kmeans::MiniBatchKMeans::new(10, self.batch_size, self.max_iter);
-                        let labels_bids = kmeans_bids.fit(&bids_for_cluster);
-                        let clustered_bids =
-                            kmeans::build_clustered_orders(&bids_for_cluster, &labels_bids);
+                        self.cluster_worker.update(now_ms, || ClusterJob {
+                            bids: view.bids.orders.clone(),
+                            asks: view.asks.orders.clone(),
+                            mid: self.orderbook_metrics.mid_price,
+                            tick_size: view.scale.tick_size(),
+                            method: self.cluster_method,
+                            params: ClusterParams {
+                                batch_size: self.batch_size,
+                                max_iter: self.max_iter,
+                                weights: self.feature_weights,
+                                num_clusters: self.min_clusters..=self.max_clusters,
+                                eps: self.density_eps,
+                                min_points: self.density_min_points,
+                                seed: self.cluster_seed,
+                            },
+                            joint: self.joint_clusters,
+                        });
+                        let latest = self.cluster_worker.latest();
+                        let empty = Default::default();
+                        let (clustered_bids, clustered_asks) = match latest {
+                            Some(labelling) => (&labelling.bids.orders, &labelling.asks.orders),
+                            None => (&empty, &empty),
+                        };
+                        let (bid_colors, ask_colors) = latest
+                            .map(|labelling| Self::labelling_colors(labelling, self.max_clusters))
+                            .unwrap_or_default();
+                        if let Some(labelling) = latest {
+                            let participants = |side: &SideLabelling| {
+                                side.summary.iter().map(|row| (row.cluster, row.participant)).collect()
+                            };
+                            bid_participants = participants(&labelling.bids);
+                            ask_participants = participants(&labelling.asks);
+                        }
+                        // The labelling can lag the view, so its levels and largest orders are
+                        // taken from the labelling itself
+                        bid_prices = clustered_bids.keys().rev().copied().collect();
+                        ask_prices = clustered_asks.keys().copied().collect();
+                        let largest = |orders: &ClusteredOrders| {
+                            orders.values().flatten().map(|order| order.qty).max().unwrap_or(Decimal::ZERO)
+                        };
+                        let max_bid_order = largest(clustered_bids);
+                        let max_ask_order = largest(clustered_asks);
 
                         // Asks in K-Means mode
-                        for (i, (_, qty_deq)) in clustered_asks.iter().enumerate() {
+                        let mut ahead = QueueAhead::default();
+                        for (i, (price, qty_deq)) in clustered_asks.iter().enumerate() {
+                            ahead.next_level();
                             let x = (i as f64 + 0.5) * step + 0.5;
                             let mut offset = 0.0;
 
-                            for &(qty, cluster) in qty_deq.iter() {
+                            for order in qty_deq.iter() {
+                                let qty = order.qty;
                                 if qty <= dec!(0.0) {
                                     continue;
                                 }
                                 let color = if qty == max_ask_order {
                                     Color32::GOLD
                                 } else {
-                                    ASK_COLORS
-                                        .get(cluster % ASK_COLORS.len())
-                                        .cloned()
-                                        .unwrap_or(Color32::GRAY)
+                                    ask_colors.get(&order.cluster).cloned().unwrap_or(Color32::GRAY)
                                 };
+                                let (ahead_at_level, ahead_from_touch) = ahead.push(qty);
+                                tooltips.push((
+                                    (x, offset),
+                                    OrderTooltip {
+                                        side: Side::Ask,
+                                        price: *price,
+                                        qty,
+                                        queue_index: order.queue_index,
+                                        age: Some(order.age),
+                                        cluster: Some(order.cluster),
+                                        ahead_at_level,
+                                        ahead_from_touch,
+                                    },
+                                ));
                                 let bar = Bar::new(x, qty.to_f64().unwrap_or(0.0))
                                     .fill(color)
                                     .base_offset(offset)
@@ -730,22 +1131,36 @@ impl eframe::App for MyApp {
                         }
 
                         // Bids in K-Means mode
-                        for (i, (_, qty_deq)) in clustered_bids.iter().rev().enumerate() {
+                        let mut ahead = QueueAhead::default();
+                        for (i, (price, qty_deq)) in clustered_bids.iter().rev().enumerate() {
+                            ahead.next_level();
                             let x = -(i as f64 + 0.5) * step - 0.5;
                             let mut offset = 0.0;
 
-                            for &(qty, cluster) in qty_deq.iter() {
+                            for order in qty_deq.iter() {
+                                let qty = order.qty;
                                 if qty <= dec!(0.0) {
                                     continue;
                                 }
                                 let color = if qty == max_bid_order {
                                     Color32::GOLD
                                 } else {
-                                    BID_COLORS
-                                        .get(cluster % BID_COLORS.len())
-                                        .cloned()
-                                        .unwrap_or(Color32::GRAY)
+                                    bid_colors.get(&order.cluster).cloned().unwrap_or(Color32::GRAY)
                                 };
+                                let (ahead_at_level, ahead_from_touch) = ahead.push(qty);
+                                tooltips.push((
+                                    (x, offset),
+                                    OrderTooltip {
+                                        side: Side::Bid,
+                                        price: *price,
+                                        qty,
+                                        queue_index: order.queue_index,
+                                        age: Some(order.age),
+                                        cluster: Some(order.cluster),
+                                        ahead_at_level,
+                                        ahead_from_touch,
+                                    },
+                                ));
                                 let bar = Bar::new(x, qty.to_f64().unwrap_or(0.0))
                                     .fill(color)
                                     .base_offset(offset)
@@ -756,15 +1171,36 @@ impl eframe::App for MyApp {
                         }
                     }
 
+                    let (price_prec, qty_prec) = (self.price_prec, self.qty_prec);
+                    let tooltip_text = move |bar: &Bar, _: &BarChart| {
+                        let Some((_, tooltip)) = tooltips
+                            .iter()
+                            .find(|((x, offset), _)| *x == bar.argument && Some(*offset) == bar.base_offset)
+                        else {
+                            return String::new();
+                        };
+                        let participants = match tooltip.side {
+                            Side::Bid => &bid_participants,
+                            Side::Ask => &ask_participants,
+                        };
+                        tooltip.text(price_prec, qty_prec, |cluster| participants.get(&cluster).copied())
+                    };
+
+                    // Pan and zoom along the price axis only; double-click to reset
                     Plot::new("orderbook_chart")
-                        .allow_drag(false)
-                        .allow_scroll(false)
-                        .allow_zoom(false)
+                        .height((ui.available_height() - CVD_PLOT_HEIGHT).max(200.0))
+                        .allow_drag([true, false])
+                        .allow_scroll([true, false])
+                        .allow_zoom([true, false])
+                        .allow_boxed_zoom(false)
                         .show_axes([true, true])
                         .show(ui, |plot_ui| {
-                            plot_ui.bar_chart(BarChart::new("ob", bars));
+                            plot_ui.bar_chart(
+                                BarChart::new("ob", bars)
+                                    .element_formatter(Box::new(tooltip_text)),
+                            );
 
-                            for (i, (price, _)) in bid_levels.iter().enumerate() {
+                            for (i, price) in bid_prices.iter().enumerate() {
                                 if i.is_multiple_of(20) {
                                     // Show label every 20th level
                                     let x = -(i as f64 + 0.5) * step - 0.5;
@@ -783,7 +1219,7 @@ impl eframe::App for MyApp {
                                 }
                             }
 
-                            for (i, (price, _)) in ask_levels.iter().enumerate() {
+                            for (i, price) in ask_prices.iter().enumerate() {
                                 if i.is_multiple_of(20) {
                                     // Show label every 20th level
                                     if i == 0 {
@@ -805,6 +1241,31 @@ impl eframe::App for MyApp {
                                 }
                             }
                         });
+
+                    // Cumulative volume delta, x in seconds relative to the latest trade
+                    let latest = self.last_trade_time;
+                    let cvd_points: PlotPoints = self
+                        .trade_flow
+                        .cvd_history()
+                        .iter()
+                        .map(|&(ts, cvd)| {
+                            [
+                                -(latest.saturating_sub(ts) as f64) / 1000.0,
+                                cvd.to_f64().unwrap_or(0.0),
+                            ]
+                        })
+                        .collect();
+                    Plot::new("cvd_chart")
+                        .height(CVD_PLOT_HEIGHT)
+                        .allow_drag(false)
+                        .allow_scroll(false)
+                        .allow_zoom(false)
+                        .show_axes([true, true])
+                        .x_axis_label("seconds")
+                        .y_axis_label("CVD")
+                        .show(ui, |plot_ui| {
+                            plot_ui.line(Line::new("cvd", cvd_points).color(Color32::LIGHT_BLUE));
+                        });
                 });
             });
             ui.horizontal(|ui| {
@@ -827,96 +1288,254 @@ impl MyApp {
 
         Color32::from_rgb(r, g, b)
     }
-}
 
-impl MyApp {
-    fn apply_update(&mut self, update: &DepthUpdate) {
-        for bid in &update.b {
-            let price = bid[0];
-            let qty = bid[1];
-            if qty == Decimal::ZERO {
-                self.bids.remove(&price);
-            } else {
-                let price = bid[0];
-                let qty = bid[1];
-                if qty > Decimal::ZERO {
-                    if let Some(old_qty) = self.bids.get_mut(&price) {
-                        let old_sum = old_qty.iter().sum::<Decimal>();
-                        if old_sum > qty {
-                            let change = old_sum - qty;
-                            if let Some(pos) = old_qty.iter().rposition(|&x| x == change) {
-                                old_qty.remove(pos); // Removes the last occurrence of the value
-                            } else {
-                                let largest_order = *old_qty.iter().max().unwrap();
-                                let largest_pos =
-                                    old_qty.iter().position(|&x| x == largest_order).unwrap();
-                                old_qty.remove(largest_pos);
-                                old_qty.push_back(largest_order - change);
-                            }
-                        } else if old_sum < qty {
-                            if old_sum < qty {
-                                let change = qty - old_sum;
-                                old_qty.push_back(change);
-                            }
-                        } else {
-                            continue;
-                        }
-                    } else {
-                        self.bids.insert(price, VecDeque::from(vec![qty]));
-                    }
-                }
+    // One colour per cluster id in use. Each id keeps its slot among at least `slots` evenly
+    // spaced shades, so a cluster does not change colour when another one appears or goes away;
+    // the palette grows to the highest id in use, so distinct ids never share a shade
+    fn cluster_colors(
+        clustered: &[&ClusteredOrders],
+        (light, dark): ([f32; 3], [f32; 3]),
+        slots: usize,
+    ) -> BTreeMap<usize, Color32> {
+        // Noise gets no colour and is drawn grey
+        let ids: BTreeSet<usize> = clustered
+            .iter()
+            .flat_map(|orders| orders.values().flatten())
+            .map(|order| order.cluster)
+            .filter(|&cluster| cluster != NOISE)
+            .collect();
+        // DBSCAN numbers clusters without a cap, and a labelling can predate a lower maximum
+        let slots = slots.max(ids.last().map_or(1, |&id| id + 1));
+        let last = (slots - 1).max(1) as f32;
+        ids.into_iter()
+            .map(|id| {
+                let f = id as f32 / last;
+                let mix = |c: usize| (light[c] + (dark[c] - light[c]) * f) as u8;
+                (id, Color32::from_rgb(mix(0), mix(1), mix(2)))
+            })
+            .collect()
+    }
+
+    // Bid and ask cluster colours, with a slot per cluster the fit may use; a joint labelling
+    // colours both sides from one palette
+    fn labelling_colors(
+        labelling: &Labelling,
+        slots: usize,
+    ) -> (BTreeMap<usize, Color32>, BTreeMap<usize, Color32>) {
+        let (bids, asks) = (&labelling.bids.orders, &labelling.asks.orders);
+        if labelling.joint {
+            let colors = Self::cluster_colors(&[bids, asks], JOINT_PALETTE, slots);
+            (colors.clone(), colors)
+        } else {
+            (
+                Self::cluster_colors(&[bids], BID_PALETTE, slots),
+                Self::cluster_colors(&[asks], ASK_PALETTE, slots),
+            )
+        }
+    }
+
+    // Dark blue -> blue -> yellow -> white as resting size grows
+    fn heat_color(t: f32) -> Color32 {
+        const STOPS: [(f32, [f32; 3]); 4] = [
+            (0.0, [8.0, 8.0, 32.0]),
+            (0.35, [30.0, 70.0, 200.0]),
+            (0.7, [240.0, 200.0, 40.0]),
+            (1.0, [255.0, 255.0, 255.0]),
+        ];
+        let t = t.clamp(0.0, 1.0);
+        for pair in STOPS.windows(2) {
+            let ((t0, c0), (t1, c1)) = (pair[0], pair[1]);
+            if t <= t1 {
+                let f = (t - t0) / (t1 - t0);
+                let mix = |i: usize| (c0[i] + (c1[i] - c0[i]) * f) as u8;
+                return Color32::from_rgb(mix(0), mix(1), mix(2));
             }
         }
-        for ask in &update.a {
-            let price = ask[0];
-            let qty = ask[1];
-            if qty == Decimal::ZERO {
-                self.asks.remove(&price);
-            } else if let Some(old_qty) = self.asks.get_mut(&price) {
-                let old_sum = old_qty.iter().sum::<Decimal>();
-                if old_sum > qty {
-                    let change = old_sum - qty;
-                    if let Some(pos) = old_qty.iter().rposition(|&x| x == change) {
-                        old_qty.remove(pos); // Removes the last occurrence of the value
+        Color32::WHITE
+    }
+
+    // One row per cluster and side, coloured like the chart, with a guess at who is behind it
+    fn draw_cluster_summary(&self, ui: &mut egui::Ui) {
+        let Some(labelling) = self.cluster_worker.latest() else {
+            ui.label("Waiting for the first labelling");
+            return;
+        };
+        let (bid_colors, ask_colors) = Self::labelling_colors(labelling, self.max_clusters);
+        let sides: [(&str, &SideLabelling, _); 2] = [
+            ("Asks", &labelling.asks, ask_colors),
+            ("Bids", &labelling.bids, bid_colors),
+        ];
+        for (name, side, colors) in sides {
+            ui.label(name);
+            egui::Grid::new(("cluster_summary", name)).striped(true).show(ui, |ui| {
+                for header in [
+                    "", "Cluster", "Type", "Orders", "Total size", "Mean size", "Mean age",
+                    "At touch", "Cancel rate",
+                ] {
+                    ui.label(header);
+                }
+                ui.end_row();
+
+                for row in &side.summary {
+                    ui.colored_label(colors.get(&row.cluster).cloned().unwrap_or(Color32::GRAY), "■");
+                    if row.cluster == NOISE {
+                        ui.label("noise");
                     } else {
-                        let largest_order = *old_qty.iter().max().unwrap();
-                        let largest_pos = old_qty.iter().position(|&x| x == largest_order).unwrap();
-                        old_qty.remove(largest_pos);
-                        old_qty.push_back(largest_order - change);
-                    }
-                } else if old_sum < qty {
-                    if old_sum < qty {
-                        let change = qty - old_sum;
-                        old_qty.push_back(change);
+                        ui.label(row.cluster.to_string());
                     }
-                } else {
+                    ui.label(row.participant.label());
+                    ui.label(row.count.to_string());
+                    ui.label(format!("{:.1$}", row.total_size, self.qty_prec));
+                    ui.label(format!("{:.1$}", row.mean_size, self.qty_prec));
+                    ui.label(format!("{:.0}", row.mean_age));
+                    ui.label(format!("{:.0}%", row.touch_share * 100.0));
+                    match row.cancel_rate {
+                        Some(rate) => ui.label(format!("{:.0}%", rate * 100.0)),
+                        None => ui.label("-"),
+                    };
+                    ui.end_row();
+                }
+            });
+        }
+    }
+
+    fn draw_heatmap(&self, ui: &mut egui::Ui, now_ms: u64) {
+        let (response, painter) = ui.allocate_painter(ui.available_size(), egui::Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 0.0, Self::heat_color(0.0));
+
+        // Price range and row height follow the latest sample, older columns are clipped to it
+        let Some(latest) = self.heatmap.columns().back() else {
+            return;
+        };
+        let lo = latest.levels.first().map(|&(p, _)| p).unwrap_or(0.0);
+        let hi = latest.levels.last().map(|&(p, _)| p).unwrap_or(0.0);
+        if hi <= lo {
+            return;
+        }
+        let tick = latest
+            .levels
+            .windows(2)
+            .map(|w| w[1].0 - w[0].0)
+            .filter(|&d| d > 0.0)
+            .fold(f64::MAX, f64::min)
+            .min(hi - lo);
+
+        let max_qty = self
+            .heatmap
+            .columns()
+            .iter()
+            .flat_map(|c| c.levels.iter().map(|&(_, q)| q))
+            .fold(0.0, f64::max);
+        let log_max = (1.0 + max_qty).ln();
+        let window = self.heatmap.window_ms() as f32;
+        let to_x = |ts: u64| rect.right() - now_ms.saturating_sub(ts) as f32 / window * rect.width();
+        let to_y = |price: f64| {
+            rect.bottom() - ((price - lo + tick / 2.0) / (hi - lo + tick)) as f32 * rect.height()
+        };
+        let col_w = self.heatmap.sample_interval_ms() as f32 / window * rect.width();
+
+        for column in self.heatmap.columns() {
+            let x = to_x(column.ts);
+            for &(price, qty) in &column.levels {
+                if price < lo || price > hi || qty <= 0.0 {
                     continue;
                 }
-            } else {
-                self.asks.insert(price, VecDeque::from(vec![qty]));
+                let cell = egui::Rect::from_min_max(
+                    egui::pos2(x - col_w, to_y(price + tick / 2.0)),
+                    egui::pos2(x, to_y(price - tick / 2.0)),
+                )
+                .intersect(rect);
+                painter.rect_filled(cell, 0.0, Self::heat_color(((1.0 + qty).ln() / log_max) as f32));
             }
         }
-        self.calculate_orderbook_metrics();
-    }
 
-    fn calculate_orderbook_metrics(&mut self) {
-        let mut bid_qty_sum = Decimal::ZERO;
-        let mut bid_price_sum = Decimal::ZERO;
-        let mut ask_qty_sum = Decimal::ZERO;
-        let mut ask_price_sum = Decimal::ZERO;
-        let mut total_level_qty = Decimal::ZERO;
-
-        for (price, level_qtys) in &self.asks {
-            total_level_qty = level_qtys.iter().copied().sum();
-            ask_qty_sum += total_level_qty;
-            ask_price_sum += price * total_level_qty;
+        // Trades as bubbles, area proportional to size
+        let max_trade = self.heatmap.trades().iter().map(|t| t.qty).fold(0.0, f64::max);
+        for trade in self.heatmap.trades() {
+            let pos = egui::pos2(to_x(trade.ts), to_y(trade.price));
+            if !rect.contains(pos) || max_trade <= 0.0 {
+                continue;
+            }
+            let radius = 2.0 + 10.0 * (trade.qty / max_trade).sqrt() as f32;
+            let color = if trade.is_buy {
+                Color32::from_rgba_unmultiplied(0, 220, 90, 170)
+            } else {
+                Color32::from_rgba_unmultiplied(230, 40, 40, 170)
+            };
+            painter.circle_filled(pos, radius, color);
         }
 
-        for (price, level_qtys) in &self.bids {
-            total_level_qty = level_qtys.iter().copied().sum();
-            bid_qty_sum += total_level_qty;
-            bid_price_sum += price * total_level_qty;
+        for price in [lo, (lo + hi) / 2.0, hi] {
+            painter.text(
+                egui::pos2(rect.left() + 4.0, to_y(price)),
+                Align2::LEFT_CENTER,
+                format!("{:.1$}", price, self.price_prec),
+                egui::FontId::monospace(12.0),
+                Color32::LIGHT_GRAY,
+            );
+        }
+    }
+}
+
+impl MyApp {
+    fn process_trade(&mut self, trade: &TradeUpdate) {
+        self.trades_ring.push(trade.trade_time);
+        // buyer is the maker => the aggressor sold
+        if trade.buyer_market_maker {
+            self.sell_hawkes.push(trade.trade_time);
+        } else {
+            self.buy_hawkes.push(trade.trade_time);
         }
+        self.last_trade_time = self.last_trade_time.max(trade.trade_time);
+        self.trade_flow.push(trade);
+        self.heatmap.push_trade(trade);
+    }
+
+    fn now_ms(&self) -> u64 {
+        let wall_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        // local clock may lag the exchange, never evaluate before the last trade we saw
+        wall_ms.max(self.last_trade_time)
+    }
+
+    fn calculate_trade_metrics(&mut self, now_ms: u64) {
+        let trade_rate = |ring: &LambdaRing, window_ms: u64| {
+            Decimal::from(ring.count_since(now_ms.saturating_sub(window_ms)))
+                / Decimal::from(window_ms / 1000)
+        };
+        self.trade_metrics.lambda_one_second = trade_rate(&self.trades_ring, 1_000);
+        self.trade_metrics.lambda_thirty_seconds = trade_rate(&self.trades_ring, 30_000);
+        self.trade_metrics.lambda_one_minute = trade_rate(&self.trades_ring, 60_000);
+
+        self.trade_metrics.buy_hawkes = self.buy_hawkes.update(now_ms);
+        self.trade_metrics.sell_hawkes = self.sell_hawkes.update(now_ms);
+
+        let longest_window_ms = self.imbalance_windows_secs.iter().max().copied().unwrap_or(0) * 1000;
+        self.trade_flow.prune(now_ms, longest_window_ms);
+        self.trade_metrics.imbalance = self
+            .imbalance_windows_secs
+            .iter()
+            .map(|&secs| (secs * 1000, self.trade_flow.imbalance(now_ms, secs * 1000)))
+            .collect();
+        self.trade_metrics.cvd = self.trade_flow.cvd();
+    }
+
+    fn calculate_ofi_metrics(&mut self) {
+        let longest_window_ms = self.ofi_windows_secs.iter().max().copied().unwrap_or(0) * 1000;
+        self.order_flow.prune(longest_window_ms);
+        self.orderbook_metrics.ofi = self
+            .ofi_windows_secs
+            .iter()
+            .map(|&secs| (secs * 1000, self.order_flow.sum(secs * 1000)))
+            .collect();
+    }
+
+    fn calculate_orderbook_metrics(&mut self, view: &BookView) {
+        let bid_qty_sum = view.bids.total_qty;
+        let ask_qty_sum = view.asks.total_qty;
 
         let imbalance = if bid_qty_sum + ask_qty_sum > dec!(0) {
             (bid_qty_sum - ask_qty_sum) / (bid_qty_sum + ask_qty_sum)
@@ -924,12 +1543,20 @@ impl MyApp {
             Decimal::ZERO
         };
 
-        let ask_vwap = if ask_qty_sum > dec!(0) {ask_price_sum / ask_qty_sum} else {Decimal::ZERO};
-        let bid_vwap = if bid_qty_sum > dec!(0) {bid_price_sum / bid_qty_sum} else {Decimal::ZERO};
+        if let (Some((best_bid, _)), Some((best_ask, _))) = (view.best_bid(), view.best_ask()) {
+            self.orderbook_metrics.mid_price = (best_bid + best_ask) / dec!(2);
+            self.orderbook_metrics.spread = best_ask - best_bid;
+        }
 
         self.orderbook_metrics.imbalance = imbalance;
-        self.orderbook_metrics.ask_vwap = ask_vwap;
-        self.orderbook_metrics.bid_vwap = bid_vwap;
+        self.orderbook_metrics.ask_vwap = view.asks.vwap.unwrap_or(Decimal::ZERO);
+        self.orderbook_metrics.bid_vwap = view.bids.vwap.unwrap_or(Decimal::ZERO);
+
+        let sweep_qty = Decimal::from_f64(self.sweep_qty).map(|qty| qty.round_dp(self.qty_prec as u32));
+        self.orderbook_metrics.buy_sweep = sweep_qty.and_then(|qty| view.sweep(Side::Ask, qty));
+        self.orderbook_metrics.sell_sweep = sweep_qty.and_then(|qty| view.sweep(Side::Bid, qty));
+        self.orderbook_metrics.bid_depth_near = view.depth_near(Side::Bid, self.depth_near_ticks).ok();
+        self.orderbook_metrics.ask_depth_near = view.depth_near(Side::Ask, self.depth_near_ticks).ok();
     }
 
 }
@@ -0,0 +1,170 @@
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::VecDeque;
+
+use crate::model::{OrderbookMetrics, TradeMetrics};
+
+pub const SERIES_COUNT: usize = 13;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricSeries {
+    MidPrice,
+    Spread,
+    BookImbalance,
+    BidVwap,
+    AskVwap,
+    Ofi,
+    TradeImbalance,
+    Cvd,
+    LambdaOneSecond,
+    LambdaThirtySeconds,
+    LambdaOneMinute,
+    BuyIntensity,
+    SellIntensity,
+}
+
+impl MetricSeries {
+    pub const ALL: [MetricSeries; SERIES_COUNT] = [
+        MetricSeries::MidPrice,
+        MetricSeries::Spread,
+        MetricSeries::BookImbalance,
+        MetricSeries::BidVwap,
+        MetricSeries::AskVwap,
+        MetricSeries::Ofi,
+        MetricSeries::TradeImbalance,
+        MetricSeries::Cvd,
+        MetricSeries::LambdaOneSecond,
+        MetricSeries::LambdaThirtySeconds,
+        MetricSeries::LambdaOneMinute,
+        MetricSeries::BuyIntensity,
+        MetricSeries::SellIntensity,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MetricSeries::MidPrice => "Mid price",
+            MetricSeries::Spread => "Spread",
+            MetricSeries::BookImbalance => "Book imbalance",
+            MetricSeries::BidVwap => "Bid VWAP",
+            MetricSeries::AskVwap => "Ask VWAP",
+            MetricSeries::Ofi => "OFI (shortest window)",
+            MetricSeries::TradeImbalance => "Trade imbalance (shortest window)",
+            MetricSeries::Cvd => "CVD",
+            MetricSeries::LambdaOneSecond => "Lambda - 1 second",
+            MetricSeries::LambdaThirtySeconds => "Lambda - 30 seconds",
+            MetricSeries::LambdaOneMinute => "Lambda - 1 minute",
+            MetricSeries::BuyIntensity => "Hawkes buy intensity",
+            MetricSeries::SellIntensity => "Hawkes sell intensity",
+        }
+    }
+
+    fn value(self, book: &OrderbookMetrics, trade: &TradeMetrics) -> f64 {
+        let first = |windows: &[(u64, Decimal)]| windows.first().map(|&(_, v)| v).unwrap_or_default();
+        let d = match self {
+            MetricSeries::MidPrice => book.mid_price,
+            MetricSeries::Spread => book.spread,
+            MetricSeries::BookImbalance => book.imbalance,
+            MetricSeries::BidVwap => book.bid_vwap,
+            MetricSeries::AskVwap => book.ask_vwap,
+            MetricSeries::Ofi => first(&book.ofi),
+            MetricSeries::TradeImbalance => first(&trade.imbalance),
+            MetricSeries::Cvd => trade.cvd,
+            MetricSeries::LambdaOneSecond => trade.lambda_one_second,
+            MetricSeries::LambdaThirtySeconds => trade.lambda_thirty_seconds,
+            MetricSeries::LambdaOneMinute => trade.lambda_one_minute,
+            MetricSeries::BuyIntensity => return trade.buy_hawkes.intensity,
+            MetricSeries::SellIntensity => return trade.sell_hawkes.intensity,
+        };
+        d.to_f64().unwrap_or(0.0)
+    }
+}
+
+// Bounded time series of every chartable metric, sampled at a fixed cadence so the
+// memory use does not depend on the frame rate.
+pub struct MetricsHistory {
+    samples: VecDeque<(u64, [f64; SERIES_COUNT])>,
+    sample_interval_ms: u64,
+    max_age_ms: u64,
+}
+
+impl MetricsHistory {
+    pub fn new(sample_interval_ms: u64, max_age_ms: u64) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            sample_interval_ms,
+            max_age_ms,
+        }
+    }
+
+    pub fn max_age_ms(&self) -> u64 {
+        self.max_age_ms
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn record(&mut self, now_ms: u64, book: &OrderbookMetrics, trade: &TradeMetrics) {
+        if let Some(&(last_ts, _)) = self.samples.back()
+            && now_ms < last_ts + self.sample_interval_ms
+        {
+            return;
+        }
+        let values = MetricSeries::ALL.map(|series| series.value(book, trade));
+        self.samples.push_back((now_ms, values));
+
+        let cutoff = now_ms.saturating_sub(self.max_age_ms);
+        while self.samples.front().is_some_and(|&(ts, _)| ts < cutoff) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Points of one series over the lookback, x in seconds relative to `now_ms`.
+    pub fn points(&self, series: MetricSeries, now_ms: u64, lookback_ms: u64) -> Vec<[f64; 2]> {
+        let idx = series as usize;
+        let cutoff = now_ms.saturating_sub(lookback_ms);
+        self.samples
+            .iter()
+            .filter(|&&(ts, _)| ts >= cutoff)
+            .map(|&(ts, values)| [-(now_ms.saturating_sub(ts) as f64) / 1000.0, values[idx]])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_record_respects_interval_and_age() {
+        let mut history = MetricsHistory::new(100, 1_000);
+        let mut book = OrderbookMetrics::default();
+        let trade = TradeMetrics::default();
+        for ts in (0..2_000).step_by(50) {
+            book.mid_price = Decimal::from(ts);
+            history.record(ts, &book, &trade);
+        }
+        let points = history.points(MetricSeries::MidPrice, 1_950, 10_000);
+        // one sample per 100ms, nothing older than a second
+        assert_eq!(points.len(), 11);
+        assert_eq!(points.first(), Some(&[-1.05, 900.0]));
+        assert_eq!(points.last(), Some(&[-0.05, 1_900.0]));
+    }
+
+    #[test]
+    fn test_points_lookback_and_series() {
+        let mut history = MetricsHistory::new(0, 60_000);
+        let mut book = OrderbookMetrics::default();
+        let mut trade = TradeMetrics::default();
+        for ts in [1_000, 2_000, 3_000] {
+            book.spread = dec!(0.5);
+            trade.imbalance = vec![(1_000, Decimal::from(ts) / dec!(1000)), (10_000, dec!(9))];
+            history.record(ts, &book, &trade);
+        }
+        let spread = history.points(MetricSeries::Spread, 3_000, 1_000);
+        assert_eq!(spread, vec![[-1.0, 0.5], [0.0, 0.5]]);
+        let imbalance = history.points(MetricSeries::TradeImbalance, 3_000, 60_000);
+        assert_eq!(imbalance, vec![[-2.0, 1.0], [-1.0, 2.0], [0.0, 3.0]]);
+    }
+}
@@ -1,7 +1,9 @@
 use rust_decimal::Decimal;
 use rust_decimal::prelude::*;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::hawkes::HawkesFit;
 
 use std::collections::HashMap;
 
@@ -40,7 +42,8 @@ pub struct TradeUpdate {
     pub event_time: u64,
     #[serde(rename = "s")]
     pub symbol: String,
-    #[serde(rename = "t")]
+    // aggTrade carries the aggregate id in `a`, the raw trade stream uses `t`
+    #[serde(rename = "a", alias = "t")]
     pub trade_id: u64,
     pub p: Decimal,
     pub q: Decimal,
@@ -51,37 +54,64 @@ pub struct TradeUpdate {
 }
 
 pub struct TradeMetrics {
-    pub imbalance: Decimal, // shouldn't this be over some period?
+    pub imbalance: Vec<(u64, Decimal)>, // (window ms, signed aggressor volume imbalance)
+    pub cvd: Decimal,
     pub lambda_five_micros: Decimal,
     pub lambda_one_milli: Decimal,
     pub lambda_one_second: Decimal,
     pub lambda_thirty_seconds: Decimal,
     pub lambda_one_minute: Decimal,
+    pub buy_hawkes: HawkesFit,
+    pub sell_hawkes: HawkesFit,
 }
 
 impl Default for TradeMetrics {
     fn default() -> Self {
         TradeMetrics {
-            imbalance: Decimal::ZERO,
+            imbalance: Vec::new(),
+            cvd: Decimal::ZERO,
             lambda_five_micros: Decimal::ZERO,
             lambda_one_milli: Decimal::ZERO,
             lambda_one_second: Decimal::ZERO,
             lambda_thirty_seconds: Decimal::ZERO,
-            lambda_one_minute: Decimal::ZERO
+            lambda_one_minute: Decimal::ZERO,
+            buy_hawkes: HawkesFit::default(),
+            sell_hawkes: HawkesFit::default(),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+// One estimated resting order with what the clustering needs to know about it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrderSample {
+    // Id of the estimated order, stable for as long as it rests
+    pub id: u64,
+    pub side: Side,
+    pub price: Decimal,
+    pub qty: Decimal,
+    // Orders that joined the same side after this one, a clock-free measure of age
+    pub age: u64,
+    // Position in its level's queue, 0 at the front
+    pub queue_index: usize,
+}
+
+#[derive(Clone, Copy)]
 pub struct BestBidAsk {
-    best_bid_price: Decimal,
-    best_bid_qty: Decimal,
-    best_ask_price: Decimal,
-    best_offer_qty: Decimal
+    pub best_bid_price: Decimal,
+    pub best_bid_qty: Decimal,
+    pub best_ask_price: Decimal,
+    pub best_offer_qty: Decimal
 }
 
 //should rename these properties to be full names. use serde(rename)s to work around exchange variations
 #[allow(dead_code)]
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct DepthUpdate {
     pub e: String,
     #[serde(rename = "E")]
@@ -98,6 +128,14 @@ pub struct DepthUpdate {
     pub a: Vec<Vec<Decimal>>,
 }
 
+#[derive(Deserialize)]
+pub struct OrderBookSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    pub last_update_id: u64,
+    pub bids: Vec<Vec<Decimal>>,
+    pub asks: Vec<Vec<Decimal>>,
+}
+
 pub struct OrderbookMetrics {
     pub mid_price: Decimal,
     pub spread: Decimal,
@@ -105,6 +143,11 @@ pub struct OrderbookMetrics {
     pub imbalance: Decimal,
     pub bid_vwap: Decimal,
     pub ask_vwap: Decimal,
+    pub ofi: Vec<(u64, Decimal)>, // (window ms, summed order flow imbalance)
+    pub buy_sweep: Option<(Decimal, Decimal)>, // (average, worst) ask a market buy of the sweep qty fills at
+    pub sell_sweep: Option<(Decimal, Decimal)>, // (average, worst) bid a market sell of the sweep qty fills at
+    pub bid_depth_near: Option<Decimal>, // qty within the near-depth ticks of the touch, None on overflow
+    pub ask_depth_near: Option<Decimal>,
 }
 
 impl Default for OrderbookMetrics {
@@ -116,6 +159,11 @@ impl Default for OrderbookMetrics {
             imbalance: Decimal::ZERO,
             bid_vwap: Decimal::ZERO,
             ask_vwap: Decimal::ZERO,
+            ofi: Vec::new(),
+            buy_sweep: None,
+            sell_sweep: None,
+            bid_depth_near: None,
+            ask_depth_near: None,
         }
     }
 }
\ No newline at end of file
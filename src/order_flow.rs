@@ -0,0 +1,126 @@
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+use crate::model::BestBidAsk;
+
+// Cont-Kukanov-Stoikov order flow imbalance contribution between two consecutive best quotes.
+// Bid size arriving at or above the previous best bid counts as buy pressure, bid size leaving
+// at or below it as sell pressure, and symmetrically on the ask side.
+fn ofi_contribution(prev: &BestBidAsk, cur: &BestBidAsk) -> Decimal {
+    let mut e = Decimal::ZERO;
+    if cur.best_bid_price >= prev.best_bid_price {
+        e += cur.best_bid_qty;
+    }
+    if cur.best_bid_price <= prev.best_bid_price {
+        e -= prev.best_bid_qty;
+    }
+    if cur.best_ask_price <= prev.best_ask_price {
+        e -= cur.best_offer_qty;
+    }
+    if cur.best_ask_price >= prev.best_ask_price {
+        e += prev.best_offer_qty;
+    }
+    e
+}
+
+// Rolling OFI over the synced book's best levels, keyed by depth event time (ms).
+pub struct OrderFlowImbalance {
+    prev: Option<BestBidAsk>,
+    events: VecDeque<(u64, Decimal)>,
+    latest_ts: u64,
+}
+
+impl OrderFlowImbalance {
+    pub fn new() -> Self {
+        Self {
+            prev: None,
+            events: VecDeque::new(),
+            latest_ts: 0,
+        }
+    }
+
+    /// Forgets the last best quotes, e.g. after a snapshot replaced the book.
+    pub fn reset(&mut self) {
+        self.prev = None;
+    }
+
+    pub fn update(&mut self, ts: u64, best: BestBidAsk) {
+        if let Some(prev) = &self.prev {
+            let e = ofi_contribution(prev, &best);
+            if e != Decimal::ZERO {
+                self.events.push_back((ts, e));
+            }
+        }
+        self.prev = Some(best);
+        self.latest_ts = self.latest_ts.max(ts);
+    }
+
+    pub fn prune(&mut self, max_window_ms: u64) {
+        let cutoff = self.latest_ts.saturating_sub(max_window_ms);
+        while self.events.front().is_some_and(|&(ts, _)| ts < cutoff) {
+            self.events.pop_front();
+        }
+    }
+
+    /// Sum of OFI contributions over the last `window_ms` before the latest book event.
+    pub fn sum(&self, window_ms: u64) -> Decimal {
+        let cutoff = self.latest_ts.saturating_sub(window_ms);
+        self.events
+            .iter()
+            .rev()
+            .take_while(|&&(ts, _)| ts >= cutoff)
+            .map(|&(_, e)| e)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn quote(bid: Decimal, bid_qty: Decimal, ask: Decimal, ask_qty: Decimal) -> BestBidAsk {
+        BestBidAsk {
+            best_bid_price: bid,
+            best_bid_qty: bid_qty,
+            best_ask_price: ask,
+            best_offer_qty: ask_qty,
+        }
+    }
+
+    #[test]
+    fn test_size_changes_at_unchanged_prices() {
+        let prev = quote(dec!(10), dec!(5), dec!(11), dec!(7));
+        // bid grows by 2, ask shrinks by 3: both are buy pressure
+        let cur = quote(dec!(10), dec!(7), dec!(11), dec!(4));
+        assert_eq!(ofi_contribution(&prev, &cur), dec!(5));
+    }
+
+    #[test]
+    fn test_price_moves() {
+        let prev = quote(dec!(10), dec!(5), dec!(11), dec!(7));
+        // new higher bid counts fully, ask untouched
+        let up = quote(dec!(10.5), dec!(2), dec!(11), dec!(7));
+        assert_eq!(ofi_contribution(&prev, &up), dec!(2));
+        // bid level wiped out and the ask steps down on top of it
+        let down = quote(dec!(9.5), dec!(4), dec!(10.5), dec!(3));
+        assert_eq!(ofi_contribution(&prev, &down), dec!(-8));
+    }
+
+    #[test]
+    fn test_rolling_sum() {
+        let mut ofi = OrderFlowImbalance::new();
+        ofi.update(1_000, quote(dec!(10), dec!(5), dec!(11), dec!(7)));
+        ofi.update(2_000, quote(dec!(10), dec!(8), dec!(11), dec!(7))); // +3
+        ofi.update(9_000, quote(dec!(10), dec!(8), dec!(11), dec!(9))); // -2
+        assert_eq!(ofi.sum(1_000), dec!(-2));
+        assert_eq!(ofi.sum(10_000), dec!(1));
+        ofi.prune(1_000);
+        assert_eq!(ofi.sum(10_000), dec!(-2));
+
+        // after a reset the next quote only seeds the state
+        ofi.reset();
+        ofi.update(9_500, quote(dec!(1), dec!(1), dec!(2), dec!(1)));
+        assert_eq!(ofi.sum(1_000), dec!(-2));
+    }
+}
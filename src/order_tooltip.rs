@@ -0,0 +1,123 @@
+use rust_decimal::Decimal;
+
+use crate::cluster_summary::Participant;
+use crate::clustering::NOISE;
+use crate::model::Side;
+
+/// What hovering one order's segment of the order book chart shows. Only numbers are kept per
+/// order; the text is built for the hovered one alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrderTooltip {
+    pub side: Side,
+    pub price: Decimal,
+    pub qty: Decimal,
+    pub queue_index: usize,
+    pub age: Option<u64>,
+    pub cluster: Option<usize>,
+    /// Quantity that trades before this order: the queue in front of it at its price, and
+    /// everything from the touch up to it.
+    pub ahead_at_level: Decimal,
+    pub ahead_from_touch: Decimal,
+}
+
+impl OrderTooltip {
+    /// `participant` names the guess behind a cluster id, when the labelling has one.
+    pub fn text(
+        &self,
+        price_prec: usize,
+        qty_prec: usize,
+        participant: impl Fn(usize) -> Option<Participant>,
+    ) -> String {
+        let side = match self.side {
+            Side::Bid => "Bid",
+            Side::Ask => "Ask",
+        };
+        let age = self.age.map_or("-".to_string(), |age| age.to_string());
+        let mut text = format!(
+            "{side} {:.price_prec$} x {:.qty_prec$}\nQueue position: {}\nAge: {age} newer orders\n\
+             Ahead at level: {:.qty_prec$}\nAhead from touch: {:.qty_prec$}",
+            self.price, self.qty, self.queue_index, self.ahead_at_level, self.ahead_from_touch,
+        );
+        match self.cluster {
+            Some(NOISE) => text.push_str("\nCluster: noise"),
+            Some(cluster) => match participant(cluster) {
+                Some(participant) => text.push_str(&format!("\nCluster: {cluster} ({})", participant.label())),
+                None => text.push_str(&format!("\nCluster: {cluster}")),
+            },
+            None => {}
+        }
+        text
+    }
+}
+
+/// Quantity ahead of each order while walking one side from the touch outwards, level by
+/// level and front of the queue first within a level.
+#[derive(Default)]
+pub struct QueueAhead {
+    before_level: Decimal,
+    at_level: Decimal,
+}
+
+impl QueueAhead {
+    /// Moves on to the next price level.
+    pub fn next_level(&mut self) {
+        self.before_level += self.at_level;
+        self.at_level = Decimal::ZERO;
+    }
+
+    /// (ahead at its level, ahead from the touch) for the next order in the queue, which is
+    /// then counted as ahead of the ones after it.
+    pub fn push(&mut self, qty: Decimal) -> (Decimal, Decimal) {
+        let ahead = (self.at_level, self.before_level + self.at_level);
+        self.at_level += qty;
+        ahead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_queue_ahead_counts_from_the_touch() {
+        let mut ahead = QueueAhead::default();
+        assert_eq!(ahead.push(dec!(2)), (dec!(0), dec!(0)));
+        assert_eq!(ahead.push(dec!(3)), (dec!(2), dec!(2)));
+        ahead.next_level();
+        assert_eq!(ahead.push(dec!(1)), (dec!(0), dec!(5)));
+        assert_eq!(ahead.push(dec!(4)), (dec!(1), dec!(6)));
+        // An empty level adds nothing
+        ahead.next_level();
+        ahead.next_level();
+        assert_eq!(ahead.push(dec!(7)), (dec!(0), dec!(10)));
+    }
+
+    #[test]
+    fn test_tooltip_text() {
+        let mut tooltip = OrderTooltip {
+            side: Side::Ask,
+            price: dec!(100.1),
+            qty: dec!(2.5),
+            queue_index: 1,
+            age: Some(3),
+            cluster: None,
+            ahead_at_level: dec!(4),
+            ahead_from_touch: dec!(9.25),
+        };
+        assert_eq!(
+            tooltip.text(2, 1, |_| None),
+            "Ask 100.10 x 2.5\nQueue position: 1\nAge: 3 newer orders\n\
+             Ahead at level: 4.0\nAhead from touch: 9.2"
+        );
+        tooltip.side = Side::Bid;
+        tooltip.age = None;
+        tooltip.cluster = Some(2);
+        let text = tooltip.text(1, 0, |cluster| (cluster == 2).then_some(Participant::Wall));
+        assert!(text.starts_with("Bid 100.1 x 2\nQueue position: 1\nAge: - newer orders"));
+        assert!(text.ends_with("\nCluster: 2 (whale/wall)"));
+        assert!(tooltip.text(1, 0, |_| None).ends_with("\nCluster: 2"));
+        tooltip.cluster = Some(NOISE);
+        assert!(tooltip.text(1, 0, |_| Some(Participant::Retail)).ends_with("\nCluster: noise"));
+    }
+}
@@ -0,0 +1,156 @@
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+struct Shared<T> {
+    epoch: AtomicU64,
+    latest: ArcSwap<T>,
+}
+
+impl<T> Shared<T> {
+    // The value is loaded after the epoch, so it is never older than the epoch says.
+    fn latest(&self) -> (u64, Arc<T>) {
+        let epoch = self.epoch.load(Ordering::Acquire);
+        (epoch, self.latest.load_full())
+    }
+}
+
+// Single-writer publication of immutable values. The writer builds a new value off to the
+// side and swaps it in without taking a lock; readers keep the Arc they have until the epoch
+// moves, so their hot path is one atomic load and every value they see is complete.
+pub struct Publisher<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Publisher<T> {
+    pub fn new(initial: T) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                epoch: AtomicU64::new(0),
+                latest: ArcSwap::from_pointee(initial),
+            }),
+        }
+    }
+
+    /// Makes `value` the state new reads see and returns its epoch.
+    pub fn publish(&self, value: T) -> u64 {
+        let previous = self.shared.latest.swap(Arc::new(value));
+        let epoch = self.shared.epoch.fetch_add(1, Ordering::Release) + 1;
+        // Freed here only if no reader still holds it, and never while a reader waits on us
+        drop(previous);
+        epoch
+    }
+
+    pub fn reader(&self) -> Reader<T> {
+        let (epoch, current) = self.shared.latest();
+        Reader {
+            shared: self.shared.clone(),
+            epoch,
+            current,
+        }
+    }
+}
+
+/// One reader's view of a [`Publisher`]. Cheap to clone, one per thread.
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    epoch: u64,
+    current: Arc<T>,
+}
+
+impl<T> Clone for Reader<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            epoch: self.epoch,
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<T> Reader<T> {
+    /// The latest published value. It stays valid, and unchanged, for as long as the caller
+    /// holds it, however far the writer moves on.
+    pub fn latest(&mut self) -> &Arc<T> {
+        if self.shared.epoch.load(Ordering::Acquire) != self.epoch {
+            (self.epoch, self.current) = self.shared.latest();
+        }
+        &self.current
+    }
+
+    /// Epoch of the value last returned by [`Reader::latest`].
+    #[cfg(test)]
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glass::{Glass, GlassSnapshot};
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    #[test]
+    fn test_reader_follows_epochs() {
+        let publisher = Publisher::new(1);
+        let mut reader = publisher.reader();
+        let held = reader.latest().clone();
+        assert_eq!(publisher.publish(2), 1);
+        assert_eq!(publisher.publish(3), 2);
+        assert_eq!(**reader.latest(), 3);
+        assert_eq!(reader.epoch(), 2);
+        // What a reader already holds is not affected by later publishes
+        assert_eq!(*held, 1);
+    }
+
+    #[test]
+    fn test_readers_see_whole_batches() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Reader<GlassSnapshot>>();
+
+        // Every batch moves shares between levels but keeps the total, so any torn view
+        // would show up as a different sum.
+        const TOTAL: u64 = 64 * 100;
+        let mut glass = Glass::new();
+        for key in 0..64 {
+            glass.insert(key, 100);
+        }
+        let publisher = Publisher::new(glass.snapshot());
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for _ in 0..3 {
+                let mut reader = publisher.reader();
+                let done = &done;
+                scope.spawn(move || {
+                    let mut last_epoch = 0;
+                    while !done.load(Ordering::Acquire) {
+                        let snapshot = reader.latest().clone();
+                        assert_eq!(snapshot.range_sum(0, u32::MAX), Ok(TOTAL));
+                        assert!(reader.epoch() >= last_epoch);
+                        last_epoch = reader.epoch();
+                    }
+                });
+            }
+            // Diffs stay private to the writer until the snapshot after them is published
+            for batch in 0..2_000u32 {
+                let from = batch % 64;
+                let to = (batch * 7 + 1) % 64;
+                if from != to {
+                    let moved = glass.get(from).unwrap_or(0).min(50);
+                    glass.update_value(from, |v| *v -= moved);
+                    let to_value = glass.get(to).unwrap_or(0) + moved;
+                    glass.insert(to, to_value);
+                    if glass.get(from) == Some(0) {
+                        glass.remove(from);
+                    }
+                }
+                publisher.publish(glass.snapshot());
+            }
+            done.store(true, Ordering::Release);
+        });
+        assert_eq!(publisher.reader().latest().range_sum(0, u32::MAX), Ok(TOTAL));
+        assert_eq!(publisher.reader().latest().len(), glass.iter().count());
+    }
+}
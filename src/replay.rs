@@ -0,0 +1,85 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::book_feed::{self, ReplayError, SideView};
+use crate::cluster_summary;
+use crate::clustering;
+use crate::kmeans::MiniBatchKMeans;
+
+// Replays are always seeded, so the same recording prints the same clusters every time
+const DEFAULT_SEED: u64 = 0;
+// The fit settings the app starts with
+const BATCH_SIZE: usize = 1024;
+const MAX_ITER: usize = 1024;
+
+// Headless `--replay <dir>`: rebuilds the book a BOOK_RECORD_DIR run left behind, prints it
+// and clusters each side with k-means seeded from KMEANS_SEED, so a capture can be inspected
+// or diffed without the UI or a connection. `depth` is the levels per side the live feed
+// copies out, so the replay clusters the same orders the app does.
+pub fn run(dir: &Path, depth: usize, clusters: RangeInclusive<usize>) -> Result<(), String> {
+    let replay = book_feed::replay(dir, depth).map_err(|err| {
+        let reason = match err {
+            ReplayError::Io(err) => err.to_string(),
+            ReplayError::Checkpoint(err) => format!("unreadable checkpoint: {:?}", err),
+            ReplayError::Parse(err) => format!("unreadable depth update: {}", err),
+            ReplayError::Gap { last_update_id } => {
+                format!("the recording skips updates after {}", last_update_id)
+            }
+        };
+        format!("cannot replay {}: {}", dir.display(), reason)
+    })?;
+    let view = &replay.view;
+    println!("applied {} recorded updates, last update id {}", replay.applied, replay.last_update_id);
+    match (view.best_bid(), view.best_ask()) {
+        (Some((bid, bid_qty)), Some((ask, ask_qty))) => {
+            println!("best bid {} x {}, best ask {} x {}, spread {}", bid, bid_qty, ask, ask_qty, ask - bid);
+        }
+        _ => println!("book has an empty side"),
+    }
+    print_side("bids", &view.bids);
+    print_side("asks", &view.asks);
+
+    let (Some((bid, _)), Some((ask, _))) = (view.best_bid(), view.best_ask()) else {
+        return Ok(());
+    };
+    let seed = clustering::env_seed().unwrap_or(DEFAULT_SEED);
+    println!("k-means seed {}", seed);
+    let mid = (bid + ask) / Decimal::TWO;
+    for (name, side) in [("bids", &view.bids), ("asks", &view.asks)] {
+        print_clusters(name, side, mid, view.scale.tick_size(), clusters.clone(), seed);
+    }
+    Ok(())
+}
+
+fn print_clusters(
+    name: &str,
+    side: &SideView,
+    mid: Decimal,
+    tick_size: Decimal,
+    clusters: RangeInclusive<usize>,
+    seed: u64,
+) {
+    let mut kmeans = MiniBatchKMeans::new(*clusters.start(), BATCH_SIZE, MAX_ITER).with_seed(seed);
+    let fit = kmeans.fit_auto(&side.orders, mid, tick_size, clusters);
+    println!("{} clusters: k = {}, silhouette {:.2}", name, fit.num_clusters, fit.silhouette);
+    // A single book has no previous labelling to take cancel rates from
+    for row in cluster_summary::summarize(&side.orders, &fit.labels, tick_size, &HashMap::new()) {
+        println!(
+            "  {}: {} orders, {} total, mean size {:.4}, mean age {:.1}, {:.0}% at the touch ({})",
+            row.cluster,
+            row.count,
+            row.total_size,
+            row.mean_size,
+            row.mean_age,
+            row.touch_share * 100.0,
+            row.participant.label()
+        );
+    }
+}
+
+fn print_side(name: &str, side: &SideView) {
+    let vwap = side.vwap.map_or("-".to_string(), |vwap| vwap.round_dp(8).to_string());
+    println!("{}: {} levels, {} total qty, vwap {}", name, side.levels.len(), side.total_qty, vwap);
+}
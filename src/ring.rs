@@ -32,6 +32,19 @@ impl LambdaRing {
         }
     }
 
+    /// Iterates the stored timestamps from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.len).map(move |i| self.inner[(self.head + i) & (LAMBDA_RING_CAP - 1)])
+    }
+
+    /// Number of stored timestamps at or after `cutoff_ts`, scanning from the newest.
+    pub fn count_since(&self, cutoff_ts: u64) -> usize {
+        (0..self.len)
+            .rev()
+            .take_while(|&i| self.inner[(self.head + i) & (LAMBDA_RING_CAP - 1)] >= cutoff_ts)
+            .count()
+    }
+
     pub fn rate(&self, window_ns: u64) -> f64 {
         self.len as f64 / window_ns as f64 * 1e-9
     }
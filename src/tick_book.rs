@@ -0,0 +1,692 @@
+use rust_decimal::Decimal;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::glass::{Glass, GlassConfig, GlassSnapshot, SnapshotError};
+use crate::l3_glass::{EstimatedOrder, L3Glass};
+use crate::model::{OrderSample, Side};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickError {
+    /// The value is not a whole multiple of the tick or step size.
+    OffGrid,
+    /// The value does not fit the key/lot range around the base.
+    OutOfRange,
+}
+
+// Highest price key. Glass never stores u32::MAX, and the bid side mirrors keys onto
+// `MAX_KEY - key`, so both sides stay within 0..=MAX_KEY.
+const MAX_KEY: u32 = u32::MAX - 1;
+
+// Fixed-point mapping between exchange decimals and Glass keys/values.
+// Prices become tick counts relative to `base_tick` so that symbols quoted with many
+// decimals still fit a u32 key; quantities become whole lots of `step_size`.
+#[derive(Clone, Copy, Debug)]
+pub struct TickScale {
+    tick_size: Decimal,
+    step_size: Decimal,
+    base_tick: i64,
+}
+
+impl TickScale {
+    /// Centres the key range on `reference_price` (usually the first snapshot's touch),
+    /// leaving about 2^31 ticks of room on either side.
+    pub fn new(tick_size: Decimal, step_size: Decimal, reference_price: Decimal) -> Self {
+        let reference_tick = (reference_price / tick_size).trunc().to_i64().unwrap_or(0);
+        Self {
+            tick_size,
+            step_size,
+            base_tick: (reference_tick - (1i64 << 31)).max(0),
+        }
+    }
+
+    pub fn tick_size(&self) -> Decimal {
+        self.tick_size
+    }
+
+    pub fn price_to_key(&self, price: Decimal) -> Result<u32, TickError> {
+        let ticks = price / self.tick_size;
+        if !ticks.fract().is_zero() {
+            return Err(TickError::OffGrid);
+        }
+        let ticks = ticks.to_i64().ok_or(TickError::OutOfRange)?;
+        u32::try_from(ticks - self.base_tick)
+            .ok()
+            .filter(|&key| key <= MAX_KEY)
+            .ok_or(TickError::OutOfRange)
+    }
+
+    pub fn key_to_price(&self, key: u32) -> Decimal {
+        Decimal::from(self.base_tick + key as i64) * self.tick_size
+    }
+
+    pub fn qty_to_lots(&self, qty: Decimal) -> Result<u64, TickError> {
+        let lots = qty / self.step_size;
+        if !lots.fract().is_zero() {
+            return Err(TickError::OffGrid);
+        }
+        lots.to_u64().ok_or(TickError::OutOfRange)
+    }
+
+    pub fn lots_to_qty(&self, lots: u64) -> Decimal {
+        Decimal::from(lots) * self.step_size
+    }
+}
+
+// One side of the book in lots, with the estimated order queues kept by the L3Glass.
+// Glass keeps its lowest keys in the trie, so the bid side stores `MAX_KEY - key`: the best
+// bids are then the lowest keys, like the best asks, and the far end of either side is what
+// spills into the preempt map. Everything outside BookSide sees real price keys.
+struct BookSide {
+    orders: L3Glass,
+    mirrored: bool,
+    // Running sums over all levels so depth and VWAP never need a scan
+    total_lots: u128,
+    total_key_lots: u128,
+}
+
+impl BookSide {
+    fn new(config: GlassConfig, mirrored: bool) -> Self {
+        Self {
+            orders: L3Glass::with_config(config),
+            mirrored,
+            total_lots: 0,
+            total_key_lots: 0,
+        }
+    }
+
+    // Rebuilds a side around its restored levels and queues.
+    fn restore(orders: L3Glass, mirrored: bool) -> Self {
+        let mut side = Self {
+            orders,
+            mirrored,
+            total_lots: 0,
+            total_key_lots: 0,
+        };
+        let levels: Vec<(u32, u64)> = side.outward().collect();
+        for (key, lots) in levels {
+            side.adjust_totals(key, 0, lots);
+        }
+        side
+    }
+
+    fn levels(&self) -> &Glass {
+        self.orders.levels()
+    }
+
+    // Maps a price key to its Glass key and back; mirroring is its own inverse.
+    fn glass_key(&self, key: u32) -> u32 {
+        if self.mirrored { MAX_KEY - key } else { key }
+    }
+
+    // Levels as (price key, lots) from the touch outwards.
+    fn outward(&self) -> impl Iterator<Item = (u32, u64)> + '_ {
+        self.levels().iter().map(|(key, lots)| (self.glass_key(key), lots))
+    }
+
+    fn queue(&self, key: u32) -> &VecDeque<EstimatedOrder> {
+        self.orders.orders(self.glass_key(key)).expect("every level has a queue")
+    }
+
+    fn adjust_totals(&mut self, key: u32, old_lots: u64, new_lots: u64) {
+        self.total_lots = self.total_lots - old_lots as u128 + new_lots as u128;
+        self.total_key_lots =
+            self.total_key_lots - key as u128 * old_lots as u128 + key as u128 * new_lots as u128;
+    }
+
+    fn set(&mut self, key: u32, lots: u64) {
+        let glass_key = self.glass_key(key);
+        let old_lots = self.orders.get(glass_key).unwrap_or(0);
+        self.orders.set_level(glass_key, lots);
+        self.adjust_totals(key, old_lots, lots);
+    }
+
+    fn apply(&mut self, key: u32, lots: u64) {
+        let glass_key = self.glass_key(key);
+        let old_lots = self.orders.get(glass_key).unwrap_or(0);
+        self.orders.apply_level(glass_key, lots);
+        self.adjust_totals(key, old_lots, lots);
+    }
+}
+
+// Fixed-point L3 estimate of the book: Glass-backed level totals per side plus the
+// estimated order queue at each level, addressed with exchange decimals.
+pub struct TickBook {
+    scale: TickScale,
+    config: GlassConfig,
+    bids: BookSide,
+    asks: BookSide,
+}
+
+impl TickBook {
+    pub fn new(scale: TickScale) -> Self {
+        Self::with_config(scale, GlassConfig::default())
+    }
+
+    /// Builds both sides' Glass from `config`: `hot_levels` per side stay in the trie, the rest
+    /// spill into its preempt map. Keys are centred on 2^31 by the scale, so `key_bits` should
+    /// stay at the full width.
+    pub fn with_config(scale: TickScale, config: GlassConfig) -> Self {
+        Self {
+            scale,
+            config,
+            bids: BookSide::new(config, true),
+            asks: BookSide::new(config, false),
+        }
+    }
+
+    pub fn scale(&self) -> &TickScale {
+        &self.scale
+    }
+
+    fn side(&self, side: Side) -> &BookSide {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BookSide {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    /// Sets the resting quantity at `price` as a single order; a zero quantity removes the level.
+    pub fn set_level(&mut self, side: Side, price: Decimal, qty: Decimal) -> Result<(), TickError> {
+        let key = self.scale.price_to_key(price)?;
+        let lots = self.scale.qty_to_lots(qty)?;
+        self.side_mut(side).set(key, lots);
+        Ok(())
+    }
+
+    /// Applies a depth update for one level, estimating which orders joined or left the queue:
+    /// growth is a new order at the back, a reduction cancels the newest order of exactly that
+    /// size or else shrinks the largest one.
+    pub fn apply_level(&mut self, side: Side, price: Decimal, qty: Decimal) -> Result<(), TickError> {
+        let key = self.scale.price_to_key(price)?;
+        let lots = self.scale.qty_to_lots(qty)?;
+        self.side_mut(side).apply(key, lots);
+        Ok(())
+    }
+
+    pub fn best_bid(&self) -> Option<(Decimal, Decimal)> {
+        self.best(Side::Bid)
+    }
+
+    pub fn best_ask(&self) -> Option<(Decimal, Decimal)> {
+        self.best(Side::Ask)
+    }
+
+    // Both sides keep their touch at the lowest Glass key, which the Glass tracks directly
+    fn best(&self, side: Side) -> Option<(Decimal, Decimal)> {
+        let book_side = self.side(side);
+        book_side.levels().min().map(|(key, lots)| {
+            (self.scale.key_to_price(book_side.glass_key(key)), self.scale.lots_to_qty(lots))
+        })
+    }
+
+    /// Resting quantity summed over every level of one side.
+    pub fn total_qty(&self, side: Side) -> Decimal {
+        Decimal::from_u128(self.side(side).total_lots).unwrap_or_default() * self.scale.step_size
+    }
+
+    /// Quantity-weighted average price of one side, `None` when it is empty.
+    pub fn vwap(&self, side: Side) -> Option<Decimal> {
+        let side = self.side(side);
+        if side.total_lots == 0 {
+            return None;
+        }
+        let mean_key = Decimal::from_u128(side.total_key_lots)? / Decimal::from_u128(side.total_lots)?;
+        Some((Decimal::from(self.scale.base_tick) + mean_key) * self.scale.tick_size)
+    }
+
+    /// Decimal copy of the `depth` levels nearest the touch with their estimated orders, in
+    /// the per-price layout the charts, clustering and heatmap consume.
+    pub fn levels(&self, side: Side, depth: usize) -> BTreeMap<Decimal, VecDeque<Decimal>> {
+        let book_side = self.side(side);
+        book_side
+            .outward()
+            .take(depth)
+            .map(|(key, _)| {
+                let orders = book_side
+                    .queue(key)
+                    .iter()
+                    .map(|order| Decimal::from(order.size) * self.scale.step_size)
+                    .collect();
+                (self.scale.key_to_price(key), orders)
+            })
+            .collect()
+    }
+
+    /// Read-only copy of every level of one side, keyed like [`TickScale::price_to_key`].
+    pub fn snapshot(&self, side: Side) -> GlassSnapshot {
+        let book_side = self.side(side);
+        // Ask keys are stored as they are, so the Glass can copy them out directly
+        if book_side.mirrored { book_side.outward().collect() } else { book_side.levels().snapshot() }
+    }
+
+    /// Every estimated order in the `depth` levels nearest the touch, touch first and
+    /// front of the queue first within a level.
+    pub fn order_samples(&self, side: Side, depth: usize) -> Vec<OrderSample> {
+        let book_side = self.side(side);
+        let mut samples = Vec::new();
+        for (key, _) in book_side.outward().take(depth) {
+            let price = self.scale.key_to_price(key);
+            samples.extend(book_side.queue(key).iter().enumerate().map(|(queue_index, order)| OrderSample {
+                id: order.id,
+                side,
+                price,
+                qty: Decimal::from(order.size) * self.scale.step_size,
+                age: book_side.orders.age(order),
+                queue_index,
+            }));
+        }
+        samples
+    }
+
+    pub fn clear(&mut self) {
+        self.bids = BookSide::new(self.config, true);
+        self.asks = BookSide::new(self.config, false);
+    }
+}
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"TKB\x02";
+
+// Checkpointing. Little-endian: magic, tick_size and step_size as serialized Decimals (16
+// bytes each), base_tick: i64, then the bid and the ask side, each as len: u64 followed by
+// its L3Glass checkpoint. The estimated queues are written with the levels, so a restored
+// book carries on with the same orders, ids and ages.
+impl TickBook {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(CHECKPOINT_MAGIC);
+        out.extend_from_slice(&self.scale.tick_size.serialize());
+        out.extend_from_slice(&self.scale.step_size.serialize());
+        out.extend_from_slice(&self.scale.base_tick.to_le_bytes());
+        for side in [&self.bids, &self.asks] {
+            let orders = side.orders.to_bytes();
+            out.extend_from_slice(&(orders.len() as u64).to_le_bytes());
+            out.extend_from_slice(&orders);
+        }
+        out
+    }
+
+    /// Restores a book written by [`TickBook::to_bytes`].
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<TickBook, SnapshotError> {
+        fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], SnapshotError> {
+            let (head, rest) = bytes.split_first_chunk::<N>().ok_or(SnapshotError::Truncated)?;
+            *bytes = rest;
+            Ok(*head)
+        }
+        fn side(bytes: &mut &[u8]) -> Result<L3Glass, SnapshotError> {
+            let len = u64::from_le_bytes(take(bytes)?);
+            let len = usize::try_from(len).map_err(|_| SnapshotError::Corrupt)?;
+            let (orders, rest) = bytes.split_at_checked(len).ok_or(SnapshotError::Truncated)?;
+            *bytes = rest;
+            L3Glass::from_bytes(orders)
+        }
+
+        if take::<4>(&mut bytes)? != *CHECKPOINT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let scale = TickScale {
+            tick_size: Decimal::deserialize(take(&mut bytes)?),
+            step_size: Decimal::deserialize(take(&mut bytes)?),
+            base_tick: i64::from_le_bytes(take(&mut bytes)?),
+        };
+        if scale.tick_size <= Decimal::ZERO || scale.step_size <= Decimal::ZERO || scale.base_tick < 0 {
+            return Err(SnapshotError::Corrupt);
+        }
+        let bids = side(&mut bytes)?;
+        let asks = side(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err(SnapshotError::Corrupt);
+        }
+        let config = GlassConfig {
+            hot_levels: bids.levels().hot_levels(),
+            arena_capacity: bids.levels().hot_levels() * 2,
+            key_bits: bids.levels().key_bits(),
+            bit_scan: bids.levels().bit_scan(),
+        };
+        Ok(TickBook {
+            scale,
+            config,
+            bids: BookSide::restore(bids, true),
+            asks: BookSide::restore(asks, false),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::DepthUpdate;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use rust_decimal::dec;
+    use std::time::Instant;
+
+    type DecimalBook = BTreeMap<Decimal, VecDeque<Decimal>>;
+
+    // The per-price Decimal estimator the book replaced, kept as the reference model.
+    fn decimal_apply(book: &mut DecimalBook, price: Decimal, qty: Decimal) {
+        if qty == Decimal::ZERO {
+            book.remove(&price);
+        } else if let Some(old_qty) = book.get_mut(&price) {
+            let old_sum = old_qty.iter().sum::<Decimal>();
+            if old_sum > qty {
+                let change = old_sum - qty;
+                if let Some(pos) = old_qty.iter().rposition(|&x| x == change) {
+                    old_qty.remove(pos);
+                } else {
+                    let largest_order = *old_qty.iter().max().unwrap();
+                    let largest_pos = old_qty.iter().position(|&x| x == largest_order).unwrap();
+                    old_qty.remove(largest_pos);
+                    old_qty.push_back(largest_order - change);
+                }
+            } else if old_sum < qty {
+                old_qty.push_back(qty - old_sum);
+            }
+        } else {
+            book.insert(price, VecDeque::from(vec![qty]));
+        }
+    }
+
+    // Full-scan depth and VWAP, as the app computed them after every update.
+    fn decimal_totals(book: &DecimalBook) -> (Decimal, Decimal) {
+        let mut qty_sum = Decimal::ZERO;
+        let mut price_sum = Decimal::ZERO;
+        for (price, orders) in book {
+            let level_qty: Decimal = orders.iter().copied().sum();
+            qty_sum += level_qty;
+            price_sum += price * level_qty;
+        }
+        (qty_sum, price_sum)
+    }
+
+    // Random walk of level updates around a moving touch on a 0.01 / 0.001 grid, with a
+    // bias towards resizing existing levels so queues build up and get partially cancelled.
+    fn synthetic_updates(seed: u64, count: usize) -> Vec<(Side, Decimal, Decimal)> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut mid: i64 = 1_000_000;
+        let mut sizes: Vec<i64> = Vec::new();
+        (0..count)
+            .map(|_| {
+                mid += rng.random_range(-2..=2);
+                let side = if rng.random_bool(0.5) { Side::Bid } else { Side::Ask };
+                let offset = rng.random_range(1..200);
+                let tick = if side == Side::Bid { mid - offset } else { mid + offset };
+                let lots = match rng.random_range(0..10) {
+                    0 => 0,
+                    1..=3 if !sizes.is_empty() => sizes[rng.random_range(0..sizes.len())],
+                    _ => rng.random_range(1..50_000),
+                };
+                sizes.push(lots);
+                if sizes.len() > 64 {
+                    sizes.remove(0);
+                }
+                (side, Decimal::new(tick, 2), Decimal::new(lots, 3))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_price_round_trip() {
+        let scale = TickScale::new(dec!(0.00001), dec!(1), dec!(0.16512));
+        for price in [dec!(0.16512), dec!(0.00001), dec!(1.23456), dec!(420.00000)] {
+            let key = scale.price_to_key(price).unwrap();
+            assert_eq!(scale.key_to_price(key), price);
+            assert_eq!(scale.key_to_price(key).to_string(), price.to_string());
+        }
+        assert_eq!(scale.price_to_key(dec!(0.165125)), Err(TickError::OffGrid));
+    }
+
+    #[test]
+    fn test_wide_range_uses_offset_base() {
+        // 1e-8 ticks on a six figure price overflow u32 without an offset
+        let scale = TickScale::new(dec!(0.00000001), dec!(0.001), dec!(100000));
+        let key = scale.price_to_key(dec!(100000)).unwrap();
+        assert_eq!(key, 1 << 31);
+        assert_eq!(scale.key_to_price(key), dec!(100000));
+        assert_eq!(scale.price_to_key(dec!(99999.99999999)).unwrap(), key - 1);
+        assert_eq!(scale.price_to_key(dec!(200000)), Err(TickError::OutOfRange));
+        assert_eq!(scale.price_to_key(dec!(1)), Err(TickError::OutOfRange));
+    }
+
+    #[test]
+    fn test_lots_round_trip() {
+        let scale = TickScale::new(dec!(0.1), dec!(0.001), dec!(60000));
+        assert_eq!(scale.qty_to_lots(dec!(1.234)), Ok(1234));
+        assert_eq!(scale.lots_to_qty(1234), dec!(1.234));
+        assert_eq!(scale.qty_to_lots(dec!(0.0005)), Err(TickError::OffGrid));
+        assert_eq!(scale.qty_to_lots(dec!(0)), Ok(0));
+    }
+
+    #[test]
+    fn test_book_levels() {
+        let mut book = TickBook::new(TickScale::new(dec!(0.01), dec!(0.1), dec!(100)));
+        book.set_level(Side::Bid, dec!(99.98), dec!(1.5)).unwrap();
+        book.set_level(Side::Bid, dec!(99.99), dec!(2.0)).unwrap();
+        book.set_level(Side::Ask, dec!(100.01), dec!(3.0)).unwrap();
+        book.set_level(Side::Ask, dec!(100.02), dec!(0.7)).unwrap();
+        assert_eq!(book.best_bid(), Some((dec!(99.99), dec!(2.0))));
+        assert_eq!(book.best_ask(), Some((dec!(100.01), dec!(3.0))));
+        assert_eq!(book.levels(Side::Ask, 2)[&dec!(100.02)], [dec!(0.7)]);
+
+        book.set_level(Side::Bid, dec!(99.99), dec!(0)).unwrap();
+        assert_eq!(book.best_bid(), Some((dec!(99.98), dec!(1.5))));
+        assert_eq!(book.set_level(Side::Ask, dec!(100.015), dec!(1)), Err(TickError::OffGrid));
+    }
+
+    #[test]
+    fn test_best_bids_stay_hot() {
+        let config = GlassConfig { hot_levels: 8, ..GlassConfig::default() };
+        let mut book = TickBook::with_config(TickScale::new(dec!(1), dec!(1), dec!(1000)), config);
+        // Far levels first, so the near ones arrive after the trie has filled up
+        for offset in (1..=100).rev() {
+            book.set_level(Side::Bid, Decimal::from(1000 - offset), dec!(1)).unwrap();
+            book.set_level(Side::Ask, Decimal::from(1000 + offset), dec!(1)).unwrap();
+        }
+        for side in [&book.bids, &book.asks] {
+            let keys: Vec<u32> = side.outward().map(|(key, _)| side.glass_key(key)).collect();
+            assert!(keys[..8].iter().all(|&key| side.levels().is_hot(key)));
+            assert!(!keys[8..].iter().any(|&key| side.levels().is_hot(key)));
+        }
+        assert_eq!(book.best_bid(), Some((dec!(999), dec!(1))));
+        assert_eq!(book.best_ask(), Some((dec!(1001), dec!(1))));
+        let near_bids: Vec<Decimal> = book.levels(Side::Bid, 3).into_keys().collect();
+        assert_eq!(near_bids, [dec!(997), dec!(998), dec!(999)]);
+        assert_eq!(book.vwap(Side::Bid), Some(dec!(949.5)));
+    }
+
+    #[test]
+    fn test_order_samples_from_touch() {
+        let mut book = TickBook::new(TickScale::new(dec!(0.01), dec!(0.1), dec!(100)));
+        book.apply_level(Side::Bid, dec!(99.98), dec!(1)).unwrap();
+        book.apply_level(Side::Bid, dec!(99.99), dec!(2)).unwrap();
+        book.apply_level(Side::Bid, dec!(99.99), dec!(2.5)).unwrap();
+        book.apply_level(Side::Ask, dec!(100.01), dec!(3)).unwrap();
+        let bids = book.order_samples(Side::Bid, 10);
+        let summary: Vec<_> = bids.iter().map(|o| (o.price, o.qty, o.age, o.queue_index)).collect();
+        assert_eq!(
+            summary,
+            [
+                (dec!(99.99), dec!(2), 1, 0),
+                (dec!(99.99), dec!(0.5), 0, 1),
+                (dec!(99.98), dec!(1), 2, 0),
+            ]
+        );
+        assert!(bids.iter().all(|o| o.side == Side::Bid));
+        assert_eq!(book.order_samples(Side::Bid, 1).len(), 2);
+        assert_eq!(book.order_samples(Side::Ask, 10)[0].age, 0);
+    }
+
+    #[test]
+    fn test_apply_level_estimates_queue() {
+        let mut book = TickBook::new(TickScale::new(dec!(0.01), dec!(0.1), dec!(100)));
+        book.apply_level(Side::Bid, dec!(99.99), dec!(5)).unwrap();
+        book.apply_level(Side::Bid, dec!(99.99), dec!(7)).unwrap(); // +2 joins the back
+        book.apply_level(Side::Bid, dec!(99.99), dec!(10)).unwrap(); // +3
+        book.apply_level(Side::Bid, dec!(99.99), dec!(8)).unwrap(); // -2 cancels that order
+        let orders = |book: &TickBook| book.levels(Side::Bid, 1)[&dec!(99.99)].clone();
+        assert_eq!(orders(&book), [dec!(5), dec!(3)]);
+
+        // no order of size 4: the largest one absorbs the reduction and moves to the back
+        book.apply_level(Side::Bid, dec!(99.99), dec!(4)).unwrap();
+        assert_eq!(orders(&book), [dec!(3), dec!(1)]);
+        assert_eq!(book.total_qty(Side::Bid), dec!(4));
+
+        book.apply_level(Side::Bid, dec!(99.99), dec!(0)).unwrap();
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(book.total_qty(Side::Bid), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_totals_and_vwap() {
+        let mut book = TickBook::new(TickScale::new(dec!(0.5), dec!(0.01), dec!(100)));
+        book.set_level(Side::Ask, dec!(100.5), dec!(1)).unwrap();
+        book.apply_level(Side::Ask, dec!(101), dec!(2)).unwrap();
+        book.apply_level(Side::Ask, dec!(101), dec!(3)).unwrap();
+        assert_eq!(book.total_qty(Side::Ask), dec!(4));
+        assert_eq!(book.vwap(Side::Ask), Some(dec!(100.875)));
+        assert_eq!(book.vwap(Side::Bid), None);
+
+        // a snapshot level replaces whatever queue was estimated there
+        book.set_level(Side::Ask, dec!(101), dec!(1)).unwrap();
+        assert_eq!(book.levels(Side::Ask, 10)[&dec!(101)], [dec!(1)]);
+        assert_eq!(book.vwap(Side::Ask), Some(dec!(100.75)));
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let config = GlassConfig { hot_levels: 16, ..GlassConfig::default() };
+        let mut book = TickBook::with_config(TickScale::new(dec!(0.01), dec!(0.001), dec!(10000)), config);
+        for (side, price, qty) in synthetic_updates(3, 5_000) {
+            book.apply_level(side, price, qty).unwrap();
+        }
+        let bytes = book.to_bytes();
+        let mut restored = TickBook::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.to_bytes(), bytes);
+        assert_eq!(restored.config.hot_levels, 16);
+        for side in [Side::Bid, Side::Ask] {
+            assert_eq!(restored.snapshot(side), book.snapshot(side));
+            assert_eq!(restored.total_qty(side), book.total_qty(side));
+            assert_eq!(restored.vwap(side), book.vwap(side));
+            // The estimated queues come back order for order, ids and ages included
+            assert_eq!(restored.levels(side, usize::MAX), book.levels(side, usize::MAX));
+            assert_eq!(restored.order_samples(side, usize::MAX), book.order_samples(side, usize::MAX));
+            assert!(restored.levels(side, usize::MAX).values().any(|queue| queue.len() > 1));
+        }
+        assert_eq!(restored.best_bid(), book.best_bid());
+        assert_eq!(restored.best_ask(), book.best_ask());
+
+        // The restored book carries on like the original
+        for (side, price, qty) in synthetic_updates(4, 2_000) {
+            book.apply_level(side, price, qty).unwrap();
+            restored.apply_level(side, price, qty).unwrap();
+        }
+        assert_eq!(restored.snapshot(Side::Bid), book.snapshot(Side::Bid));
+        assert_eq!(restored.total_qty(Side::Ask), book.total_qty(Side::Ask));
+        assert_eq!(restored.order_samples(Side::Ask, 50), book.order_samples(Side::Ask, 50));
+
+        assert_eq!(TickBook::from_bytes(&bytes[..bytes.len() - 1]).err(), Some(SnapshotError::Truncated));
+        assert_eq!(TickBook::from_bytes(b"GLS\x02").err(), Some(SnapshotError::BadMagic));
+        let mut zero_tick = bytes;
+        zero_tick[4..20].copy_from_slice(&Decimal::ZERO.serialize());
+        assert_eq!(TickBook::from_bytes(&zero_tick).err(), Some(SnapshotError::Corrupt));
+    }
+
+    #[test]
+    fn test_matches_decimal_model() {
+        for seed in 0..4 {
+            let mut book = TickBook::new(TickScale::new(dec!(0.01), dec!(0.001), dec!(10000)));
+            let mut bids = DecimalBook::new();
+            let mut asks = DecimalBook::new();
+            for (side, price, qty) in synthetic_updates(seed, 20_000) {
+                book.apply_level(side, price, qty).unwrap();
+                match side {
+                    Side::Bid => decimal_apply(&mut bids, price, qty),
+                    Side::Ask => decimal_apply(&mut asks, price, qty),
+                }
+            }
+            assert_eq!(book.levels(Side::Bid, usize::MAX), bids);
+            assert_eq!(book.levels(Side::Ask, usize::MAX), asks);
+            let (bid_qty, bid_notional) = decimal_totals(&bids);
+            assert_eq!(book.total_qty(Side::Bid), bid_qty);
+            assert_eq!(book.vwap(Side::Bid).unwrap().round_dp(12), (bid_notional / bid_qty).round_dp(12));
+        }
+    }
+
+    /// Replays depth updates through the Decimal model and the tick book, checks they end up
+    /// identical and prints the update throughput of each, including the per-update depth and
+    /// VWAP the app derives. Point `BOOK_REPLAY` at a `BOOK_RECORD_DIR` recording, or at any
+    /// capture of the raw `@depth` stream (one event per line), to measure recorded data;
+    /// otherwise a synthetic stream is used:
+    ///
+    /// `BOOK_REPLAY=capture cargo test --release bench_replay -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_replay_throughput() {
+        let updates: Vec<(Side, Decimal, Decimal)> = match std::env::var_os("BOOK_REPLAY") {
+            Some(path) => {
+                let path = std::path::PathBuf::from(path);
+                let path = if path.is_dir() { path.join(crate::book_feed::DEPTH_FILE) } else { path };
+                std::fs::read_to_string(path)
+                    .unwrap()
+                    .lines()
+                    .filter_map(|line| serde_json::from_str::<DepthUpdate>(line).ok())
+                    .flat_map(|update| {
+                        let bids = update.b.into_iter().map(|l| (Side::Bid, l[0], l[1]));
+                        bids.chain(update.a.into_iter().map(|l| (Side::Ask, l[0], l[1])))
+                    })
+                    .collect()
+            }
+            None => synthetic_updates(42, 500_000),
+        };
+        let (reference, tick_size, step_size) = {
+            let scale = |values: &mut dyn Iterator<Item = Decimal>| {
+                Decimal::new(1, values.map(|v| v.normalize().scale()).max().unwrap_or(0))
+            };
+            (
+                updates[0].1,
+                scale(&mut updates.iter().map(|u| u.1)),
+                scale(&mut updates.iter().map(|u| u.2)),
+            )
+        };
+
+        let start = Instant::now();
+        let mut bids = DecimalBook::new();
+        let mut asks = DecimalBook::new();
+        let mut checksum = Decimal::ZERO;
+        for &(side, price, qty) in &updates {
+            let book = if side == Side::Bid { &mut bids } else { &mut asks };
+            decimal_apply(book, price, qty);
+            checksum += decimal_totals(book).0;
+        }
+        let decimal_elapsed = start.elapsed();
+
+        let start = Instant::now();
+        let mut book = TickBook::new(TickScale::new(tick_size, step_size, reference));
+        let mut tick_checksum = Decimal::ZERO;
+        for &(side, price, qty) in &updates {
+            book.apply_level(side, price, qty).unwrap();
+            tick_checksum += book.total_qty(side);
+            std::hint::black_box(book.vwap(side));
+        }
+        let tick_elapsed = start.elapsed();
+
+        assert_eq!(checksum, tick_checksum);
+        assert_eq!(book.levels(Side::Bid, usize::MAX), bids);
+        assert_eq!(book.levels(Side::Ask, usize::MAX), asks);
+        let rate = |elapsed: std::time::Duration| updates.len() as f64 / elapsed.as_secs_f64();
+        println!(
+            "{} level updates: decimal {:.0}/s, tick book {:.0}/s ({:.1}x)",
+            updates.len(),
+            rate(decimal_elapsed),
+            rate(tick_elapsed),
+            decimal_elapsed.as_secs_f64() / tick_elapsed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+use crate::model::TradeUpdate;
+
+// Signed aggressor volume from aggTrade: positive when the buyer took liquidity.
+fn signed_qty(trade: &TradeUpdate) -> Decimal {
+    if trade.buyer_market_maker { -trade.q } else { trade.q }
+}
+
+// Keeps the recent signed trades for windowed imbalance and the cumulative volume delta series.
+pub struct TradeFlow {
+    trades: VecDeque<(u64, Decimal)>,
+    cvd: Decimal,
+    cvd_history: VecDeque<(u64, Decimal)>,
+    history_ms: u64,
+}
+
+impl TradeFlow {
+    pub fn new(history_ms: u64) -> Self {
+        Self {
+            trades: VecDeque::new(),
+            cvd: Decimal::ZERO,
+            cvd_history: VecDeque::new(),
+            history_ms,
+        }
+    }
+
+    pub fn push(&mut self, trade: &TradeUpdate) {
+        let signed = signed_qty(trade);
+        self.trades.push_back((trade.trade_time, signed));
+        self.cvd += signed;
+        self.cvd_history.push_back((trade.trade_time, self.cvd));
+    }
+
+    /// Drops trades that fall outside both the longest imbalance window and the CVD history.
+    pub fn prune(&mut self, now_ms: u64, max_window_ms: u64) {
+        let trade_cutoff = now_ms.saturating_sub(max_window_ms);
+        while self.trades.front().is_some_and(|&(ts, _)| ts < trade_cutoff) {
+            self.trades.pop_front();
+        }
+        let history_cutoff = now_ms.saturating_sub(self.history_ms);
+        while self.cvd_history.front().is_some_and(|&(ts, _)| ts < history_cutoff) {
+            self.cvd_history.pop_front();
+        }
+    }
+
+    /// (buy - sell) / (buy + sell) aggressor volume over the last `window_ms`, in [-1, 1].
+    pub fn imbalance(&self, now_ms: u64, window_ms: u64) -> Decimal {
+        let cutoff = now_ms.saturating_sub(window_ms);
+        let mut buy = Decimal::ZERO;
+        let mut sell = Decimal::ZERO;
+        for &(_, qty) in self.trades.iter().rev().take_while(|&&(ts, _)| ts >= cutoff) {
+            if qty > Decimal::ZERO {
+                buy += qty;
+            } else {
+                sell -= qty;
+            }
+        }
+        if buy + sell > Decimal::ZERO {
+            (buy - sell) / (buy + sell)
+        } else {
+            Decimal::ZERO
+        }
+    }
+
+    pub fn cvd(&self) -> Decimal {
+        self.cvd
+    }
+
+    pub fn cvd_history(&self) -> &VecDeque<(u64, Decimal)> {
+        &self.cvd_history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn trade(ts: u64, qty: Decimal, buyer_market_maker: bool) -> TradeUpdate {
+        TradeUpdate {
+            e: "aggTrade".to_string(),
+            event_time: ts,
+            symbol: "DOGEUSDT".to_string(),
+            trade_id: ts,
+            p: dec!(0.1),
+            q: qty,
+            trade_time: ts,
+            buyer_market_maker,
+        }
+    }
+
+    #[test]
+    fn test_imbalance_over_window() {
+        let mut flow = TradeFlow::new(60_000);
+        flow.push(&trade(1_000, dec!(30), true)); // sell, only in the long window
+        flow.push(&trade(9_500, dec!(3), false)); // buy
+        flow.push(&trade(9_800, dec!(1), true)); // sell
+        assert_eq!(flow.imbalance(10_000, 1_000), dec!(0.5));
+        assert_eq!(flow.imbalance(10_000, 10_000), dec!(-28) / dec!(34));
+        assert_eq!(flow.imbalance(20_000, 1_000), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_cvd_accumulates_and_prunes_history() {
+        let mut flow = TradeFlow::new(5_000);
+        flow.push(&trade(1_000, dec!(2), false));
+        flow.push(&trade(2_000, dec!(5), true));
+        flow.push(&trade(8_000, dec!(4), false));
+        assert_eq!(flow.cvd(), dec!(1));
+        flow.prune(8_000, 1_000);
+        let history: Vec<_> = flow.cvd_history().iter().copied().collect();
+        assert_eq!(history, vec![(8_000, dec!(1))]);
+        // pruning never rewrites the running total
+        assert_eq!(flow.cvd(), dec!(1));
+    }
+}